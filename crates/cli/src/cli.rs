@@ -6,7 +6,7 @@ use std::process;
 use vanth::hash as vanth_hash;
 use vanth::{
     ContentHash, Ty,
-    store::{Store, StoreParams},
+    store::{Encoding, Store, StoreParams},
 };
 
 #[derive(Parser, Debug)]
@@ -29,6 +29,8 @@ pub enum Commands {
     Delete(DeleteArgs),
     #[command(about = "Delete all values of a type")]
     DeleteAll(DeleteAllArgs),
+    #[command(about = "List the types in the store with their value counts and sizes")]
+    Types(TypesArgs),
 }
 
 #[derive(Args, Debug)]
@@ -39,6 +41,8 @@ pub struct WriteArgs {
     ty: String,
     #[arg(long, help = "JSON value to write, optional (read from stdin if omitted)")]
     value: Option<String>,
+    #[arg(long, help = "Encoding to store values with if the database is new: json or cbor")]
+    encoding: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -75,6 +79,12 @@ pub struct DeleteAllArgs {
     ty: String,
 }
 
+#[derive(Args, Debug)]
+pub struct TypesArgs {
+    #[arg(long, help = "Database file path")]
+    db: PathBuf,
+}
+
 pub fn execute(cli: Cli) {
     match cli.command {
         Commands::Write(args) => handle_write(&args),
@@ -82,13 +92,12 @@ pub fn execute(cli: Cli) {
         Commands::GetAll(args) => handle_get_all(&args),
         Commands::Delete(args) => handle_delete(&args),
         Commands::DeleteAll(args) => handle_delete_all(&args),
+        Commands::Types(args) => handle_types(&args),
     }
 }
 
 fn parse_ty(s: &str) -> Ty {
-    Ty {
-        path: s.split("::").map(|p| p.to_string()).collect(),
-    }
+    Ty::from(s)
 }
 
 fn parse_hash(s: &str) -> ContentHash {
@@ -107,11 +116,48 @@ fn parse_hash(s: &str) -> ContentHash {
     ContentHash { hash }
 }
 
+/// Convert a stored value to a JSON string for display.
+fn to_json_string(encoding: Encoding, data: &[u8]) -> String {
+    let json = encoding.transcode_to_json(data).unwrap_or_else(|e| {
+        eprintln!("Error converting value to JSON: {:?}", e);
+        process::exit(1);
+    });
+    String::from_utf8(json).unwrap_or_else(|e| {
+        eprintln!("Invalid UTF-8 in data: {}", e);
+        process::exit(1);
+    })
+}
+
+/// Exit if values can't be written in `encoding` from JSON. Bincode values can only be encoded from their Rust type.
+fn check_writable_encoding(encoding: Encoding) {
+    if encoding == Encoding::Bincode {
+        eprintln!("Values can't be written to a bincode store from the CLI");
+        process::exit(1);
+    }
+}
+
 fn handle_write(args: &WriteArgs) {
-    let mut store = Store::sqlite_from_path(args.db.clone(), StoreParams::default()).unwrap_or_else(|e| {
+    let encoding = args.encoding.as_deref().map(|name| {
+        name.parse::<Encoding>().unwrap_or_else(|e| {
+            eprintln!("Invalid encoding: {:?}", e);
+            process::exit(1);
+        })
+    });
+    if let Some(encoding) = encoding {
+        check_writable_encoding(encoding);
+    }
+    let mut store = Store::sqlite_from_path(
+        args.db.clone(),
+        StoreParams {
+            encoding,
+            ..Default::default()
+        },
+    )
+    .unwrap_or_else(|e| {
         eprintln!("Error opening store: {:?}", e);
         process::exit(1);
     });
+    check_writable_encoding(store.encoding());
     let ty = parse_ty(&args.ty);
 
     let mut content = String::new();
@@ -130,7 +176,10 @@ fn handle_write(args: &WriteArgs) {
         eprintln!("Invalid JSON: {}", e);
         process::exit(1);
     });
-    let data = serde_json::to_vec(&value).unwrap();
+    let data = store.encoding().encode(&value).unwrap_or_else(|e| {
+        eprintln!("Error encoding value: {:?}", e);
+        process::exit(1);
+    });
     let content_hash = vanth_hash(&value);
 
     store.write_raw(ty, content_hash, data).unwrap_or_else(|e| {
@@ -162,11 +211,7 @@ fn handle_get(args: &GetArgs) {
     });
     match raw {
         Some(data) => {
-            let output = String::from_utf8(data).unwrap_or_else(|e| {
-                eprintln!("Invalid UTF-8 in data: {}", e);
-                process::exit(1);
-            });
-            println!("{}", output);
+            println!("{}", to_json_string(store.encoding(), &data));
         }
         None => {
             process::exit(1);
@@ -193,11 +238,7 @@ fn handle_get_all(args: &GetAllArgs) {
         process::exit(1);
     });
     for (_, data) in items {
-        let output = String::from_utf8(data).unwrap_or_else(|e| {
-            eprintln!("Invalid UTF-8 in data: {}", e);
-            process::exit(1);
-        });
-        println!("{}", output);
+        println!("{}", to_json_string(store.encoding(), &data));
     }
 }
 
@@ -214,6 +255,17 @@ fn handle_delete(args: &DeleteArgs) {
         process::exit(1);
     });
     let content_hash = parse_hash(&args.content_hash);
+
+    let tys = store.delete_everywhere(content_hash).unwrap_or_else(|e| {
+        eprintln!("Error deleting from store: {:?}", e);
+        process::exit(1);
+    });
+    if tys.is_empty() {
+        process::exit(1);
+    }
+    for ty in tys {
+        println!("{}", ty);
+    }
 }
 
 fn handle_delete_all(args: &DeleteAllArgs) {
@@ -235,3 +287,33 @@ fn handle_delete_all(args: &DeleteAllArgs) {
         process::exit(1);
     });
 }
+
+fn handle_types(args: &TypesArgs) {
+    let mut store = Store::sqlite_from_path(
+        args.db.clone(),
+        StoreParams {
+            create_if_not_exists: false,
+            read_only: true,
+            ..Default::default()
+        },
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("Error opening store: {:?}", e);
+        process::exit(1);
+    });
+
+    let types = store.list_types().unwrap_or_else(|e| {
+        eprintln!("Error listing types: {:?}", e);
+        process::exit(1);
+    });
+    for info in types {
+        let line = serde_json::json!({
+            "ty": info.ty.to_string(),
+            "count": info.count,
+            "total_bytes": info.total_bytes,
+            "first_write": info.first_write,
+            "last_write": info.last_write,
+        });
+        println!("{}", line);
+    }
+}
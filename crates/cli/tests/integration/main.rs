@@ -55,10 +55,136 @@ fn test_write_get() {
     let hash = stdout.trim();
     println!("{}", hash);
 
-    let (stdout, stderr, exit) = run_vanth(&["get", "--db", &db_path, "--ty", &Foo::ty().to_string(), &hash], None);
+    let (stdout, stderr, exit) = run_vanth(&["get", "--db", &db_path, "--ty", &Foo::ty().to_string(), hash], None);
     if exit != 0 {
         panic!("{}", stderr);
     }
     let recovered_foo = serde_json::from_str(&stdout).unwrap();
     assert_eq!(foo, recovered_foo);
 }
+
+/// Values in a CBOR database are transcoded to JSON by `get` and `get-all`.
+#[test]
+fn test_write_get_cbor() {
+    let tempdir = tempdir().unwrap();
+    let db_path = tempdir.path().join("test.sqlite").to_str().unwrap().to_string();
+    let ty = Foo::ty().to_string();
+
+    let foo = Foo { inner: 7 };
+    let (stdout, stderr, exit) = run_vanth(
+        &[
+            "write",
+            "--db",
+            &db_path,
+            "--ty",
+            &ty,
+            "--encoding",
+            "cbor",
+            "--value",
+            &serde_json::to_string(&foo).unwrap(),
+        ],
+        None,
+    );
+    assert_eq!(exit, 0, "{}", stderr);
+    let hash = stdout.trim().to_string();
+
+    let (stdout, stderr, exit) = run_vanth(&["get", "--db", &db_path, "--ty", &ty, &hash], None);
+    assert_eq!(exit, 0, "{}", stderr);
+    assert_eq!(foo, serde_json::from_str(&stdout).unwrap());
+
+    let (stdout, stderr, exit) = run_vanth(&["get-all", "--db", &db_path, "--ty", &ty], None);
+    assert_eq!(exit, 0, "{}", stderr);
+    assert_eq!(foo, serde_json::from_str(stdout.trim()).unwrap());
+
+    // The database records its encoding, so it can't be reopened with another one.
+    let (_, _, exit) = run_vanth(
+        &[
+            "write",
+            "--db",
+            &db_path,
+            "--ty",
+            &ty,
+            "--encoding",
+            "json",
+            "--value",
+            "{}",
+        ],
+        None,
+    );
+    assert_ne!(exit, 0);
+}
+
+/// Bincode values can only be encoded from their Rust type, so the CLI refuses to write them.
+#[test]
+fn test_write_bincode() {
+    let tempdir = tempdir().unwrap();
+    let db_path = tempdir.path().join("test.sqlite").to_str().unwrap().to_string();
+    let ty = Foo::ty().to_string();
+    let (_, _, exit) = run_vanth(
+        &[
+            "write",
+            "--db",
+            &db_path,
+            "--ty",
+            &ty,
+            "--encoding",
+            "bincode",
+            "--value",
+            "{}",
+        ],
+        None,
+    );
+    assert_ne!(exit, 0);
+}
+
+/// `types` prints one JSON line per type with its count and size.
+#[test]
+fn test_types() {
+    let tempdir = tempdir().unwrap();
+    let db_path = tempdir.path().join("test.sqlite").to_str().unwrap().to_string();
+
+    for value in [r#"{"inner":1}"#, r#"{"inner":2}"#] {
+        let (_, stderr, exit) = run_vanth(
+            &["write", "--db", &db_path, "--ty", &Foo::ty().to_string(), "--value", value],
+            None,
+        );
+        assert_eq!(exit, 0, "{}", stderr);
+    }
+
+    let (stdout, stderr, exit) = run_vanth(&["types", "--db", &db_path], None);
+    assert_eq!(exit, 0, "{}", stderr);
+    let lines: Vec<Value> = stdout.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["ty"], json!(Foo::ty().to_string()));
+    assert_eq!(lines[0]["count"], json!(2));
+    assert_eq!(lines[0]["total_bytes"], json!(r#"{"inner":1}"#.len() * 2));
+    assert!(lines[0]["last_write"].is_u64());
+}
+
+/// `delete` removes a hash from every type it is stored under and prints those types.
+#[test]
+fn test_delete() {
+    let tempdir = tempdir().unwrap();
+    let db_path = tempdir.path().join("test.sqlite").to_str().unwrap().to_string();
+    let value = r#"{"inner":1}"#;
+
+    let mut hash = String::new();
+    for ty in [Foo::ty().to_string(), Bar::ty().to_string()] {
+        let (stdout, stderr, exit) = run_vanth(&["write", "--db", &db_path, "--ty", &ty, "--value", value], None);
+        assert_eq!(exit, 0, "{}", stderr);
+        hash = stdout.trim().to_string();
+    }
+
+    let (stdout, stderr, exit) = run_vanth(&["delete", "--db", &db_path, &hash], None);
+    assert_eq!(exit, 0, "{}", stderr);
+    let mut deleted: Vec<&str> = stdout.lines().collect();
+    deleted.sort();
+    let mut expected = vec![Foo::ty().to_string(), Bar::ty().to_string()];
+    expected.sort();
+    assert_eq!(deleted, expected);
+
+    let (_, _, exit) = run_vanth(&["get", "--db", &db_path, "--ty", &Foo::ty().to_string(), &hash], None);
+    assert_ne!(exit, 0);
+    let (_, _, exit) = run_vanth(&["delete", "--db", &db_path, &hash], None);
+    assert_ne!(exit, 0);
+}
@@ -1,4 +1,4 @@
-//!
+//! Draws the vanth logo, printing it to stdout as SVG.
 
 use std::f32::consts::PI;
 
@@ -18,9 +18,11 @@ impl Point {
         let angle = 2.0 / 6.0 * PI;
         (self.x + amount * angle.cos(), self.y + amount * angle.sin()).into()
     }
+}
 
-    fn to_string(&self) -> String {
-        format!("{},{}", self.x, self.y)
+impl std::fmt::Display for Point {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
     }
 }
 
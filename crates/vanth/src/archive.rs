@@ -0,0 +1,310 @@
+//! Portable archives of stored values.
+//!
+//! [`Store::export`] writes values to a single archive that [`Store::import`] reads into any other store, whatever its
+//! backend. An archive is laid out as:
+//!
+//! - [`MAGIC`], followed by the length of the header as a little-endian `u32` and the [`ArchiveHeader`] as JSON.
+//! - One record per value: a `1` byte, the length of the type's name as a little-endian `u32`, the type's name, the
+//!   32-byte [`ContentHash`], the length of the value as a little-endian `u64` and the value in the archive's
+//!   [`Encoding`].
+//! - A `0` byte, the number of records as a little-endian `u64` and the BLAKE3 hash of everything before it, so that a
+//!   truncated or corrupted archive is rejected.
+
+use std::{
+    collections::HashSet,
+    io::{self, BufReader, BufWriter, Read, Write},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ContentHash, Ty,
+    gc::Collector,
+    store::{Encoding, Error, IterParams, Store, Verifier, unix_millis},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The first bytes of every archive.
+pub const MAGIC: [u8; 8] = *b"VANTHARC";
+
+/// The version of the archive format written by [`Store::export`].
+pub const VERSION: u32 = 1;
+
+/// Describes the values in an archive.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ArchiveHeader {
+    pub version: u32,
+    /// The encoding of every value in the archive, which is the encoding of the store it was exported from.
+    pub encoding: Encoding,
+    /// When the archive was written, in milliseconds since the Unix epoch.
+    pub created: u64,
+}
+
+/// Which values [`Store::export`] writes. By default, every value in the store.
+#[derive(Clone, Debug, Default)]
+pub struct ExportParams {
+    /// Only export values of these types.
+    pub tys: Option<Vec<Ty>>,
+    /// Only export these values and the values they refer to through the types registered with
+    /// [`ExportParams::collector`], as with [`Store::collect_garbage`].
+    pub roots: Option<Vec<(Ty, ContentHash)>>,
+    pub collector: Collector,
+}
+
+/// The result of [`Store::export`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ExportReport {
+    /// Number of values written.
+    pub exported: usize,
+    /// Total size of the values written, as encoded.
+    pub bytes: u64,
+    /// Roots and references pointing to values that are not in the store, and so were not exported.
+    pub missing: Vec<(Ty, ContentHash)>,
+}
+
+/// How [`Store::import`] checks values.
+#[derive(Clone, Debug, Default)]
+pub struct ImportParams {
+    /// Values of types registered with this are re-hashed, and the import fails if one doesn't match its hash.
+    pub verifier: Verifier,
+}
+
+/// The result of [`Store::import`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ImportReport {
+    pub header: Option<ArchiveHeader>,
+    /// Number of values written.
+    pub imported: usize,
+    /// Number of values skipped because the store already had them.
+    pub skipped: usize,
+    /// Types in the archive that are not registered with [`ImportParams::verifier`], so their values were not checked.
+    pub unchecked_tys: Vec<Ty>,
+}
+
+impl Store {
+    /// Write the values selected by `params` to `writer` as an archive.
+    pub fn export(&mut self, writer: impl Write, params: &ExportParams) -> Result<ExportReport> {
+        let mut report = ExportReport::default();
+        let selected: Option<HashSet<(String, ContentHash)>> = match &params.roots {
+            Some(roots) => {
+                let reachable = self.reachable(&params.collector, roots.clone())?;
+                report.missing = reachable.missing;
+                Some(
+                    reachable
+                        .found
+                        .into_iter()
+                        .map(|(ty, content_hash)| (ty.to_string(), content_hash))
+                        .collect(),
+                )
+            }
+            None => None,
+        };
+        let tys: Vec<Ty> = self
+            .backend
+            .list_tys()?
+            .into_iter()
+            .filter(|ty| match &params.tys {
+                Some(tys) => tys.iter().any(|wanted| wanted == &ty.to_string()),
+                None => true,
+            })
+            .collect();
+
+        let mut writer = HashingWriter::new(BufWriter::new(writer));
+        let header = ArchiveHeader {
+            version: VERSION,
+            encoding: self.encoding,
+            created: unix_millis(SystemTime::now()),
+        };
+        let header = serde_json::to_vec(&header)?;
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&(header.len() as u32).to_le_bytes())?;
+        writer.write_all(&header)?;
+
+        for ty in tys {
+            let name = ty.to_string();
+            for item in self.iter_of_type_raw(ty.clone(), IterParams::default()) {
+                let (content_hash, data) = item?;
+                if let Some(selected) = &selected
+                    && !selected.contains(&(name.clone(), content_hash))
+                {
+                    continue;
+                }
+                writer.write_all(&[1])?;
+                writer.write_all(&(name.len() as u32).to_le_bytes())?;
+                writer.write_all(name.as_bytes())?;
+                writer.write_all(&content_hash.hash)?;
+                writer.write_all(&(data.len() as u64).to_le_bytes())?;
+                writer.write_all(&data)?;
+                report.exported += 1;
+                report.bytes += data.len() as u64;
+            }
+        }
+
+        writer.write_all(&[0])?;
+        writer.write_all(&(report.exported as u64).to_le_bytes())?;
+        let checksum = writer.hasher.finalize();
+        writer.inner.write_all(checksum.as_bytes())?;
+        writer.inner.flush()?;
+        Ok(report)
+    }
+
+    /// Write the values in the archive read from `reader` that the store doesn't already have.
+    ///
+    /// The archive must have been exported from a store with the same [`Encoding`]. Nothing is written unless the whole
+    /// archive is valid: if it is truncated or corrupted, or a value doesn't match its hash, the import fails.
+    pub fn import(&mut self, reader: impl Read, params: &ImportParams) -> Result<ImportReport> {
+        let mut reader = HashingReader::new(BufReader::new(reader));
+        if reader.read_array::<8>()? != MAGIC {
+            return Err(Error::InvalidArchive("not a vanth archive".into()));
+        }
+        let header_len = u32::from_le_bytes(reader.read_array()?) as usize;
+        let header: ArchiveHeader = serde_json::from_slice(&reader.read_vec(header_len)?)?;
+        if header.version != VERSION {
+            return Err(Error::InvalidArchive(format!(
+                "unsupported archive version {}",
+                header.version
+            )));
+        }
+        if header.encoding != self.encoding {
+            return Err(Error::EncodingMismatch {
+                stored: self.encoding,
+                requested: header.encoding,
+            });
+        }
+
+        let encoding = self.encoding;
+        let mut report = ImportReport::default();
+        self.transaction(|tx| {
+            let mut records = 0u64;
+            while reader.read_array::<1>()? == [1] {
+                let name_len = u32::from_le_bytes(reader.read_array()?) as usize;
+                let name = String::from_utf8(reader.read_vec(name_len)?)
+                    .map_err(|_| Error::InvalidArchive("type name is not UTF-8".into()))?;
+                let ty = Ty::from(name.as_str());
+                let content_hash = ContentHash {
+                    hash: reader.read_array()?,
+                };
+                let data_len = u64::from_le_bytes(reader.read_array()?) as usize;
+                let data = reader.read_vec(data_len)?;
+                records += 1;
+
+                match params.verifier.rehashers.get(&name) {
+                    Some((_, rehash)) => {
+                        let actual = rehash(encoding, &data).map_err(|message| Error::Deserialization {
+                            ty: ty.clone(),
+                            content_hash,
+                            message,
+                        })?;
+                        if actual != content_hash {
+                            return Err(Error::HashMismatch {
+                                ty,
+                                expected: content_hash,
+                                actual,
+                            });
+                        }
+                    }
+                    None if !report.unchecked_tys.contains(&ty) => report.unchecked_tys.push(ty.clone()),
+                    None => {}
+                }
+
+                let present = match tx.get_from_hash_raw(ty.clone(), content_hash) {
+                    Ok(present) => present.is_some(),
+                    Err(Error::SqliteTableDoesNotExist { .. }) => false,
+                    Err(e) => return Err(e),
+                };
+                if present {
+                    report.skipped += 1;
+                } else {
+                    tx.write_raw(ty, content_hash, data)?;
+                    report.imported += 1;
+                }
+            }
+
+            let count = u64::from_le_bytes(reader.read_array()?);
+            let expected = reader.hasher.finalize();
+            let mut checksum = [0; 32];
+            reader.inner.read_exact(&mut checksum).map_err(truncated)?;
+            if count != records || checksum != *expected.as_bytes() {
+                return Err(Error::InvalidArchive("archive is corrupted".into()));
+            }
+            Ok(())
+        })?;
+        report.header = Some(header);
+        Ok(report)
+    }
+}
+
+/// Hashes everything written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: blake3::Hasher::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hashes everything read through it.
+struct HashingReader<R> {
+    inner: R,
+    hasher: blake3::Hasher,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: blake3::Hasher::new(),
+        }
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.read_exact(&mut buf).map_err(truncated)?;
+        Ok(buf)
+    }
+
+    /// Read `len` bytes, without allocating them all up front, since `len` is read from the archive.
+    fn read_vec(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.by_ref().take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            return Err(truncated(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(buf)
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn truncated(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => Error::InvalidArchive("archive is truncated".into()),
+        _ => err.into(),
+    }
+}
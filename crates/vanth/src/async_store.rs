@@ -0,0 +1,176 @@
+//! An async facade over [`Store`].
+//!
+//! [`AsyncStore`] moves a [`Store`] onto a dedicated worker thread and sends it operations over a channel. Each
+//! operation returns a [`Task`], a future that resolves once the worker has run it, so store I/O can be awaited without
+//! blocking an executor. Nothing here depends on a particular async runtime.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, mpsc},
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    ContentHash, Ty, Vanth,
+    store::{Error, Store},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+type Job = Box<dyn FnOnce(&mut Store) + Send>;
+
+/// A [`Store`] owned by a worker thread.
+///
+/// Operations run one at a time in the order they were submitted, so a read submitted after a write sees the write.
+/// Dropping the `AsyncStore` lets the worker finish the operations already submitted and then close the store; use
+/// [`AsyncStore::into_inner`] to get the store back instead.
+#[derive(Debug)]
+pub struct AsyncStore {
+    sender: mpsc::Sender<Job>,
+    worker: JoinHandle<Store>,
+}
+
+impl AsyncStore {
+    /// Move `store` onto a new worker thread.
+    pub fn new(store: Store) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let worker = thread::Builder::new()
+            .name("vanth-store".into())
+            .spawn(move || {
+                let mut store = store;
+                for job in receiver {
+                    job(&mut store);
+                }
+                store
+            })
+            .expect("failed to spawn store worker thread");
+        Self { sender, worker }
+    }
+
+    /// Run `f` on the worker thread with exclusive access to the store.
+    pub fn run<R: Send + 'static>(&self, f: impl FnOnce(&mut Store) -> Result<R> + Send + 'static) -> Task<R> {
+        let (task, completer) = Task::new();
+        // If the worker has stopped, the job is dropped along with its completer, which fails the task.
+        let _ = self.sender.send(Box::new(move |store| completer.complete(f(store))));
+        task
+    }
+
+    pub fn get_from_hash<T: Vanth + Serialize + DeserializeOwned + Send + 'static>(
+        &self,
+        content_hash: ContentHash,
+    ) -> Task<Option<T>> {
+        self.run(move |store| store.get_from_hash(content_hash))
+    }
+
+    pub fn get_from_hash_raw(&self, ty: Ty, content_hash: ContentHash) -> Task<Option<Vec<u8>>> {
+        self.run(move |store| store.get_from_hash_raw(ty, content_hash))
+    }
+
+    pub fn get_all_of_type<T: Vanth + DeserializeOwned + Send + 'static>(&self) -> Task<Vec<(ContentHash, T)>> {
+        self.run(|store| store.get_all_of_type())
+    }
+
+    pub fn write<T: Vanth + Serialize + Send + 'static>(&self, value: T) -> Task<ContentHash> {
+        self.run(move |store| store.write(&value))
+    }
+
+    pub fn write_raw(&self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Task<()> {
+        self.run(move |store| store.write_raw(ty, content_hash, content))
+    }
+
+    pub fn delete<T: Vanth>(&self, content_hash: ContentHash) -> Task<()> {
+        self.run(move |store| store.delete::<T>(content_hash))
+    }
+
+    pub fn delete_raw(&self, ty: Ty, content_hash: ContentHash) -> Task<()> {
+        self.run(move |store| store.delete_raw(ty, content_hash))
+    }
+
+    /// Wait for every submitted operation to finish, then take the store back from the worker. This blocks the calling
+    /// thread. Fails with [`Error::WorkerStopped`] if an operation panicked.
+    pub fn into_inner(self) -> Result<Store> {
+        drop(self.sender);
+        self.worker.join().map_err(|_| Error::WorkerStopped)
+    }
+}
+
+/// The result of an operation submitted to an [`AsyncStore`], available once the worker has run it.
+///
+/// Resolves to [`Error::WorkerStopped`] if the worker stopped before running the operation.
+pub struct Task<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+struct Shared<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T> Task<T> {
+    fn new() -> (Self, Completer<T>) {
+        let shared = Arc::new(Mutex::new(Shared {
+            result: None,
+            waker: None,
+        }));
+        let completer = Completer {
+            shared: Some(shared.clone()),
+        };
+        (Self { shared }, completer)
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Task<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let done = self.shared.lock().unwrap().result.is_some();
+        f.debug_struct("Task").field("done", &done).finish()
+    }
+}
+
+/// The worker's side of a [`Task`]. Dropping it without completing the task, e.g. because the operation panicked,
+/// fails the task.
+struct Completer<T> {
+    shared: Option<Arc<Mutex<Shared<T>>>>,
+}
+
+impl<T> Completer<T> {
+    fn complete(mut self, result: Result<T>) {
+        self.finish(result);
+    }
+
+    fn finish(&mut self, result: Result<T>) {
+        let Some(shared) = self.shared.take() else {
+            return;
+        };
+        let mut shared = shared.lock().unwrap();
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        self.finish(Err(Error::WorkerStopped));
+    }
+}
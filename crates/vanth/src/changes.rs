@@ -0,0 +1,459 @@
+//! Notifications of writes and deletes.
+//!
+//! Every [`Store`] numbers the changes made through it and passes them to its subscribers, registered with
+//! [`Store::subscribe`] or [`Store::on_change`]. Changes made in a transaction are only passed on once it is committed.
+//!
+//! With [`StoreParams::change_log`] set, changes are also recorded in the store along with the last sequence number, so
+//! that a consumer can read the changes it missed with [`Store::changes_since`] after a restart. Without it, sequence
+//! numbers continue from the last recorded one but are not persisted.
+//!
+//! [`StoreParams::change_log`]: crate::store::StoreParams::change_log
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, mpsc},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ContentHash, Ty,
+    pins::is_pin_ty,
+    query::{FieldPath, FieldValue, Query},
+    store::{Backend, CacheStats, Encoding, Error, Store, TypeInfo, Values, or_missing},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The type the change log is stored under, keyed by [`log_key`].
+const LOG_TY: &str = "vanth_change";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum ChangeKind {
+    Write,
+    Delete,
+}
+
+/// A value written to or deleted from a [`Store`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Change {
+    /// Increases by one with each change to the store.
+    pub seq: u64,
+    pub kind: ChangeKind,
+    pub ty: Ty,
+    pub content_hash: ContentHash,
+}
+
+/// Identifies a callback registered with [`Store::on_change`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+enum Sink {
+    Channel(mpsc::Sender<Change>),
+    Callback(Box<dyn FnMut(&Change) + Send>),
+}
+
+struct Subscriber {
+    id: SubscriptionId,
+    /// Only changes to these types, compared by their string form, are passed on. All changes if `None`.
+    tys: Option<Vec<String>>,
+    sink: Sink,
+}
+
+/// The state shared between a [`Store`] and its [`ChangeFeed`].
+#[derive(Default)]
+pub(crate) struct Feed {
+    subscribers: Vec<Subscriber>,
+    next_id: u64,
+    /// The sequence number of the last committed change.
+    last_seq: u64,
+}
+
+impl fmt::Debug for Feed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Feed")
+            .field("subscribers", &self.subscribers.len())
+            .field("last_seq", &self.last_seq)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Feed {
+    fn subscribe(&mut self, tys: Option<Vec<Ty>>, sink: Sink) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push(Subscriber {
+            id,
+            tys: tys.map(|tys| tys.iter().map(ToString::to_string).collect()),
+            sink,
+        });
+        id
+    }
+
+    /// Pass `changes` to every subscriber interested in them, dropping channels whose receiver has been dropped.
+    fn publish(&mut self, changes: &[Change]) {
+        if let Some(last) = changes.last() {
+            self.last_seq = last.seq;
+        }
+        self.subscribers.retain_mut(|subscriber| {
+            for change in changes {
+                if let Some(tys) = &subscriber.tys
+                    && !tys.iter().any(|ty| change.ty == ty)
+                {
+                    continue;
+                }
+                match &mut subscriber.sink {
+                    Sink::Channel(sender) => {
+                        if sender.send(change.clone()).is_err() {
+                            return false;
+                        }
+                    }
+                    Sink::Callback(callback) => callback(change),
+                }
+            }
+            true
+        });
+    }
+}
+
+/// Lock `feed`, ignoring poisoning from a callback that panicked.
+fn lock(feed: &Mutex<Feed>) -> MutexGuard<'_, Feed> {
+    feed.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A [`Backend`] that numbers the writes and deletes passed through it, records them if logging is enabled and
+/// publishes them to the subscribers of its [`Feed`].
+///
+/// Every [`Store`] is built on one. The change log is hidden from [`Backend::list_tys`].
+pub(crate) struct ChangeFeed {
+    backend: Box<dyn Backend>,
+    feed: Arc<Mutex<Feed>>,
+    log: bool,
+    /// The sequence number of the last change.
+    seq: u64,
+    /// The sequence number of the last committed change, restored if a transaction is rolled back.
+    committed_seq: u64,
+    /// Changes made in the open transaction, if there is one.
+    pending: Option<Vec<Change>>,
+    /// Whether `seq` has been read back from the store in the open transaction, so that stores sharing a logged
+    /// backend don't hand out the same sequence numbers.
+    synced: bool,
+}
+
+impl fmt::Debug for ChangeFeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChangeFeed")
+            .field("backend", &self.backend)
+            .field("log", &self.log)
+            .field("seq", &self.seq)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ChangeFeed {
+    pub(crate) const METADATA_KEY: &'static str = "change_seq";
+
+    pub(crate) fn new(mut backend: Box<dyn Backend>, feed: Arc<Mutex<Feed>>, log: bool) -> Result<Self> {
+        let seq = stored_seq(backend.as_mut())?;
+        lock(&feed).last_seq = seq;
+        Ok(Self {
+            backend,
+            feed,
+            log,
+            seq,
+            committed_seq: seq,
+            pending: None,
+            synced: false,
+        })
+    }
+
+    /// Whether changes need to be found out about, which for deletes means reading what is being deleted.
+    fn observed(&self) -> bool {
+        self.log || !lock(&self.feed).subscribers.is_empty()
+    }
+
+    /// Number, record and publish `changes`, made by `f`, together.
+    fn record(
+        &mut self,
+        changes: Vec<(ChangeKind, Ty, ContentHash)>,
+        f: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let in_transaction = self.pending.is_some();
+        if !self.log || changes.is_empty() || in_transaction {
+            f(self)?;
+            return self.push(changes);
+        }
+        // Outside a transaction, the change and its log entries are written in one.
+        self.begin_transaction()?;
+        let result = f(self).and_then(|()| self.push(changes));
+        match result {
+            Ok(()) => self.commit_transaction(),
+            Err(e) => {
+                self.rollback_transaction()?;
+                Err(e)
+            }
+        }
+    }
+
+    fn push(&mut self, changes: Vec<(ChangeKind, Ty, ContentHash)>) -> Result<()> {
+        if self.log && !self.synced && !changes.is_empty() {
+            // Always in a transaction, since logged changes are only made in one.
+            self.seq = self.seq.max(stored_seq(self.backend.as_mut())?);
+            self.synced = true;
+        }
+        let mut numbered = Vec::with_capacity(changes.len());
+        for (kind, ty, content_hash) in changes {
+            self.seq += 1;
+            let change = Change {
+                seq: self.seq,
+                kind,
+                ty,
+                content_hash,
+            };
+            if self.log {
+                self.backend
+                    .write(Ty::from(LOG_TY), log_key(change.seq), serde_json::to_vec(&change)?)?;
+            }
+            numbered.push(change);
+        }
+        match &mut self.pending {
+            Some(pending) => pending.extend(numbered),
+            None => {
+                self.committed_seq = self.seq;
+                lock(&self.feed).publish(&numbered);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The sequence number of the last change recorded in `backend`.
+fn stored_seq(backend: &mut dyn Backend) -> Result<u64> {
+    match backend.get_metadata(ChangeFeed::METADATA_KEY)? {
+        Some(seq) => seq
+            .parse()
+            .map_err(|_| Error::Serializiation(format!("invalid change sequence number {}", seq))),
+        None => Ok(0),
+    }
+}
+
+/// The key the change with sequence number `seq` is logged under, which sorts in order of sequence number.
+fn log_key(seq: u64) -> ContentHash {
+    let mut hash = [0; 32];
+    hash[..8].copy_from_slice(&seq.to_be_bytes());
+    ContentHash { hash }
+}
+
+/// Whether `ty` is the change log or another type the store keeps its own records under, whose changes aren't
+/// published.
+fn is_internal_ty(ty: &Ty) -> bool {
+    *ty == LOG_TY || is_pin_ty(ty)
+}
+
+impl Backend for ChangeFeed {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        self.backend.get_from_hash(ty, content_hash)
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Values> {
+        self.backend.get_all_of_ty(ty)
+    }
+
+    fn get_page_of_ty(&mut self, ty: Ty, after: Option<ContentHash>, offset: usize, limit: usize) -> Result<Values> {
+        self.backend.get_page_of_ty(ty, after, offset, limit)
+    }
+
+    /// Writing a value that is already stored is not a change.
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        if is_internal_ty(&ty) || !self.observed() {
+            return self.backend.write(ty, content_hash, content);
+        }
+        let mut changes = Vec::new();
+        if or_missing(self.backend.get_from_hash(ty.clone(), content_hash), None)?.is_none() {
+            changes.push((ChangeKind::Write, ty.clone(), content_hash));
+        }
+        self.record(changes, |this| this.backend.write(ty, content_hash, content))
+    }
+
+    /// Deleting a value that isn't stored is not a change.
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        if is_internal_ty(&ty) || !self.observed() {
+            return self.backend.delete_by_hash(ty, content_hash);
+        }
+        let mut changes = Vec::new();
+        if or_missing(self.backend.get_from_hash(ty.clone(), content_hash), None)?.is_some() {
+            changes.push((ChangeKind::Delete, ty.clone(), content_hash));
+        }
+        self.record(changes, |this| this.backend.delete_by_hash(ty, content_hash))
+    }
+
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
+        if is_internal_ty(&ty) || !self.observed() {
+            return self.backend.delete_all_of_ty(ty);
+        }
+        let changes = or_missing(self.backend.get_all_of_ty(ty.clone()), Vec::new())?
+            .into_iter()
+            .map(|(content_hash, _)| (ChangeKind::Delete, ty.clone(), content_hash))
+            .collect();
+        self.record(changes, |this| this.backend.delete_all_of_ty(ty))
+    }
+
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        let mut tys = self.backend.list_tys()?;
+        tys.retain(|ty| !is_internal_ty(ty));
+        Ok(tys)
+    }
+
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        self.backend.type_info(ty)
+    }
+
+    fn list_types(&mut self) -> Result<Vec<TypeInfo>> {
+        let mut types = self.backend.list_types()?;
+        types.retain(|info| !is_internal_ty(&info.ty));
+        Ok(types)
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        let mut tys = self.backend.find_by_hash(content_hash)?;
+        tys.retain(|ty| !is_internal_ty(ty));
+        Ok(tys)
+    }
+
+    fn create_index(&mut self, ty: Ty, path: &FieldPath, encoding: Encoding) -> Result<()> {
+        self.backend.create_index(ty, path, encoding)
+    }
+
+    fn drop_indexes(&mut self, ty: Ty) -> Result<()> {
+        self.backend.drop_indexes(ty)
+    }
+
+    fn find_by_field(
+        &mut self,
+        ty: Ty,
+        path: &FieldPath,
+        value: &FieldValue,
+        encoding: Encoding,
+    ) -> Result<Option<Values>> {
+        self.backend.find_by_field(ty, path, value, encoding)
+    }
+
+    fn query(&mut self, ty: Ty, query: &Query, encoding: Encoding) -> Result<Option<Values>> {
+        self.backend.query(ty, query, encoding)
+    }
+
+    fn storage_name(&self, ty: &Ty) -> String {
+        self.backend.storage_name(ty)
+    }
+
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        self.backend.foreign_tables()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.backend.cache_stats()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.backend.flush()
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        self.backend.get_metadata(key)
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        self.backend.set_metadata(key, value)
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        self.backend.begin_transaction()?;
+        self.pending = Some(Vec::new());
+        self.synced = false;
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        if self.log && self.seq != self.committed_seq {
+            self.backend.set_metadata(Self::METADATA_KEY, &self.seq.to_string())?;
+        }
+        self.backend.commit_transaction()?;
+        self.committed_seq = self.seq;
+        if let Some(pending) = self.pending.take() {
+            lock(&self.feed).publish(&pending);
+        }
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.pending = None;
+        self.seq = self.committed_seq;
+        self.backend.rollback_transaction()
+    }
+}
+
+impl Store {
+    /// Receive every change to values of `tys`, or to every value if `tys` is `None`, made through this store from now
+    /// on. Dropping the receiver ends the subscription.
+    pub fn subscribe(&mut self, tys: Option<Vec<Ty>>) -> mpsc::Receiver<Change> {
+        let (sender, receiver) = mpsc::channel();
+        lock(&self.feed).subscribe(tys, Sink::Channel(sender));
+        receiver
+    }
+
+    /// Call `callback` with every change to values of `tys`, or to every value if `tys` is `None`, made through this
+    /// store from now on. The callback runs on the thread making the change, before the change's operation returns.
+    pub fn on_change(
+        &mut self,
+        tys: Option<Vec<Ty>>,
+        callback: impl FnMut(&Change) + Send + 'static,
+    ) -> SubscriptionId {
+        lock(&self.feed).subscribe(tys, Sink::Callback(Box::new(callback)))
+    }
+
+    /// Remove a callback registered with [`Store::on_change`]. Returns whether it was registered.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let mut feed = lock(&self.feed);
+        let count = feed.subscribers.len();
+        feed.subscribers.retain(|subscriber| subscriber.id != id);
+        feed.subscribers.len() != count
+    }
+
+    /// Up to `limit` logged changes with sequence numbers greater than `seq`, in order. Empty unless the store was
+    /// opened with [`crate::store::StoreParams::change_log`].
+    pub fn changes_since(&mut self, seq: u64, limit: usize) -> Result<Vec<Change>> {
+        let page = self
+            .backend
+            .get_page_of_ty(Ty::from(LOG_TY), Some(log_key(seq)), 0, limit);
+        or_missing(page, Vec::new())?
+            .into_iter()
+            .map(|(_, data)| Ok(serde_json::from_slice(&data)?))
+            .collect()
+    }
+
+    /// Delete logged changes with sequence numbers up to and including `seq`, once every consumer has seen them.
+    pub fn prune_changes(&mut self, seq: u64) -> Result<()> {
+        const PAGE_SIZE: usize = 1000;
+        let log_ty = Ty::from(LOG_TY);
+        loop {
+            let page = or_missing(
+                self.backend.get_page_of_ty(log_ty.clone(), None, 0, PAGE_SIZE),
+                Vec::new(),
+            )?;
+            for (key, _) in &page {
+                if *key > log_key(seq) {
+                    return Ok(());
+                }
+                self.backend.delete_by_hash(log_ty.clone(), *key)?;
+            }
+            if page.len() < PAGE_SIZE {
+                return Ok(());
+            }
+        }
+    }
+
+    /// The sequence number of the last committed change, or 0 if there have been none.
+    pub fn last_seq(&self) -> u64 {
+        lock(&self.feed).last_seq
+    }
+}
@@ -0,0 +1,378 @@
+//! Content-defined chunking of large values.
+//!
+//! With [`StoreParams::chunking`] set, values of at least [`Chunking::threshold_bytes`] are split into chunks with
+//! FastCDC, which places chunk boundaries by content rather than by offset. Each chunk is stored once under the hash of
+//! its bytes, and the value is stored as a manifest listing its chunks, so values that differ in a few bytes share
+//! most of their chunks. Reads reassemble chunked values, and hashes are still computed over the values themselves.
+//!
+//! Each chunk has a count of the manifests that refer to it, and is deleted along with the last of them.
+//!
+//! Once a store has been opened with chunking, it records that in its metadata and always reassembles values it
+//! reads, even if it is later opened without [`StoreParams::chunking`].
+//!
+//! [`StoreParams::chunking`]: crate::store::StoreParams::chunking
+
+use std::collections::BTreeSet;
+
+use fastcdc::v2020::{self as cdc, FastCDC};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ContentHash, Ty,
+    store::{Backend, CacheStats, Error, TypeInfo, Values, or_missing},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The start of every manifest, followed by the length of the value as a little-endian `u64` and then the hashes of
+/// its chunks.
+const MANIFEST_MAGIC: [u8; 8] = *b"\0vanthcm";
+
+/// The type chunks are stored under.
+const CHUNK_TY: &str = "vanth_chunk";
+
+/// The type the number of manifests referring to each chunk is stored under, as a little-endian `u64`.
+const CHUNK_REFS_TY: &str = "vanth_chunk_refs";
+
+/// How values are chunked, as set by [`StoreParams::chunking`].
+///
+/// [`StoreParams::chunking`]: crate::store::StoreParams::chunking
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Chunking {
+    /// Values smaller than this many bytes once encoded are stored whole.
+    pub threshold_bytes: usize,
+    /// The smallest chunk size, except for the last chunk of a value. At least 64.
+    pub min_chunk_bytes: u32,
+    /// The chunk size aimed for. At least 256.
+    pub avg_chunk_bytes: u32,
+    /// The largest chunk size. At least 1024.
+    pub max_chunk_bytes: u32,
+}
+
+impl Default for Chunking {
+    fn default() -> Self {
+        Self {
+            threshold_bytes: 256 * 1024,
+            min_chunk_bytes: 16 * 1024,
+            avg_chunk_bytes: 64 * 1024,
+            max_chunk_bytes: 256 * 1024,
+        }
+    }
+}
+
+impl Chunking {
+    pub(crate) const METADATA_KEY: &'static str = "chunking";
+
+    /// Check the chunk sizes are within the bounds FastCDC accepts and in order.
+    pub fn validate(&self) -> Result<()> {
+        let in_range = (cdc::MINIMUM_MIN..=cdc::MINIMUM_MAX).contains(&self.min_chunk_bytes)
+            && (cdc::AVERAGE_MIN..=cdc::AVERAGE_MAX).contains(&self.avg_chunk_bytes)
+            && (cdc::MAXIMUM_MIN..=cdc::MAXIMUM_MAX).contains(&self.max_chunk_bytes);
+        if !in_range || self.min_chunk_bytes > self.avg_chunk_bytes || self.avg_chunk_bytes > self.max_chunk_bytes {
+            return Err(Error::InvalidChunking(format!("{:?}", self)));
+        }
+        Ok(())
+    }
+}
+
+/// A [`Backend`] that splits large values into chunks on their way to another backend and reassembles them on their way
+/// back.
+///
+/// The types chunks are stored under are hidden from [`Backend::list_tys`].
+#[derive(Debug)]
+pub struct Chunked {
+    backend: Box<dyn Backend>,
+    /// `None` if values are only reassembled, for stores that have chunked values but weren't opened with chunking.
+    chunking: Option<Chunking>,
+    in_transaction: bool,
+}
+
+impl Chunked {
+    pub fn new(backend: Box<dyn Backend>, chunking: Option<Chunking>) -> Self {
+        Self {
+            backend,
+            chunking,
+            in_transaction: false,
+        }
+    }
+
+    /// The manifest to store for `content`, or `None` if it is stored whole.
+    fn chunk(&mut self, content: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(chunking) = self.chunking else {
+            return Ok(None);
+        };
+        // Values that look like a manifest are chunked regardless of size, so that they aren't mistaken for one.
+        if content.len() < chunking.threshold_bytes && !content.starts_with(&MANIFEST_MAGIC) {
+            return Ok(None);
+        }
+        let mut manifest = MANIFEST_MAGIC.to_vec();
+        manifest.extend_from_slice(&(content.len() as u64).to_le_bytes());
+        let chunker = FastCDC::new(
+            content,
+            chunking.min_chunk_bytes,
+            chunking.avg_chunk_bytes,
+            chunking.max_chunk_bytes,
+        );
+        for chunk in chunker {
+            let data = &content[chunk.offset..chunk.offset + chunk.length];
+            let chunk_hash = ContentHash {
+                hash: *blake3::hash(data).as_bytes(),
+            };
+            if self.chunk_refs(chunk_hash)? == 0 {
+                self.backend.write(chunk_ty(), chunk_hash, data.to_vec())?;
+            }
+            manifest.extend_from_slice(&chunk_hash.hash);
+        }
+        Ok(Some(manifest))
+    }
+
+    /// The value a stored value is for, reassembling it if it is a manifest.
+    fn reassemble(&mut self, ty: &Ty, content_hash: ContentHash, data: Vec<u8>) -> Result<Vec<u8>> {
+        let Some((len, chunks)) = parse_manifest(&data) else {
+            return Ok(data);
+        };
+        // The manifest's length isn't trusted to size the value, since the manifest may be corrupt.
+        let mut value = Vec::new();
+        for chunk in chunks {
+            match or_missing(self.backend.get_from_hash(chunk_ty(), chunk), None)? {
+                Some(data) => value.extend(data),
+                None => {
+                    return Err(Error::MissingChunk {
+                        ty: ty.clone(),
+                        content_hash,
+                        chunk,
+                    });
+                }
+            }
+        }
+        if value.len() as u64 != len {
+            return Err(Error::ChunkedLengthMismatch {
+                ty: ty.clone(),
+                content_hash,
+                expected: len,
+                actual: value.len() as u64,
+            });
+        }
+        Ok(value)
+    }
+
+    fn reassemble_all(&mut self, ty: &Ty, values: Values) -> Result<Values> {
+        values
+            .into_iter()
+            .map(|(content_hash, data)| Ok((content_hash, self.reassemble(ty, content_hash, data)?)))
+            .collect()
+    }
+
+    /// The distinct chunks of the manifest stored under `content_hash`, if there is one.
+    fn stored_chunks(&mut self, ty: &Ty, content_hash: ContentHash) -> Result<Option<BTreeSet<ContentHash>>> {
+        let stored = or_missing(self.backend.get_from_hash(ty.clone(), content_hash), None)?;
+        Ok(stored
+            .as_deref()
+            .and_then(parse_manifest)
+            .map(|(_, chunks)| chunks.collect()))
+    }
+
+    fn chunk_refs(&mut self, chunk: ContentHash) -> Result<u64> {
+        let refs = or_missing(self.backend.get_from_hash(chunk_refs_ty(), chunk), None)?;
+        Ok(refs.and_then(|refs| refs.try_into().ok()).map_or(0, u64::from_le_bytes))
+    }
+
+    fn add_refs(&mut self, chunks: &BTreeSet<ContentHash>) -> Result<()> {
+        for &chunk in chunks {
+            let refs = self.chunk_refs(chunk)? + 1;
+            self.backend
+                .write(chunk_refs_ty(), chunk, refs.to_le_bytes().to_vec())?;
+        }
+        Ok(())
+    }
+
+    /// Take a reference away from each of `chunks`, deleting those with none left.
+    fn remove_refs(&mut self, chunks: &BTreeSet<ContentHash>) -> Result<()> {
+        for &chunk in chunks {
+            match self.chunk_refs(chunk)? {
+                0 | 1 => {
+                    or_missing(self.backend.delete_by_hash(chunk_ty(), chunk), ())?;
+                    or_missing(self.backend.delete_by_hash(chunk_refs_ty(), chunk), ())?;
+                }
+                refs => {
+                    self.backend
+                        .write(chunk_refs_ty(), chunk, (refs - 1).to_le_bytes().to_vec())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `f` in a transaction on the backend, unless one is already open, so that a value and the counts of its
+    /// chunks change together.
+    fn atomically<R>(&mut self, f: impl FnOnce(&mut Self) -> Result<R>) -> Result<R> {
+        if self.in_transaction {
+            return f(self);
+        }
+        self.backend.begin_transaction()?;
+        match f(self) {
+            Ok(result) => {
+                self.backend.commit_transaction()?;
+                Ok(result)
+            }
+            Err(e) => {
+                self.backend.rollback_transaction()?;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The length and chunks of `data` if it is a manifest.
+fn parse_manifest(data: &[u8]) -> Option<(u64, impl Iterator<Item = ContentHash> + '_)> {
+    let rest = data.strip_prefix(&MANIFEST_MAGIC)?;
+    let (len, chunks) = rest.split_first_chunk::<8>()?;
+    let (chunks, []) = chunks.as_chunks::<32>() else {
+        return None;
+    };
+    let len = u64::from_le_bytes(*len);
+    Some((len, chunks.iter().map(|&hash| ContentHash { hash })))
+}
+
+fn chunk_ty() -> Ty {
+    Ty::from(CHUNK_TY)
+}
+
+fn chunk_refs_ty() -> Ty {
+    Ty::from(CHUNK_REFS_TY)
+}
+
+fn is_chunk_ty(ty: &Ty) -> bool {
+    *ty == CHUNK_TY || *ty == CHUNK_REFS_TY
+}
+
+impl Backend for Chunked {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        match self.backend.get_from_hash(ty.clone(), content_hash)? {
+            Some(data) => Ok(Some(self.reassemble(&ty, content_hash, data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Values> {
+        let values = self.backend.get_all_of_ty(ty.clone())?;
+        self.reassemble_all(&ty, values)
+    }
+
+    fn get_page_of_ty(&mut self, ty: Ty, after: Option<ContentHash>, offset: usize, limit: usize) -> Result<Values> {
+        let values = self.backend.get_page_of_ty(ty.clone(), after, offset, limit)?;
+        self.reassemble_all(&ty, values)
+    }
+
+    /// Replacing a value with the same manifest is skipped, so that its chunks aren't counted twice.
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        self.atomically(|this| {
+            let old = this.stored_chunks(&ty, content_hash)?;
+            let content = this.chunk(&content)?.unwrap_or(content);
+            let new = parse_manifest(&content).map(|(_, chunks)| chunks.collect::<BTreeSet<_>>());
+            if old.is_some() && old == new {
+                return Ok(());
+            }
+            if let Some(new) = &new {
+                this.add_refs(new)?;
+            }
+            this.backend.write(ty, content_hash, content)?;
+            if let Some(old) = &old {
+                this.remove_refs(old)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        self.atomically(|this| {
+            let old = this.stored_chunks(&ty, content_hash)?;
+            this.backend.delete_by_hash(ty, content_hash)?;
+            if let Some(old) = &old {
+                this.remove_refs(old)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
+        self.atomically(|this| {
+            let values = or_missing(this.backend.get_all_of_ty(ty.clone()), Vec::new())?;
+            this.backend.delete_all_of_ty(ty)?;
+            for (_, data) in values {
+                if let Some((_, chunks)) = parse_manifest(&data) {
+                    this.remove_refs(&chunks.collect())?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        let mut tys = self.backend.list_tys()?;
+        tys.retain(|ty| !is_chunk_ty(ty));
+        Ok(tys)
+    }
+
+    /// Sizes are of the values as stored, so a chunked value counts as the size of its manifest.
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        self.backend.type_info(ty)
+    }
+
+    fn list_types(&mut self) -> Result<Vec<TypeInfo>> {
+        let mut types = self.backend.list_types()?;
+        types.retain(|info| !is_chunk_ty(&info.ty));
+        Ok(types)
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        let mut tys = self.backend.find_by_hash(content_hash)?;
+        tys.retain(|ty| !is_chunk_ty(ty));
+        Ok(tys)
+    }
+
+    fn storage_name(&self, ty: &Ty) -> String {
+        self.backend.storage_name(ty)
+    }
+
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        self.backend.foreign_tables()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.backend.cache_stats()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.backend.flush()
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        if key == Chunking::METADATA_KEY {
+            return Ok(None);
+        }
+        self.backend.get_metadata(key)
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        self.backend.set_metadata(key, value)
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        self.backend.begin_transaction()?;
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        self.backend.commit_transaction()?;
+        self.in_transaction = false;
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.backend.rollback_transaction()?;
+        self.in_transaction = false;
+        Ok(())
+    }
+}
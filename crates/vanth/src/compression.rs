@@ -0,0 +1,232 @@
+//! Transparent compression of stored values.
+//!
+//! With [`StoreParams::compression`] set, values are compressed with zstd before being passed to the backend, unless
+//! they are smaller than [`Compression::threshold_bytes`]. Compressed and uncompressed values can be stored side by side:
+//! each stored value starts with a byte saying whether the rest is a zstd frame or the encoded value as it is. Hashes
+//! are still computed over the values themselves, so compression never changes a hash.
+//!
+//! Once a store has been opened with compression, it records that in its metadata and always decompresses values it
+//! reads, even if it is later opened without [`StoreParams::compression`]. Values already in the store when compression
+//! is first enabled are rewritten with the flag byte.
+//!
+//! [`StoreParams::compression`]: crate::store::StoreParams::compression
+
+use std::io::Read;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ContentHash, Ty,
+    store::{Backend, CacheStats, Error, TypeInfo, Values},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The first byte of a stored value that is stored as it is.
+const UNCOMPRESSED: u8 = 0;
+
+/// The first byte of a stored value that is a zstd frame.
+const ZSTD: u8 = 1;
+
+/// The most bytes a stored value is decompressed to, so that a corrupt or malicious frame can't exhaust memory.
+pub const MAX_DECOMPRESSED_BYTES: u64 = 1 << 30;
+
+/// How values are compressed, as set by [`StoreParams::compression`].
+///
+/// [`StoreParams::compression`]: crate::store::StoreParams::compression
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Compression {
+    /// The zstd compression level, from 1 to 22. Higher levels compress more but more slowly.
+    pub level: i32,
+    /// Values smaller than this many bytes once encoded are stored uncompressed.
+    pub threshold_bytes: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            level: 3,
+            threshold_bytes: 64,
+        }
+    }
+}
+
+impl Compression {
+    pub(crate) const METADATA_KEY: &'static str = "compression";
+}
+
+/// A [`Backend`] that compresses values on their way to another backend and decompresses them on their way back.
+///
+/// Indexes on fields created before compression was enabled are dropped when it is, as backends can't evaluate them on
+/// compressed values.
+#[derive(Debug)]
+pub struct Compressed {
+    backend: Box<dyn Backend>,
+    /// `None` if values are only decompressed, for stores that have compressed values but weren't opened with
+    /// compression.
+    compression: Option<Compression>,
+}
+
+impl Compressed {
+    pub fn new(backend: Box<dyn Backend>, compression: Option<Compression>) -> Self {
+        Self { backend, compression }
+    }
+
+    /// Record that `backend` is compressed, rewriting the values already in it with the flag byte that every value
+    /// stored through a [`Compressed`] backend starts with, and dropping the indexes on them.
+    pub(crate) fn enable(backend: &mut dyn Backend) -> Result<()> {
+        backend.begin_transaction()?;
+        let result = (|| {
+            for ty in backend.list_tys()? {
+                backend.drop_indexes(ty.clone())?;
+                for (content_hash, content) in backend.get_all_of_ty(ty.clone())? {
+                    backend.write(ty.clone(), content_hash, [&[UNCOMPRESSED], content.as_slice()].concat())?;
+                }
+            }
+            backend.set_metadata(Compression::METADATA_KEY, "zstd")
+        })();
+        match result {
+            Ok(()) => backend.commit_transaction(),
+            Err(e) => {
+                backend.rollback_transaction()?;
+                Err(e)
+            }
+        }
+    }
+
+    fn compress(&self, content: Vec<u8>) -> Result<Vec<u8>> {
+        match self.compression {
+            Some(compression) if content.len() >= compression.threshold_bytes => {
+                let mut stored = vec![ZSTD];
+                zstd::stream::copy_encode(content.as_slice(), &mut stored, compression.level)
+                    .map_err(|e| Error::Io(e.to_string()))?;
+                Ok(stored)
+            }
+            _ => Ok([&[UNCOMPRESSED], content.as_slice()].concat()),
+        }
+    }
+
+    fn decompress_all(ty: &Ty, values: Values) -> Result<Values> {
+        values
+            .into_iter()
+            .map(|(content_hash, data)| Ok((content_hash, decompress(ty, content_hash, &data)?)))
+            .collect()
+    }
+}
+
+/// The value stored as `data`, decompressing it if its flag byte says it is a zstd frame.
+fn decompress(ty: &Ty, content_hash: ContentHash, data: &[u8]) -> Result<Vec<u8>> {
+    let corrupt = || Error::Decompression {
+        ty: ty.clone(),
+        content_hash,
+    };
+    match data.split_first() {
+        Some((&UNCOMPRESSED, content)) => Ok(content.to_vec()),
+        Some((&ZSTD, frame)) => {
+            let decoder = zstd::stream::Decoder::new(frame).map_err(|e| Error::Io(e.to_string()))?;
+            let mut decoded = Vec::new();
+            // Read one byte past the limit, to tell a value of exactly the limit from a larger one.
+            decoder
+                .take(MAX_DECOMPRESSED_BYTES + 1)
+                .read_to_end(&mut decoded)
+                .map_err(|_| corrupt())?;
+            if decoded.len() as u64 > MAX_DECOMPRESSED_BYTES {
+                return Err(corrupt());
+            }
+            Ok(decoded)
+        }
+        _ => Err(corrupt()),
+    }
+}
+
+impl Backend for Compressed {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        let stored = self.backend.get_from_hash(ty.clone(), content_hash)?;
+        stored.map(|data| decompress(&ty, content_hash, &data)).transpose()
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        let values = self.backend.get_all_of_ty(ty.clone())?;
+        Self::decompress_all(&ty, values)
+    }
+
+    fn get_page_of_ty(
+        &mut self,
+        ty: Ty,
+        after: Option<ContentHash>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        let values = self.backend.get_page_of_ty(ty.clone(), after, offset, limit)?;
+        Self::decompress_all(&ty, values)
+    }
+
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        let content = self.compress(content)?;
+        self.backend.write(ty, content_hash, content)
+    }
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        self.backend.delete_by_hash(ty, content_hash)
+    }
+
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
+        self.backend.delete_all_of_ty(ty)
+    }
+
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        self.backend.list_tys()
+    }
+
+    /// Sizes are of the values as stored, after compression.
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        self.backend.type_info(ty)
+    }
+
+    fn list_types(&mut self) -> Result<Vec<TypeInfo>> {
+        self.backend.list_types()
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        self.backend.find_by_hash(content_hash)
+    }
+
+    fn storage_name(&self, ty: &Ty) -> String {
+        self.backend.storage_name(ty)
+    }
+
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        self.backend.foreign_tables()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.backend.cache_stats()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.backend.flush()
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        if key == Compression::METADATA_KEY {
+            return Ok(None);
+        }
+        self.backend.get_metadata(key)
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        self.backend.set_metadata(key, value)
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        self.backend.begin_transaction()
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        self.backend.commit_transaction()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.backend.rollback_transaction()
+    }
+}
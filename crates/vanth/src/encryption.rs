@@ -0,0 +1,252 @@
+//! Encryption of stored values at rest.
+//!
+//! With [`StoreParams::encryption`] set, values are encrypted with XChaCha20-Poly1305 before being passed to the
+//! backend. Each value gets a random nonce, and is authenticated together with its type's storage name and its
+//! [`ContentHash`], so a value that is modified or moved to another row fails to decrypt.
+//!
+//! Hashes are stored in the clear so that values can still be looked up by hash. Someone who can read the store but
+//! not the key can therefore tell whether a value they can guess is stored. Metadata is also stored in the clear.
+//!
+//! An encrypted store keeps a key-check record in its metadata, written when it is created, so that opening it with
+//! the wrong key fails with [`Error::WrongEncryptionKey`] rather than on the first read.
+//!
+//! [`StoreParams::encryption`]: crate::store::StoreParams::encryption
+
+use std::fmt;
+
+use chacha20poly1305::{
+    XChaCha20Poly1305, XNonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+};
+
+use crate::{
+    ContentHash, Ty,
+    store::{Backend, CacheStats, Error, TypeInfo, Values},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+const NONCE_LEN: usize = 24;
+
+/// Encrypted and stored as the key-check record.
+const KEY_CHECK: &[u8] = b"vanth key check";
+
+/// A 256-bit key for [`StoreParams::encryption`]. Its [`Debug`] output doesn't include the key.
+///
+/// [`StoreParams::encryption`]: crate::store::StoreParams::encryption
+#[derive(Clone)]
+pub struct EncryptionKey {
+    key: [u8; 32],
+}
+
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// A [`Backend`] that encrypts values on their way to another backend and decrypts them on their way back.
+pub struct Encrypted {
+    backend: Box<dyn Backend>,
+    cipher: XChaCha20Poly1305,
+}
+
+impl fmt::Debug for Encrypted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encrypted")
+            .field("backend", &self.backend)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Encrypted {
+    pub(crate) const METADATA_KEY: &'static str = "encryption_key_check";
+
+    /// Encrypt `backend` with `key`, checking the key against the backend's key-check record. A backend without a
+    /// record gets one, unless it is `read_only`, as long as it has no values yet.
+    pub fn new(mut backend: Box<dyn Backend>, key: &EncryptionKey, read_only: bool) -> Result<Self> {
+        let cipher = XChaCha20Poly1305::new((&key.key).into());
+        match backend.get_metadata(Self::METADATA_KEY)? {
+            Some(record) => {
+                let record = unhex(&record).ok_or(Error::WrongEncryptionKey)?;
+                match open(&cipher, &record, KEY_CHECK) {
+                    Some(check) if check == KEY_CHECK => {}
+                    _ => return Err(Error::WrongEncryptionKey),
+                }
+            }
+            None => {
+                if backend.list_types()?.iter().any(|info| info.count > 0) {
+                    return Err(Error::StoreNotEncrypted);
+                }
+                if !read_only {
+                    let record = seal(&cipher, KEY_CHECK, KEY_CHECK)?;
+                    backend.set_metadata(Self::METADATA_KEY, &hex(&record))?;
+                }
+            }
+        }
+        Ok(Self { backend, cipher })
+    }
+
+    /// Whether `backend` has a key-check record, and so needs a key to be read.
+    pub(crate) fn is_encrypted(backend: &mut dyn Backend) -> Result<bool> {
+        Ok(backend.get_metadata(Self::METADATA_KEY)?.is_some())
+    }
+
+    /// Ties a value to where it is stored.
+    fn associated_data(&self, ty: &Ty, content_hash: ContentHash) -> Vec<u8> {
+        let mut data = self.backend.storage_name(ty).into_bytes();
+        data.push(0);
+        data.extend_from_slice(&content_hash.hash);
+        data
+    }
+
+    fn decrypt(&self, ty: &Ty, content_hash: ContentHash, data: &[u8]) -> Result<Vec<u8>> {
+        open(&self.cipher, data, &self.associated_data(ty, content_hash)).ok_or_else(|| Error::Decryption {
+            ty: ty.clone(),
+            content_hash,
+        })
+    }
+
+    fn decrypt_all(&self, ty: &Ty, values: Values) -> Result<Values> {
+        values
+            .into_iter()
+            .map(|(content_hash, data)| Ok((content_hash, self.decrypt(ty, content_hash, &data)?)))
+            .collect()
+    }
+}
+
+/// A random nonce followed by `message` encrypted and authenticated with `associated_data`.
+fn seal(cipher: &XChaCha20Poly1305, message: &[u8], associated_data: &[u8]) -> Result<Vec<u8>> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: message,
+        aad: associated_data,
+    };
+    let ciphertext = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| Error::Io("failed to encrypt value".into()))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+/// The message sealed by [`seal`], or `None` if it doesn't authenticate.
+fn open(cipher: &XChaCha20Poly1305, sealed: &[u8], associated_data: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: associated_data,
+    };
+    cipher.decrypt(XNonce::from_slice(nonce), payload).ok()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+impl Backend for Encrypted {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        match self.backend.get_from_hash(ty.clone(), content_hash)? {
+            Some(data) => Ok(Some(self.decrypt(&ty, content_hash, &data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Values> {
+        let values = self.backend.get_all_of_ty(ty.clone())?;
+        self.decrypt_all(&ty, values)
+    }
+
+    fn get_page_of_ty(&mut self, ty: Ty, after: Option<ContentHash>, offset: usize, limit: usize) -> Result<Values> {
+        let values = self.backend.get_page_of_ty(ty.clone(), after, offset, limit)?;
+        self.decrypt_all(&ty, values)
+    }
+
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        let sealed = seal(&self.cipher, &content, &self.associated_data(&ty, content_hash))?;
+        self.backend.write(ty, content_hash, sealed)
+    }
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        self.backend.delete_by_hash(ty, content_hash)
+    }
+
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
+        self.backend.delete_all_of_ty(ty)
+    }
+
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        self.backend.list_tys()
+    }
+
+    /// Sizes are of the values as stored, after encryption.
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        self.backend.type_info(ty)
+    }
+
+    fn list_types(&mut self) -> Result<Vec<TypeInfo>> {
+        self.backend.list_types()
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        self.backend.find_by_hash(content_hash)
+    }
+
+    fn storage_name(&self, ty: &Ty) -> String {
+        self.backend.storage_name(ty)
+    }
+
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        self.backend.foreign_tables()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.backend.cache_stats()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.backend.flush()
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        if key == Self::METADATA_KEY {
+            return Ok(None);
+        }
+        self.backend.get_metadata(key)
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        self.backend.set_metadata(key, value)
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        self.backend.begin_transaction()
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        self.backend.commit_transaction()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.backend.rollback_transaction()
+    }
+}
@@ -38,8 +38,8 @@ impl<T: ?Sized> Id<T> {
     pub fn random() -> Self {
         let mut value = [0u8; 32];
         // Simple random generation for demonstration
-        for i in 0..32 {
-            value[i] = (std::time::SystemTime::now()
+        for (i, byte) in value.iter_mut().enumerate() {
+            *byte = (std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_nanos() as u8)
@@ -62,8 +62,8 @@ impl<T: ?Sized> Id<T> {
     /// Create an ID from a u64 (fills only first 8 bytes)
     pub fn from_u64(value: u64) -> Self {
         let mut bytes = [0u8; 32];
-        for i in 0..8 {
-            bytes[i] = ((value >> (i * 8)) & 0xFF) as u8;
+        for (i, byte) in bytes[..8].iter_mut().enumerate() {
+            *byte = ((value >> (i * 8)) & 0xFF) as u8;
         }
         Self::new(bytes)
     }
@@ -92,13 +92,13 @@ impl<T: ?Sized> Id<T> {
         let mut bytes = [0u8; 32];
 
         // Low bits fill first 16 bytes
-        for i in 0..16 {
-            bytes[i] = ((low >> (i * 8)) & 0xFF) as u8;
+        for (i, byte) in bytes[..16].iter_mut().enumerate() {
+            *byte = ((low >> (i * 8)) & 0xFF) as u8;
         }
 
         // High bits fill next 16 bytes
-        for i in 0..16 {
-            bytes[i + 16] = ((high >> (i * 8)) & 0xFF) as u8;
+        for (i, byte) in bytes[16..].iter_mut().enumerate() {
+            *byte = ((high >> (i * 8)) & 0xFF) as u8;
         }
 
         Self::new(bytes)
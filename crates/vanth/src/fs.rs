@@ -0,0 +1,455 @@
+//! A backend storing each value in its own file.
+//!
+//! A [`Filesystem`] store shards values by type and then by the first byte of their hash, so no directory grows too
+//! large. Each file is written atomically, but committing a transaction isn't: a crash part way through a commit can
+//! leave some of its changes applied.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    ContentHash, Ty,
+    store::{Backend, Error, Staged, TypeInfo, merge_staged, unix_millis},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Stores each value in its own file, in a directory tree laid out like git's object store:
+///
+/// ```text
+/// <root>/types/<type>/<first 2 hex digits of hash>/<remaining 62 hex digits>
+/// <root>/metadata/<key>
+/// <root>/tmp/
+/// ```
+///
+/// Type directory names are the type's name with any characters other than ASCII letters, digits, `_`, `-` and `.`
+/// percent-encoded, as is a leading `.` so that no type maps to `.` or `..`. Files are written to `tmp`, synced and then
+/// renamed into place, so readers never see a partially written value.
+///
+/// Transactions are staged in memory and applied when committed. A crash part way through a commit can leave some of
+/// its changes applied.
+#[derive(Debug)]
+pub struct Filesystem {
+    root: PathBuf,
+    read_only: bool,
+    staged: Option<Staged>,
+}
+
+/// Distinguishes temporary files written by the same process.
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+impl Filesystem {
+    const TYPES_DIR: &'static str = "types";
+    const METADATA_DIR: &'static str = "metadata";
+    const TEMP_DIR: &'static str = "tmp";
+
+    /// Use the directory at `root`, creating it if it doesn't exist and `create_if_not_exists` is set.
+    pub fn new(root: PathBuf, create_if_not_exists: bool, read_only: bool) -> Result<Self> {
+        if !root.is_dir() && !(create_if_not_exists && !read_only) {
+            return Err(Error::Io(format!("store directory {} does not exist", root.display())));
+        }
+        if !read_only {
+            for dir in [Self::TYPES_DIR, Self::METADATA_DIR, Self::TEMP_DIR] {
+                fs::create_dir_all(root.join(dir))?;
+            }
+        }
+        Ok(Self {
+            root,
+            read_only,
+            staged: None,
+        })
+    }
+
+    fn ty_dir(&self, ty: &Ty) -> PathBuf {
+        self.root.join(Self::TYPES_DIR).join(encode_ty(ty))
+    }
+
+    fn value_path(&self, ty: &Ty, content_hash: ContentHash) -> PathBuf {
+        let hex = content_hash.hex();
+        self.ty_dir(ty).join(&hex[..2]).join(&hex[2..])
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Write `content` to `path` by writing it to a temporary file, syncing it and renaming that into place. The
+    /// directory holding `path` is synced afterwards so the rename survives a crash.
+    fn write_atomic(&self, path: &Path, content: &[u8]) -> Result<()> {
+        let parent = path.parent();
+        if let Some(parent) = parent {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = self.temp_path();
+        let written = fs::File::create(&temp_path).and_then(|mut file| {
+            file.write_all(content)?;
+            file.sync_all()
+        });
+        if let Err(e) = written.and_then(|_| fs::rename(&temp_path, path)) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.into());
+        }
+        // Directories can only be opened and synced like this on Unix.
+        #[cfg(unix)]
+        if let Some(parent) = parent {
+            fs::File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        self.root
+            .join(Self::TEMP_DIR)
+            .join(format!("{}-{}", std::process::id(), counter))
+    }
+
+    fn read_committed(&self, ty: &Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.value_path(ty, content_hash)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Lazily list the committed hashes of `ty` greater than `after`, in order, reading one fan-out directory at a
+    /// time.
+    fn committed_hashes(
+        &self,
+        ty: &Ty,
+        after: Option<ContentHash>,
+    ) -> Result<impl Iterator<Item = Result<ContentHash>> + use<>> {
+        let dir = self.ty_dir(ty);
+        let start = after.map(|content_hash| format!("{:02x}", content_hash.hash[0]));
+        let mut shards = sorted_entries(&dir)?;
+        shards.retain(|shard| shard.len() == 2 && start.as_ref().is_none_or(|start| shard >= start));
+
+        let mut shards = shards.into_iter();
+        let mut current = Vec::new().into_iter();
+        Ok(std::iter::from_fn(move || {
+            loop {
+                if let Some(content_hash) = current.next() {
+                    return Some(Ok(content_hash));
+                }
+                let shard = shards.next()?;
+                match sorted_entries(&dir.join(&shard)) {
+                    Ok(names) => {
+                        current = names
+                            .into_iter()
+                            .filter_map(|name| ContentHash::from_hex(&format!("{}{}", shard, name)))
+                            .filter(|content_hash| after.is_none_or(|after| *content_hash > after))
+                            .collect::<Vec<_>>()
+                            .into_iter();
+                    }
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        }))
+    }
+
+    fn delete_committed_ty(&self, ty: &Ty) -> Result<()> {
+        // Move the directory out of the way first so the type disappears at once rather than file by file.
+        let temp_path = self.temp_path();
+        match fs::rename(self.ty_dir(ty), &temp_path) {
+            Ok(()) => Ok(fs::remove_dir_all(temp_path)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete_committed(&self, ty: &Ty, content_hash: ContentHash) -> Result<()> {
+        match fs::remove_file(self.value_path(ty, content_hash)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Names of the entries in `dir` in sorted order. A missing directory has no entries.
+fn sorted_entries(dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        if let Ok(name) = entry?.file_name().into_string() {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn encode_ty(ty: &Ty) -> String {
+    let mut encoded = String::new();
+    for (i, byte) in ty.to_string().bytes().enumerate() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'-') || (byte == b'.' && i > 0) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn decode_ty(encoded: &str) -> Option<Ty> {
+    let mut bytes = Vec::new();
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    Some(Ty::from(String::from_utf8(bytes).ok()?.as_str()))
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err.to_string())
+    }
+}
+
+impl Backend for Filesystem {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        if let Some(change) = self.staged.as_ref().and_then(|staged| staged.get(&ty, content_hash)) {
+            return Ok(change.cloned());
+        }
+        self.read_committed(&ty, content_hash)
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        self.get_page_of_ty(ty, None, 0, usize::MAX)
+    }
+
+    fn get_page_of_ty(
+        &mut self,
+        ty: Ty,
+        after: Option<ContentHash>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        let cleared = self.staged.as_ref().is_some_and(|staged| staged.cleared.contains(&ty));
+        let committed = if cleared {
+            None
+        } else {
+            Some(self.committed_hashes(&ty, after)?)
+        };
+        // Committed values are `None` until they are read, so that skipped values are never read.
+        let committed = committed
+            .into_iter()
+            .flatten()
+            .map(|item| item.map(|content_hash| (content_hash, None)));
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let changes = self
+            .staged
+            .as_ref()
+            .into_iter()
+            .flat_map(|staged| staged.changes_in(&ty, (start, Bound::Unbounded)))
+            .map(|(content_hash, change)| (content_hash, change.map(|content| Some(content.clone()))));
+
+        merge_staged(committed, changes)
+            .skip(offset)
+            .take(limit)
+            .filter_map(|item| match item {
+                Ok((content_hash, Some(content))) => Some(Ok((content_hash, content))),
+                // Skip values deleted since they were listed.
+                Ok((content_hash, None)) => self
+                    .read_committed(&ty, content_hash)
+                    .transpose()
+                    .map(|content| content.map(|content| (content_hash, content))),
+                Err(e) => Some(Err(e)),
+            })
+            .collect()
+    }
+
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        self.check_writable()?;
+        match &mut self.staged {
+            Some(staged) => {
+                staged.write(ty, content_hash, content);
+                Ok(())
+            }
+            None => self.write_atomic(&self.value_path(&ty, content_hash), &content),
+        }
+    }
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        self.check_writable()?;
+        match &mut self.staged {
+            Some(staged) => {
+                staged.delete(ty, content_hash);
+                Ok(())
+            }
+            None => self.delete_committed(&ty, content_hash),
+        }
+    }
+
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
+        self.check_writable()?;
+        match &mut self.staged {
+            Some(staged) => {
+                staged.delete_all(ty);
+                Ok(())
+            }
+            None => self.delete_committed_ty(&ty),
+        }
+    }
+
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        let mut tys: Vec<Ty> = sorted_entries(&self.root.join(Self::TYPES_DIR))?
+            .iter()
+            .filter_map(|name| decode_ty(name))
+            .collect();
+        if let Some(staged) = &self.staged {
+            tys.retain(|ty| !staged.cleared.contains(ty));
+            for ty in staged.changes.keys() {
+                if !tys.contains(ty) {
+                    tys.push(ty.clone());
+                }
+            }
+        }
+        Ok(tys)
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        // Values are laid out by hash, so this only needs to check one path per type.
+        let mut tys = self.list_tys()?;
+        tys.retain(
+            |ty| match self.staged.as_ref().and_then(|staged| staged.get(ty, content_hash)) {
+                Some(change) => change.is_some(),
+                None => self.value_path(ty, content_hash).is_file(),
+            },
+        );
+        Ok(tys)
+    }
+
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        let cleared = self.staged.as_ref().is_some_and(|staged| staged.cleared.contains(&ty));
+        let changes = self.staged.as_ref().and_then(|staged| staged.changes.get(&ty));
+        let committed = !cleared && self.ty_dir(&ty).is_dir();
+        if !committed && changes.is_none() {
+            return Ok(None);
+        }
+
+        // Write times are the modification times of the committed files, so values that have since been deleted or
+        // are only staged don't count.
+        let mut sizes = BTreeMap::new();
+        let (mut first_write, mut last_write) = (None, None);
+        if committed {
+            for content_hash in self.committed_hashes(&ty, None)? {
+                let content_hash = content_hash?;
+                let metadata = match fs::metadata(self.value_path(&ty, content_hash)) {
+                    Ok(metadata) => metadata,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                sizes.insert(content_hash, metadata.len());
+                let modified = unix_millis(metadata.modified()?);
+                first_write = Some(first_write.map_or(modified, |time: u64| time.min(modified)));
+                last_write = Some(last_write.map_or(modified, |time: u64| time.max(modified)));
+            }
+        }
+        for (content_hash, change) in changes.into_iter().flatten() {
+            match change {
+                Some(content) => sizes.insert(*content_hash, content.len() as u64),
+                None => sizes.remove(content_hash),
+            };
+        }
+        Ok(Some(TypeInfo {
+            ty,
+            count: sizes.len() as u64,
+            total_bytes: sizes.values().sum(),
+            first_write,
+            last_write,
+        }))
+    }
+
+    fn storage_name(&self, ty: &Ty) -> String {
+        // The filesystem may be case-insensitive.
+        encode_ty(ty).to_ascii_lowercase()
+    }
+
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        let mut foreign: Vec<String> = sorted_entries(&self.root)?
+            .into_iter()
+            .filter(|name| ![Self::TYPES_DIR, Self::METADATA_DIR, Self::TEMP_DIR].contains(&name.as_str()))
+            .collect();
+        foreign.extend(
+            sorted_entries(&self.root.join(Self::TYPES_DIR))?
+                .into_iter()
+                .filter(|name| decode_ty(name).is_none())
+                .map(|name| format!("{}/{}", Self::TYPES_DIR, name)),
+        );
+        Ok(foreign)
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        if let Some(value) = self.staged.as_ref().and_then(|staged| staged.metadata.get(key)) {
+            return Ok(Some(value.clone()));
+        }
+        match fs::read_to_string(self.root.join(Self::METADATA_DIR).join(key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        self.check_writable()?;
+        if let Some(staged) = &mut self.staged {
+            staged.metadata.insert(key.into(), value.into());
+            return Ok(());
+        }
+        self.write_atomic(&self.root.join(Self::METADATA_DIR).join(key), value.as_bytes())
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        if self.staged.is_some() {
+            return Err(Error::TransactionAlreadyOpen);
+        }
+        self.staged = Some(Staged::default());
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        let staged = self.staged.take().ok_or(Error::NoTransactionOpen)?;
+        for ty in &staged.cleared {
+            self.delete_committed_ty(ty)?;
+        }
+        for (ty, changes) in &staged.changes {
+            for (content_hash, change) in changes {
+                match change {
+                    Some(content) => self.write_atomic(&self.value_path(ty, *content_hash), content)?,
+                    None => self.delete_committed(ty, *content_hash)?,
+                }
+            }
+        }
+        // Written last, so that metadata recording the changes, such as applied migrations, is only written once they
+        // have been.
+        for (key, value) in &staged.metadata {
+            self.write_atomic(&self.root.join(Self::METADATA_DIR).join(key), value.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.staged.take().ok_or(Error::NoTransactionOpen)?;
+        Ok(())
+    }
+}
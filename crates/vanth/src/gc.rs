@@ -0,0 +1,215 @@
+//! Reachability-based garbage collection.
+//!
+//! Values are kept if they can be reached from a root by following the references exposed through the [`References`]
+//! trait. Everything else is deleted by [`Store::collect_garbage`].
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    ContentHash, Ty, Vanth,
+    migration::HashMapping,
+    store::{Encoding, Error, IterParams, Store},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Implemented by types whose values refer to other stored values, so that the values they refer to are kept alive.
+pub trait References {
+    /// The type and hash of every value this value refers to.
+    fn references(&self) -> Vec<(Ty, ContentHash)>;
+}
+
+/// The types [`Store::collect_garbage`] knows how to find references in. Values of other types are treated as having no
+/// references.
+#[derive(Clone, Debug, Default)]
+pub struct Collector {
+    /// Keyed by the string form of the type, as with [`crate::store::Verifier`].
+    tracers: HashMap<String, Trace>,
+}
+
+type Trace = fn(Encoding, &[u8]) -> std::result::Result<Vec<(Ty, ContentHash)>, String>;
+
+impl Collector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<T: Vanth + DeserializeOwned + References>(mut self) -> Self {
+        self.register::<T>();
+        self
+    }
+
+    pub fn register<T: Vanth + DeserializeOwned + References>(&mut self) {
+        fn trace<T: DeserializeOwned + References>(
+            encoding: Encoding,
+            data: &[u8],
+        ) -> std::result::Result<Vec<(Ty, ContentHash)>, String> {
+            encoding
+                .decode::<T>(data)
+                .map(|value| value.references())
+                .map_err(|e| format!("{:?}", e))
+        }
+        self.tracers.insert(T::ty().to_string(), trace::<T>);
+    }
+}
+
+/// A root that is stored in the database, so it is used by every garbage collection until it is removed.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct NamedRoot {
+    pub name: String,
+    pub ty: Ty,
+    pub content_hash: ContentHash,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct GcParams {
+    /// Roots used for this collection only, in addition to the [`NamedRoot`]s.
+    pub roots: Vec<(Ty, ContentHash)>,
+    /// Report what would be deleted without deleting anything.
+    pub dry_run: bool,
+}
+
+/// The result of [`Store::collect_garbage`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct GcReport {
+    /// Number of values reachable from the roots.
+    pub reachable: usize,
+    /// Values that were deleted, or would be deleted if this was a dry run.
+    pub unreachable: Vec<(Ty, ContentHash)>,
+    /// Total size of the unreachable values as stored.
+    pub freed_bytes: u64,
+    /// Roots and references pointing to values that are not in the store.
+    pub missing: Vec<(Ty, ContentHash)>,
+    pub dry_run: bool,
+}
+
+/// The result of [`Store::reachable`].
+#[derive(Debug, Default)]
+pub(crate) struct Reachable {
+    /// Reachable values that are in the store.
+    pub(crate) found: Vec<(Ty, ContentHash)>,
+    /// Roots and references pointing to values that are not in the store.
+    pub(crate) missing: Vec<(Ty, ContentHash)>,
+}
+
+impl Store {
+    /// The metadata key the list of [`NamedRoot`]s is stored under.
+    const ROOTS_METADATA_KEY: &'static str = "gc_roots";
+
+    pub fn roots(&mut self) -> Result<Vec<NamedRoot>> {
+        match self.backend.get_metadata(Self::ROOTS_METADATA_KEY)? {
+            Some(roots) => Ok(serde_json::from_str(&roots)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Add a [`NamedRoot`], replacing any existing root with the same name.
+    pub fn set_root(&mut self, name: impl Into<String>, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        let name = name.into();
+        let mut roots = self.roots()?;
+        roots.retain(|root| root.name != name);
+        roots.push(NamedRoot { name, ty, content_hash });
+        self.write_roots(&roots)
+    }
+
+    /// Remove the [`NamedRoot`] with the given name. Returns whether it existed.
+    pub fn remove_root(&mut self, name: &str) -> Result<bool> {
+        let mut roots = self.roots()?;
+        let count = roots.len();
+        roots.retain(|root| root.name != name);
+        if roots.len() == count {
+            return Ok(false);
+        }
+        self.write_roots(&roots)?;
+        Ok(true)
+    }
+
+    fn write_roots(&mut self, roots: &[NamedRoot]) -> Result<()> {
+        self.backend
+            .set_metadata(Self::ROOTS_METADATA_KEY, &serde_json::to_string(roots)?)
+    }
+
+    /// The values reachable from `roots` by following the references of types registered with `collector`.
+    ///
+    /// Fails if a reachable value of a registered type cannot be deserialized.
+    pub(crate) fn reachable(&mut self, collector: &Collector, roots: Vec<(Ty, ContentHash)>) -> Result<Reachable> {
+        let mut reachable = Reachable::default();
+        let mut seen: HashSet<(String, ContentHash)> = HashSet::new();
+        let mut pending = roots;
+        while let Some((ty, content_hash)) = pending.pop() {
+            if !seen.insert((ty.to_string(), content_hash)) {
+                continue;
+            }
+            let Some(data) = self.backend.get_from_hash(ty.clone(), content_hash)? else {
+                reachable.missing.push((ty, content_hash));
+                continue;
+            };
+            if let Some(trace) = collector.tracers.get(&ty.to_string()) {
+                let references = trace(self.encoding, &data).map_err(|message| Error::Deserialization {
+                    ty: ty.clone(),
+                    content_hash,
+                    message,
+                })?;
+                pending.extend(references);
+            }
+            reachable.found.push((ty, content_hash));
+        }
+        Ok(reachable)
+    }
+
+    /// Delete every value that is not reachable from the [`NamedRoot`]s, [`GcParams::roots`] or pinned values. Values
+    /// the store keeps for its own bookkeeping, such as [`HashMapping`]s, are never deleted.
+    ///
+    /// Fails without deleting anything if a reachable value of a type registered with `collector` cannot be
+    /// deserialized, since the values it refers to are unknown.
+    pub fn collect_garbage(&mut self, collector: &Collector, params: &GcParams) -> Result<GcReport> {
+        let roots = self
+            .roots()?
+            .into_iter()
+            .map(|root| (root.ty, root.content_hash))
+            .chain(params.roots.iter().cloned())
+            .chain(self.pins()?.into_iter().map(|pin| (pin.ty, pin.content_hash)))
+            .collect();
+        let traced = self.reachable(collector, roots)?;
+        let mut report = GcReport {
+            reachable: traced.found.len(),
+            missing: traced.missing,
+            dry_run: params.dry_run,
+            ..Default::default()
+        };
+        // Types are compared by their string form, since types read back from a backend may be split into path segments
+        // differently.
+        let reachable: HashSet<(String, ContentHash)> = traced
+            .found
+            .into_iter()
+            .map(|(ty, content_hash)| (ty.to_string(), content_hash))
+            .collect();
+
+        let bookkeeping = HashMapping::ty().to_string();
+        for ty in self.backend.list_tys()? {
+            let ty_name = ty.to_string();
+            if ty_name == bookkeeping {
+                continue;
+            }
+            for item in self.iter_of_type_raw(ty.clone(), IterParams::default()) {
+                let (content_hash, data) = item?;
+                if !reachable.contains(&(ty_name.clone(), content_hash)) {
+                    report.unreachable.push((ty.clone(), content_hash));
+                    report.freed_bytes += data.len() as u64;
+                }
+            }
+        }
+
+        if !params.dry_run {
+            self.transaction(|tx| {
+                for (ty, content_hash) in &report.unreachable {
+                    tx.delete_raw(ty.clone(), *content_hash)?;
+                }
+                Ok::<_, Error>(())
+            })?;
+        }
+        Ok(report)
+    }
+}
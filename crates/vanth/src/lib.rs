@@ -3,11 +3,29 @@ use std::marker::PhantomData;
 use bevy_ecs::{prelude::*, query::QueryData};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
-use crate::entity::EntityId;
-
+use crate::{
+    async_store::{AsyncStore, Task},
+    entity::EntityId,
+    store::Encoding,
+};
+
+pub mod archive;
+pub mod async_store;
+pub mod changes;
+pub mod chunking;
+pub mod compression;
+pub mod encryption;
 pub mod entity;
+pub mod fs;
+pub mod gc;
 pub mod hashing_serializer;
+pub mod migration;
 pub mod nix;
+pub mod overlay;
+pub mod pins;
+pub mod query;
+pub mod shared_store;
+pub mod snapshot;
 pub mod store;
 
 pub use hashing_serializer::hash;
@@ -19,13 +37,13 @@ pub enum Error {
     Other(String),
 }
 
-
 /// A view of all of the [`Node`]s in a cluster.
 pub struct Network {
     // TODO
 }
 
 /// A Vanth server.
+#[derive(Default)]
 pub struct Node {
     // TODO
 }
@@ -77,20 +95,23 @@ pub struct Value {
 
 /// A wrapper for the fully-qualified name of a Rust type. This should be univerisally unique for a given type within a
 /// given project.
-#[derive(Clone, Debug, Deserialize, Serialize, Eq, Hash)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct Ty {
     pub path: Vec<String>,
 }
 
-impl ToString for Ty {
-    fn to_string(&self) -> String {
-        self.path.join("::")
+impl std::fmt::Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.join("::"))
     }
 }
 
-impl PartialEq for Ty {
-    fn eq(&self, other: &Self) -> bool {
-        self.path == other.path
+impl From<&str> for Ty {
+    /// Parse a fully-qualified type name such as `my::module::Type`.
+    fn from(name: &str) -> Self {
+        Self {
+            path: name.split("::").map(Into::into).collect(),
+        }
     }
 }
 
@@ -121,19 +142,40 @@ pub trait VanthTuple {}
 //     components: Vec<ComponentContents>
 // }
 
+/// The serialized contents of a stored value of type `T`.
 #[derive(Clone, Debug)]
 pub struct ComponentContents<T: Vanth> {
     content_hash: ContentHash,
     data: Vec<u8>,
+    encoding: store::Encoding,
     _marker: PhantomData<T>,
 }
 
+impl<T: Vanth> ComponentContents<T> {
+    pub fn content_hash(&self) -> ContentHash {
+        self.content_hash
+    }
+
+    /// The value as it is serialized in the store.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn encoding(&self) -> store::Encoding {
+        self.encoding
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
 // use a macro to implement VanthTuiple here.
 
 /// A 32 byte BLAKE3 hash representing the contents of some value.
 ///
 /// This can be generated with the [`hash`] function.
-#[derive(Copy, Clone, Debug, Deserialize, Component, Serialize, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, Deserialize, Component, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ContentHash {
     pub hash: [u8; 32],
 }
@@ -142,10 +184,24 @@ impl ContentHash {
     pub fn hex(&self) -> String {
         self.hash.iter().map(|b| format!("{:02x}", b)).collect::<String>()
     }
+
+    /// Parse a hash from 64 hexadecimal characters, as produced by [`ContentHash::hex`].
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 64 || !hex.is_ascii() {
+            return None;
+        }
+        let mut hash = [0u8; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(Self { hash })
+    }
 }
 
+/// A value referred to by its [`ContentHash`], retrieved from an [`AsyncStore`] when it is first needed.
 #[derive(Clone, Debug, Deserialize, Component, Serialize)]
 pub struct Reference<T: Clone + Serialize> {
+    content_hash: ContentHash,
     value: ReferenceValue,
     _marker: PhantomData<T>,
 }
@@ -154,27 +210,111 @@ pub struct Reference<T: Clone + Serialize> {
 pub enum ReferenceValue {
     Absent,
     Retrieving(ReferenceRetrievalTask),
-    Present(Vec<u8>),
+    /// The stored value, encoded with `encoding`.
+    Present {
+        encoding: Encoding,
+        data: Vec<u8>,
+    },
 }
 
-impl<T: Clone + Serialize> Reference<T> {
-    pub async fn take() -> T {
-        todo!()
+impl<T: Vanth + Clone + Serialize + DeserializeOwned + 'static> Reference<T> {
+    /// A reference to the value of `T` with hash `content_hash`, which has not been retrieved yet.
+    pub fn new(content_hash: ContentHash) -> Self {
+        Self {
+            content_hash,
+            value: ReferenceValue::Absent,
+            _marker: PhantomData,
+        }
     }
 
-    pub async fn get() -> Handle<T> {
-        todo!()
+    pub fn content_hash(&self) -> ContentHash {
+        self.content_hash
+    }
+
+    pub fn value(&self) -> &ReferenceValue {
+        &self.value
+    }
+
+    /// Start retrieving the value from `store` in the background, unless it is already present or being retrieved.
+    pub fn retrieve(&mut self, store: &AsyncStore) {
+        let start = match &self.value {
+            ReferenceValue::Absent => true,
+            ReferenceValue::Retrieving(task) => task.is_detached(),
+            ReferenceValue::Present { .. } => false,
+        };
+        if !start {
+            return;
+        }
+        let content_hash = self.content_hash;
+        let task = store.run(move |store| {
+            let Some(value) = store.get_from_hash::<T>(content_hash)? else {
+                return Ok(None);
+            };
+            Ok(Some((store.encoding(), store.encoding().encode(&value)?)))
+        });
+        self.value = ReferenceValue::Retrieving(ReferenceRetrievalTask { task: Some(task) });
+    }
+
+    /// Get the value, retrieving it from `store` unless it is already present. Returns `None` if the store has no value
+    /// of `T` with the reference's hash.
+    pub async fn get(&mut self, store: &AsyncStore) -> std::result::Result<Option<T>, store::Error> {
+        self.retrieve(store);
+        if let ReferenceValue::Retrieving(task) = &mut self.value {
+            match task.await {
+                Ok(Some((encoding, data))) => self.value = ReferenceValue::Present { encoding, data },
+                // Missing values and failed retrievals are retrieved again by the next call.
+                result => {
+                    self.value = ReferenceValue::Absent;
+                    result?;
+                }
+            }
+        }
+        match &self.value {
+            ReferenceValue::Present { encoding, data } => Ok(Some(store::decode(*encoding, self.content_hash, data)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Like [`Reference::get`], but consumes the reference.
+    pub async fn take(mut self, store: &AsyncStore) -> std::result::Result<Option<T>, store::Error> {
+        self.get(store).await
     }
 }
 
-#[derive(Component, Clone, Debug, Deserialize, Serialize)]
-pub struct ReferenceRetrievalTask {}
+/// The encoding and data of a retrieved value, if it was found.
+type Retrieved = Option<(Encoding, Vec<u8>)>;
+
+/// The retrieval of a [`Reference`]'s value from an [`AsyncStore`], resolving to the value's encoding and data if the
+/// store has it.
+///
+/// A retrieval belongs to the [`AsyncStore`] that started it, so a cloned or deserialized task is detached and resolves
+/// to [`store::Error::WorkerStopped`]. [`Reference::retrieve`] starts detached retrievals again.
+#[derive(Component, Debug, Default, Deserialize, Serialize)]
+pub struct ReferenceRetrievalTask {
+    #[serde(skip)]
+    task: Option<Task<Retrieved>>,
+}
+
+impl ReferenceRetrievalTask {
+    pub fn is_detached(&self) -> bool {
+        self.task.is_none()
+    }
+}
+
+impl Clone for ReferenceRetrievalTask {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
 
 impl Future for ReferenceRetrievalTask {
-    type Output = Vec<u8>;
+    type Output = std::result::Result<Retrieved, store::Error>;
 
-    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
-        todo!()
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
+        match &mut self.task {
+            Some(task) => std::pin::Pin::new(task).poll(cx),
+            None => std::task::Poll::Ready(Err(store::Error::WorkerStopped)),
+        }
     }
 }
 
@@ -0,0 +1,196 @@
+//! Migrations for types that have been renamed or restructured.
+//!
+//! Renaming or moving a type changes its [`Ty`], so its values are no longer found under the new type. A [`Migrations`]
+//! registry declares how to move values from the old type to the new one, and [`Store::migrate`] applies each migration
+//! that has not been applied to the store yet.
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    ContentHash, Ty, Vanth, hash,
+    store::{Encoding, Error, IterParams, Store},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+type Transform = Box<dyn Fn(Encoding, &[u8]) -> Result<(ContentHash, Vec<u8>)>>;
+
+/// A single step moving every value of one type to another.
+pub struct Migration {
+    /// Identifies the migration in the store once it has been applied, so it must never be reused.
+    pub name: String,
+    pub from: Ty,
+    pub to: Ty,
+    /// Converts an encoded value of `from` to its new hash and encoded value of `to`. `None` for a rename, which leaves
+    /// values unchanged.
+    transform: Option<Transform>,
+}
+
+impl std::fmt::Debug for Migration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Migration")
+            .field("name", &self.name)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .field("transforms", &self.transform.is_some())
+            .finish()
+    }
+}
+
+/// An ordered list of [`Migration`]s.
+#[derive(Debug, Default)]
+pub struct Migrations {
+    migrations: Vec<Migration>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the values of `from` to `to` without changing them. Their hashes stay the same.
+    pub fn rename(mut self, name: impl Into<String>, from: Ty, to: Ty) -> Self {
+        self.migrations.push(Migration {
+            name: name.into(),
+            from,
+            to,
+            transform: None,
+        });
+        self
+    }
+
+    /// Replace each value of `from`, deserialized as `Old`, with the result of `f`. The new values are re-hashed and
+    /// the mapping from old to new hashes is recorded as [`HashMapping`]s.
+    ///
+    /// `from` may be the same as `New::ty()` to restructure a type in place.
+    pub fn transform<Old: DeserializeOwned, New: Vanth + Serialize>(
+        mut self,
+        name: impl Into<String>,
+        from: Ty,
+        f: impl Fn(Old) -> New + 'static,
+    ) -> Self {
+        self.migrations.push(Migration {
+            name: name.into(),
+            from,
+            to: New::ty(),
+            transform: Some(Box::new(move |encoding, data| {
+                let new = f(encoding.decode(data)?);
+                Ok((hash(&new), encoding.encode(&new)?))
+            })),
+        });
+        self
+    }
+}
+
+/// Records that a migration replaced a value with a new one with a different hash.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct HashMapping {
+    pub migration: String,
+    pub from: Ty,
+    pub old: ContentHash,
+    pub to: Ty,
+    pub new: ContentHash,
+}
+
+impl Vanth for HashMapping {
+    fn ty() -> Ty {
+        let mut path: Vec<String> = module_path!().split("::").map(Into::into).collect();
+        path.push("HashMapping".into());
+        Ty { path }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MigrationReport {
+    /// Names of the migrations applied, in order.
+    pub applied: Vec<String>,
+    /// Number of values moved.
+    pub values: usize,
+}
+
+impl Store {
+    /// The metadata key the names of applied migrations are stored under.
+    const MIGRATIONS_METADATA_KEY: &'static str = "migrations";
+
+    /// Names of the migrations that have been applied to this store, in order.
+    pub fn applied_migrations(&mut self) -> Result<Vec<String>> {
+        parse_applied(self.backend.get_metadata(Self::MIGRATIONS_METADATA_KEY)?)
+    }
+
+    /// The hash mappings recorded by the migration with the given name.
+    pub fn hash_mappings(&mut self, migration: &str) -> Result<Vec<HashMapping>> {
+        Ok(self
+            .get_all_of_type::<HashMapping>()?
+            .into_iter()
+            .map(|(_, mapping)| mapping)
+            .filter(|mapping| mapping.migration == migration)
+            .collect())
+    }
+
+    /// Apply each migration in `migrations` that has not already been applied to this store. Each migration is applied
+    /// in its own transaction, which also records that it has been applied.
+    pub fn migrate(&mut self, migrations: &Migrations) -> Result<MigrationReport> {
+        let mut report = MigrationReport::default();
+        for migration in &migrations.migrations {
+            if let Some(values) = self.apply_migration(migration)? {
+                report.values += values;
+                report.applied.push(migration.name.clone());
+            }
+        }
+        Ok(report)
+    }
+
+    /// Apply `migration` unless it has already been applied, returning the number of values it moved.
+    fn apply_migration(&mut self, migration: &Migration) -> Result<Option<usize>> {
+        let encoding = self.encoding;
+        self.transaction(|tx| {
+            // Checked in the transaction, so that another store sharing the backend can't apply it at the same time.
+            let mut applied = parse_applied(tx.get_metadata(Self::MIGRATIONS_METADATA_KEY)?)?;
+            if applied.contains(&migration.name) {
+                return Ok(None);
+            }
+
+            // Collect the hashes up front, since when migrating a type in place the new values are written to the same
+            // type being iterated over.
+            let hashes = tx
+                .iter_of_type_raw(migration.from.clone(), IterParams::default())
+                .map(|item| item.map(|(content_hash, _)| content_hash))
+                .collect::<Result<Vec<_>>>()?;
+
+            for &old in &hashes {
+                let Some(data) = tx.get_from_hash_raw(migration.from.clone(), old)? else {
+                    continue;
+                };
+                let (new, data) = match &migration.transform {
+                    Some(transform) => transform(encoding, &data)?,
+                    None => (old, data),
+                };
+                let unchanged = migration.from == migration.to && old == new;
+                if !unchanged {
+                    tx.delete_raw(migration.from.clone(), old)?;
+                }
+                tx.write_raw(migration.to.clone(), new, data)?;
+                if old != new {
+                    tx.write(&HashMapping {
+                        migration: migration.name.clone(),
+                        from: migration.from.clone(),
+                        old,
+                        to: migration.to.clone(),
+                        new,
+                    })?;
+                }
+            }
+
+            applied.push(migration.name.clone());
+            tx.set_metadata(Self::MIGRATIONS_METADATA_KEY, &serde_json::to_string(&applied)?)?;
+            Ok(Some(hashes.len()))
+        })
+    }
+}
+
+fn parse_applied(names: Option<String>) -> Result<Vec<String>> {
+    match names {
+        Some(names) => Ok(serde_json::from_str(&names)?),
+        None => Ok(Vec::new()),
+    }
+}
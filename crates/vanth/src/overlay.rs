@@ -0,0 +1,347 @@
+//! A writable layer over a read-only backend.
+//!
+//! An [`Overlay`] reads from its upper layer first and falls through to its base, while every change goes to the upper
+//! layer. Deleting a value that is in the base records a whiteout in the upper layer, which hides the base's value until
+//! it is written again. [`Store::flush`] applies the upper layer to the base and empties it.
+//!
+//! Metadata set through the overlay, such as the store's encoding, applied migrations and garbage collection roots, is
+//! also recorded in the upper layer, and flushed to the base along with the values it describes.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{
+    ContentHash, Ty,
+    store::{Backend, Encoding, Error, Store, StoreParams, TypeInfo, or_missing},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The first segment of the types whiteouts are stored under in the upper layer, followed by the type they hide values
+/// of.
+const WHITEOUT_PREFIX: &str = "vanth_whiteout";
+
+/// The type metadata set through the overlay is recorded under in the upper layer, as a JSON `(key, value)` pair stored
+/// under the hash of the key.
+const METADATA_TY: &str = "vanth_overlay_metadata";
+
+/// A [`Backend`] that writes to `upper` and reads from `upper` and then `base`. `base` is only written to by
+/// [`Backend::flush`].
+#[derive(Debug)]
+pub struct Overlay {
+    base: Box<dyn Backend>,
+    upper: Box<dyn Backend>,
+}
+
+impl Overlay {
+    pub fn new(base: Box<dyn Backend>, upper: Box<dyn Backend>) -> Self {
+        Self { base, upper }
+    }
+
+    /// Whether the base has a value of `ty` with `content_hash` that has not been deleted.
+    fn in_base(&mut self, ty: &Ty, content_hash: ContentHash) -> Result<bool> {
+        Ok(!self.is_whited_out(ty, content_hash)?
+            && or_missing(self.base.get_from_hash(ty.clone(), content_hash), None)?.is_some())
+    }
+
+    fn is_whited_out(&mut self, ty: &Ty, content_hash: ContentHash) -> Result<bool> {
+        Ok(or_missing(self.upper.get_from_hash(whiteout_ty(ty), content_hash), None)?.is_some())
+    }
+
+    /// Whether the base has values of `ty` and all of them have been deleted.
+    fn all_whited_out(&mut self, ty: &Ty) -> Result<bool> {
+        let whiteouts = self.whiteouts(ty)?;
+        Ok(!whiteouts.is_empty() && self.base_page(ty, None, 1, &whiteouts)?.is_empty())
+    }
+
+    fn whiteouts(&mut self, ty: &Ty) -> Result<HashSet<ContentHash>> {
+        let whiteouts = or_missing(self.upper.get_all_of_ty(whiteout_ty(ty)), Vec::new())?;
+        Ok(whiteouts.into_iter().map(|(content_hash, _)| content_hash).collect())
+    }
+
+    /// The values of `ty` in the base that have not been deleted, starting after `after`, until `count` have been found.
+    fn base_page(
+        &mut self,
+        ty: &Ty,
+        after: Option<ContentHash>,
+        count: usize,
+        whiteouts: &HashSet<ContentHash>,
+    ) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        let mut values = Vec::new();
+        let mut cursor = after;
+        while values.len() < count {
+            let page = or_missing(self.base.get_page_of_ty(ty.clone(), cursor, 0, count), Vec::new())?;
+            let exhausted = page.len() < count;
+            cursor = page.last().map(|(content_hash, _)| *content_hash);
+            values.extend(
+                page.into_iter()
+                    .filter(|(content_hash, _)| !whiteouts.contains(content_hash)),
+            );
+            if exhausted {
+                break;
+            }
+        }
+        values.truncate(count);
+        Ok(values)
+    }
+}
+
+impl Backend for Overlay {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = or_missing(self.upper.get_from_hash(ty.clone(), content_hash), None)? {
+            return Ok(Some(data));
+        }
+        if self.is_whited_out(&ty, content_hash)? {
+            return Ok(None);
+        }
+        or_missing(self.base.get_from_hash(ty, content_hash), None)
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        let whiteouts = self.whiteouts(&ty)?;
+        let mut values: BTreeMap<_, _> = or_missing(self.base.get_all_of_ty(ty.clone()), Vec::new())?
+            .into_iter()
+            .filter(|(content_hash, _)| !whiteouts.contains(content_hash))
+            .collect();
+        values.extend(or_missing(self.upper.get_all_of_ty(ty), Vec::new())?);
+        Ok(values.into_iter().collect())
+    }
+
+    fn get_page_of_ty(
+        &mut self,
+        ty: Ty,
+        after: Option<ContentHash>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        // The page is within the first `offset + limit` values of each layer.
+        let count = offset.saturating_add(limit);
+        let whiteouts = self.whiteouts(&ty)?;
+        let mut values: BTreeMap<_, _> = self.base_page(&ty, after, count, &whiteouts)?.into_iter().collect();
+        values.extend(or_missing(self.upper.get_page_of_ty(ty, after, 0, count), Vec::new())?);
+        Ok(values.into_iter().skip(offset).take(limit).collect())
+    }
+
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        or_missing(self.upper.delete_by_hash(whiteout_ty(&ty), content_hash), ())?;
+        self.upper.write(ty, content_hash, content)
+    }
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        if self.in_base(&ty, content_hash)? {
+            self.upper.write(whiteout_ty(&ty), content_hash, Vec::new())?;
+        }
+        or_missing(self.upper.delete_by_hash(ty, content_hash), ())
+    }
+
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
+        let whiteouts = self.whiteouts(&ty)?;
+        for (content_hash, _) in or_missing(self.base.get_all_of_ty(ty.clone()), Vec::new())? {
+            if !whiteouts.contains(&content_hash) {
+                self.upper.write(whiteout_ty(&ty), content_hash, Vec::new())?;
+            }
+        }
+        or_missing(self.upper.delete_all_of_ty(ty), ())
+    }
+
+    /// Types of the base whose values have all been deleted are left out, unless the upper layer has values of them.
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        let upper_tys: Vec<Ty> = self
+            .upper
+            .list_tys()?
+            .into_iter()
+            .filter(|ty| !is_bookkeeping_ty(ty))
+            .collect();
+        let mut tys = Vec::new();
+        for ty in self.base.list_tys()? {
+            if upper_tys.contains(&ty) || !self.all_whited_out(&ty)? {
+                tys.push(ty);
+            }
+        }
+        for ty in upper_tys {
+            if !tys.contains(&ty) {
+                tys.push(ty);
+            }
+        }
+        Ok(tys)
+    }
+
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        let base = self.base.type_info(ty.clone())?;
+        let upper = self.upper.type_info(ty.clone())?;
+        let (mut info, upper) = match (base, upper) {
+            (None, None) => return Ok(None),
+            (Some(base), upper) => (base, upper),
+            (None, Some(upper)) => return Ok(Some(upper)),
+        };
+
+        // Take away the base's values that have been deleted or are also in the upper layer.
+        let mut hidden = self.whiteouts(&ty)?;
+        let deleted = !hidden.is_empty();
+        for (content_hash, _) in or_missing(self.upper.get_all_of_ty(ty.clone()), Vec::new())? {
+            hidden.insert(content_hash);
+        }
+        for content_hash in hidden {
+            if let Some(data) = or_missing(self.base.get_from_hash(ty.clone(), content_hash), None)? {
+                info.count -= 1;
+                info.total_bytes -= data.len() as u64;
+            }
+        }
+        // Like a dropped table, a type whose values have all been deleted is gone.
+        if upper.is_none() && deleted && info.count == 0 {
+            return Ok(None);
+        }
+        if let Some(upper) = upper {
+            info.count += upper.count;
+            info.total_bytes += upper.total_bytes;
+            info.first_write = info.first_write.into_iter().chain(upper.first_write).min();
+            info.last_write = info.last_write.into_iter().chain(upper.last_write).max();
+        }
+        Ok(Some(info))
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        let mut tys: Vec<Ty> = self
+            .upper
+            .find_by_hash(content_hash)?
+            .into_iter()
+            .filter(|ty| !is_bookkeeping_ty(ty))
+            .collect();
+        for ty in self.base.find_by_hash(content_hash)? {
+            if !tys.contains(&ty) && !self.is_whited_out(&ty, content_hash)? {
+                tys.push(ty);
+            }
+        }
+        Ok(tys)
+    }
+
+    fn storage_name(&self, ty: &Ty) -> String {
+        self.upper.storage_name(ty)
+    }
+
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        let mut tables = self.base.foreign_tables()?;
+        tables.extend(self.upper.foreign_tables()?);
+        Ok(tables)
+    }
+
+    /// Write the upper layer's values and the metadata set through the overlay to the base, and delete the values the
+    /// upper layer has whiteouts for, in one transaction on the base. Then empty the upper layer.
+    fn flush(&mut self) -> Result<()> {
+        let tys = self.upper.list_tys()?;
+        self.base.begin_transaction()?;
+        let result = (|| {
+            for ty in &tys {
+                let values = self.upper.get_all_of_ty(ty.clone())?;
+                match whited_out_ty(ty) {
+                    None if *ty == METADATA_TY => {
+                        for (_, data) in values {
+                            let (key, value): (String, String) = serde_json::from_slice(&data)?;
+                            self.base.set_metadata(&key, &value)?;
+                        }
+                    }
+                    Some(target) => {
+                        for (content_hash, _) in values {
+                            or_missing(self.base.delete_by_hash(target.clone(), content_hash), ())?;
+                        }
+                    }
+                    None => {
+                        for (content_hash, data) in values {
+                            self.base.write(ty.clone(), content_hash, data)?;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })();
+        match result {
+            Ok(()) => self.base.commit_transaction()?,
+            Err(e) => {
+                self.base.rollback_transaction()?;
+                return Err(e);
+            }
+        }
+        for ty in tys {
+            self.upper.delete_all_of_ty(ty)?;
+        }
+        self.base.flush()
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        match self.upper.get_metadata(key)? {
+            Some(value) => Ok(Some(value)),
+            None => self.base.get_metadata(key),
+        }
+    }
+
+    /// The metadata is also recorded as a value in the upper layer, so that [`Backend::flush`] can find it.
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        let key_hash = ContentHash {
+            hash: *blake3::hash(key.as_bytes()).as_bytes(),
+        };
+        self.upper
+            .write(Ty::from(METADATA_TY), key_hash, serde_json::to_vec(&(key, value))?)?;
+        self.upper.set_metadata(key, value)
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        self.upper.begin_transaction()
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        self.upper.commit_transaction()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.upper.rollback_transaction()
+    }
+}
+
+impl Store {
+    /// Layer `upper` over `base` with an [`Overlay`], and open a store on it with `params`. Both must use the same
+    /// [`Encoding`], except that an empty `upper` takes the encoding of `base`.
+    pub fn overlay(mut base: Box<dyn Backend>, mut upper: Box<dyn Backend>, params: &StoreParams) -> Result<Store> {
+        if let Some(stored) = stored_encoding(base.as_mut())? {
+            let requested = stored_encoding(upper.as_mut())?;
+            if requested != Some(stored) {
+                if !upper.list_tys()?.is_empty() {
+                    return Err(Error::EncodingMismatch {
+                        stored,
+                        requested: requested.unwrap_or_default(),
+                    });
+                }
+                upper.set_metadata(Encoding::METADATA_KEY, stored.name())?;
+            }
+        }
+        Store::from_backend(Box::new(Overlay::new(base, upper)), params)
+    }
+}
+
+/// The encoding `backend` records, or JSON if it has values but no record because they were written before encodings
+/// were recorded.
+fn stored_encoding(backend: &mut dyn Backend) -> Result<Option<Encoding>> {
+    match backend.get_metadata(Encoding::METADATA_KEY)? {
+        Some(name) => Ok(Some(name.parse()?)),
+        None if !backend.list_tys()?.is_empty() => Ok(Some(Encoding::Json)),
+        None => Ok(None),
+    }
+}
+
+/// The type whiteouts for values of `ty` are stored under.
+fn whiteout_ty(ty: &Ty) -> Ty {
+    let mut path = vec![WHITEOUT_PREFIX.to_string()];
+    path.extend(ty.path.iter().cloned());
+    Ty { path }
+}
+
+/// Whether `ty` is one the overlay keeps its own records under in the upper layer.
+fn is_bookkeeping_ty(ty: &Ty) -> bool {
+    whited_out_ty(ty).is_some() || *ty == METADATA_TY
+}
+
+/// The type whose values whiteouts stored under `ty` are for, if `ty` is a whiteout type.
+fn whited_out_ty(ty: &Ty) -> Option<Ty> {
+    match ty.path.split_first() {
+        Some((first, rest)) if first == WHITEOUT_PREFIX => Some(Ty { path: rest.to_vec() }),
+        _ => None,
+    }
+}
@@ -0,0 +1,328 @@
+//! Pinning of stored values.
+//!
+//! A value can be pinned any number of times with [`Store::pin`], and stays pinned until it has been unpinned as many
+//! times with [`Store::unpin`]. Deleting a pinned value either fails with [`Error::Pinned`] or is deferred until its
+//! last pin is released, depending on [`StoreParams::pinned_deletes`]. Pinned values are also kept by
+//! [`Store::collect_garbage`].
+//!
+//! Pins are stored in the store itself, so they last as long as it does.
+//!
+//! [`StoreParams::pinned_deletes`]: crate::store::StoreParams::pinned_deletes
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ContentHash, Ty, Vanth,
+    query::{FieldPath, FieldValue, Query},
+    store::{Backend, CacheStats, Encoding, Error, Store, TypeInfo, Values, or_missing},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The type pins are stored under, keyed by [`pin_key`].
+pub(crate) const PIN_TY: &str = "vanth_pin";
+
+/// What happens when a pinned value is deleted, as set by [`StoreParams::pinned_deletes`].
+///
+/// [`StoreParams::pinned_deletes`]: crate::store::StoreParams::pinned_deletes
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub enum PinnedDeletes {
+    /// The delete fails with [`Error::Pinned`].
+    #[default]
+    Refuse,
+    /// The value is kept until its last pin is released, and then deleted.
+    Defer,
+}
+
+/// A pinned value, as listed by [`Store::pins`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Pin {
+    pub ty: Ty,
+    pub content_hash: ContentHash,
+    /// How many times the value has been pinned and not yet unpinned.
+    pub count: u64,
+    /// Whether the value was deleted while pinned, and will be deleted when its last pin is released.
+    pub delete_pending: bool,
+}
+
+/// The key the pin of the value of `ty` with `content_hash` is stored under.
+fn pin_key(ty: &Ty, content_hash: ContentHash) -> ContentHash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(ty.to_string().as_bytes());
+    hasher.update(&[0]);
+    hasher.update(&content_hash.hash);
+    ContentHash {
+        hash: *hasher.finalize().as_bytes(),
+    }
+}
+
+fn get_pin(backend: &mut dyn Backend, ty: &Ty, content_hash: ContentHash) -> Result<Option<Pin>> {
+    let stored = or_missing(backend.get_from_hash(Ty::from(PIN_TY), pin_key(ty, content_hash)), None)?;
+    stored.map(|data| Ok(serde_json::from_slice(&data)?)).transpose()
+}
+
+fn all_pins(backend: &mut dyn Backend) -> Result<Vec<Pin>> {
+    or_missing(backend.get_all_of_ty(Ty::from(PIN_TY)), Vec::new())?
+        .into_iter()
+        .map(|(_, data)| Ok(serde_json::from_slice(&data)?))
+        .collect()
+}
+
+fn put_pin(backend: &mut dyn Backend, pin: &Pin) -> Result<()> {
+    let key = pin_key(&pin.ty, pin.content_hash);
+    backend.write(Ty::from(PIN_TY), key, serde_json::to_vec(pin)?)
+}
+
+/// Run `f` in a transaction on `backend`, so that a count it reads isn't changed by another connection before `f`
+/// writes it back.
+fn in_transaction<R>(backend: &mut dyn Backend, f: impl FnOnce(&mut dyn Backend) -> Result<R>) -> Result<R> {
+    backend.begin_transaction()?;
+    match f(backend) {
+        Ok(result) => {
+            backend.commit_transaction()?;
+            Ok(result)
+        }
+        Err(e) => {
+            backend.rollback_transaction()?;
+            Err(e)
+        }
+    }
+}
+
+pub(crate) fn is_pin_ty(ty: &Ty) -> bool {
+    *ty == PIN_TY
+}
+
+/// A [`Backend`] that stops pinned values being deleted.
+///
+/// Every [`Store`] is built on one. Pins are hidden from [`Backend::list_tys`].
+#[derive(Debug)]
+pub(crate) struct PinGuard {
+    backend: Box<dyn Backend>,
+    policy: PinnedDeletes,
+}
+
+impl PinGuard {
+    pub(crate) fn new(backend: Box<dyn Backend>, policy: PinnedDeletes) -> Self {
+        Self { backend, policy }
+    }
+
+    /// Whether the value of `ty` with `content_hash` may be deleted now. Otherwise, its deletion is refused or
+    /// recorded to happen later.
+    fn may_delete(&mut self, ty: &Ty, content_hash: ContentHash) -> Result<bool> {
+        if !self.has_pins()? {
+            return Ok(true);
+        }
+        let Some(mut pin) = get_pin(self.backend.as_mut(), ty, content_hash)? else {
+            return Ok(true);
+        };
+        match self.policy {
+            PinnedDeletes::Refuse => Err(Error::Pinned {
+                ty: ty.clone(),
+                content_hash,
+            }),
+            PinnedDeletes::Defer => {
+                if !pin.delete_pending {
+                    pin.delete_pending = true;
+                    put_pin(self.backend.as_mut(), &pin)?;
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// Whether any value is pinned, checked before looking pins up so that stores without pins don't pay for it.
+    fn has_pins(&mut self) -> Result<bool> {
+        let info = or_missing(self.backend.type_info(Ty::from(PIN_TY)), None)?;
+        Ok(info.is_some_and(|info| info.count > 0))
+    }
+}
+
+impl Backend for PinGuard {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        self.backend.get_from_hash(ty, content_hash)
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Values> {
+        self.backend.get_all_of_ty(ty)
+    }
+
+    fn get_page_of_ty(&mut self, ty: Ty, after: Option<ContentHash>, offset: usize, limit: usize) -> Result<Values> {
+        self.backend.get_page_of_ty(ty, after, offset, limit)
+    }
+
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        self.backend.write(ty, content_hash, content)
+    }
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        if !is_pin_ty(&ty) && !self.may_delete(&ty, content_hash)? {
+            return Ok(());
+        }
+        self.backend.delete_by_hash(ty, content_hash)
+    }
+
+    /// With pinned values of `ty`, either nothing is deleted or every value but the pinned ones is.
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
+        if is_pin_ty(&ty) || !self.has_pins()? {
+            return self.backend.delete_all_of_ty(ty);
+        }
+        let ty_name = ty.to_string();
+        let pinned: Vec<Pin> = all_pins(self.backend.as_mut())?
+            .into_iter()
+            .filter(|pin| pin.ty == ty_name)
+            .collect();
+        if pinned.is_empty() {
+            return self.backend.delete_all_of_ty(ty);
+        }
+        if self.policy == PinnedDeletes::Refuse {
+            let pin = &pinned[0];
+            return Err(Error::Pinned {
+                ty: pin.ty.clone(),
+                content_hash: pin.content_hash,
+            });
+        }
+        for (content_hash, _) in self.backend.get_all_of_ty(ty.clone())? {
+            if self.may_delete(&ty, content_hash)? {
+                self.backend.delete_by_hash(ty.clone(), content_hash)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        let mut tys = self.backend.list_tys()?;
+        tys.retain(|ty| !is_pin_ty(ty));
+        Ok(tys)
+    }
+
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        self.backend.type_info(ty)
+    }
+
+    fn list_types(&mut self) -> Result<Vec<TypeInfo>> {
+        let mut types = self.backend.list_types()?;
+        types.retain(|info| !is_pin_ty(&info.ty));
+        Ok(types)
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        let mut tys = self.backend.find_by_hash(content_hash)?;
+        tys.retain(|ty| !is_pin_ty(ty));
+        Ok(tys)
+    }
+
+    fn create_index(&mut self, ty: Ty, path: &FieldPath, encoding: Encoding) -> Result<()> {
+        self.backend.create_index(ty, path, encoding)
+    }
+
+    fn drop_indexes(&mut self, ty: Ty) -> Result<()> {
+        self.backend.drop_indexes(ty)
+    }
+
+    fn find_by_field(
+        &mut self,
+        ty: Ty,
+        path: &FieldPath,
+        value: &FieldValue,
+        encoding: Encoding,
+    ) -> Result<Option<Values>> {
+        self.backend.find_by_field(ty, path, value, encoding)
+    }
+
+    fn query(&mut self, ty: Ty, query: &Query, encoding: Encoding) -> Result<Option<Values>> {
+        self.backend.query(ty, query, encoding)
+    }
+
+    fn storage_name(&self, ty: &Ty) -> String {
+        self.backend.storage_name(ty)
+    }
+
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        self.backend.foreign_tables()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.backend.cache_stats()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.backend.flush()
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        self.backend.get_metadata(key)
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        self.backend.set_metadata(key, value)
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        self.backend.begin_transaction()
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        self.backend.commit_transaction()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.backend.rollback_transaction()
+    }
+}
+
+impl Store {
+    /// Pin the value of `T` with `content_hash`, which need not be stored yet. Returns how many times it is now pinned.
+    pub fn pin<T: Vanth>(&mut self, content_hash: ContentHash) -> Result<u64> {
+        self.pin_raw(T::ty(), content_hash)
+    }
+
+    pub fn pin_raw(&mut self, ty: Ty, content_hash: ContentHash) -> Result<u64> {
+        in_transaction(self.backend.as_mut(), |backend| {
+            let mut pin = get_pin(backend, &ty, content_hash)?.unwrap_or(Pin {
+                ty,
+                content_hash,
+                count: 0,
+                delete_pending: false,
+            });
+            pin.count += 1;
+            put_pin(backend, &pin)?;
+            Ok(pin.count)
+        })
+    }
+
+    /// Release a pin on the value of `T` with `content_hash`. Returns how many times it is still pinned, which is 0 if
+    /// it wasn't pinned. Releasing the last pin of a value whose deletion was deferred deletes it.
+    pub fn unpin<T: Vanth>(&mut self, content_hash: ContentHash) -> Result<u64> {
+        self.unpin_raw(T::ty(), content_hash)
+    }
+
+    pub fn unpin_raw(&mut self, ty: Ty, content_hash: ContentHash) -> Result<u64> {
+        in_transaction(self.backend.as_mut(), |backend| {
+            let Some(mut pin) = get_pin(backend, &ty, content_hash)? else {
+                return Ok(0);
+            };
+            pin.count -= 1;
+            if pin.count > 0 {
+                put_pin(backend, &pin)?;
+                return Ok(pin.count);
+            }
+            backend.delete_by_hash(Ty::from(PIN_TY), pin_key(&ty, content_hash))?;
+            if pin.delete_pending {
+                or_missing(backend.delete_by_hash(ty, content_hash), ())?;
+            }
+            Ok(0)
+        })
+    }
+
+    /// Every pinned value.
+    pub fn pins(&mut self) -> Result<Vec<Pin>> {
+        all_pins(self.backend.as_mut())
+    }
+
+    /// How many times the value of `T` with `content_hash` is pinned.
+    pub fn pin_count<T: Vanth>(&mut self, content_hash: ContentHash) -> Result<u64> {
+        let pin = get_pin(self.backend.as_mut(), &T::ty(), content_hash)?;
+        Ok(pin.map_or(0, |pin| pin.count))
+    }
+}
@@ -0,0 +1,477 @@
+//! Looking values up by the contents of their fields.
+//!
+//! Fields are compared the way SQLite compares the results of `json_extract`, so that every backend returns the same
+//! values whether it evaluates a lookup itself or leaves it to [`Store`](crate::store::Store). Booleans compare as the
+//! integers `0` and `1`, integers and reals compare numerically, and objects and arrays compare as their JSON text.
+//! Missing fields are null. Values of different kinds are never equal, and order as null, then numbers, then text.
+//!
+//! [`Predicate`]s follow SQL's three-valued logic: comparing anything with null is neither true nor false, so neither
+//! `field("x")?.eq(1)` nor `!field("x")?.eq(1)` match values without an `x`. Use [`Field::is_null`] to match those.
+//!
+//! ```ignore
+//! let query = Query::new()
+//!     .filter(field("team")?.eq(3).and(field("score")?.between(10, 20)))
+//!     .order_by(field("score")?, Order::Descending)
+//!     .limit(5);
+//! let players = store.query::<Player>(&query)?;
+//! ```
+
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use serde_json::Value;
+
+use crate::{ContentHash, store::Error};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A path to a field of a value, such as `position.x`. Numeric segments index into arrays, so `items.0` is the first
+/// element of the `items` field.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FieldPath {
+    segments: Vec<String>,
+}
+
+impl FieldPath {
+    /// Parse a dot-separated path. Segments may only contain ASCII letters, digits and `_`.
+    pub fn parse(path: &str) -> Result<Self> {
+        let segments: Vec<String> = path.split('.').map(String::from).collect();
+        let valid = |segment: &String| {
+            !segment.is_empty() && segment.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+        };
+        if !segments.iter().all(valid) {
+            return Err(Error::InvalidFieldPath(path.into()));
+        }
+        Ok(Self { segments })
+    }
+
+    /// The path in SQLite's JSON path syntax, e.g. `$.items[0]`.
+    pub(crate) fn json_path(&self) -> String {
+        let mut json_path = String::from("$");
+        for segment in &self.segments {
+            match segment.parse::<usize>() {
+                Ok(index) => json_path.push_str(&format!("[{}]", index)),
+                Err(_) => json_path.push_str(&format!(".{}", segment)),
+            }
+        }
+        json_path
+    }
+
+    /// The field at this path in `value`, if there is one.
+    pub(crate) fn extract<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(value, |value, segment| match segment.parse::<usize>() {
+                Ok(index) => value.as_array()?.get(index),
+                Err(_) => value.as_object()?.get(segment),
+            })
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.segments.join("."))
+    }
+}
+
+impl std::str::FromStr for FieldPath {
+    type Err = Error;
+
+    fn from_str(path: &str) -> Result<Self> {
+        Self::parse(path)
+    }
+}
+
+/// The value of a field as SQLite's `json_extract` sees it. A missing field is [`FieldValue::Null`].
+#[derive(Clone, Debug)]
+pub enum FieldValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl FieldValue {
+    /// Reals with an integral value become [`FieldValue::Integer`]s, so that `1` and `1.0` are equal.
+    pub fn from_json(value: &Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Bool(value) => Self::Integer(*value as i64),
+            Value::Number(number) => match number.as_i64() {
+                Some(integer) => Self::Integer(integer),
+                None => Self::real(number.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(text) => Self::Text(text.clone()),
+            Value::Array(_) | Value::Object(_) => Self::Text(value.to_string()),
+        }
+    }
+
+    /// The field at `path` of `value`.
+    pub(crate) fn of(value: &Value, path: &FieldPath) -> Self {
+        path.extract(value).map_or(Self::Null, Self::from_json)
+    }
+
+    fn real(real: f64) -> Self {
+        if real.fract() == 0.0 && real >= i64::MIN as f64 && real < i64::MAX as f64 {
+            Self::Integer(real as i64)
+        } else {
+            Self::Real(real)
+        }
+    }
+
+    /// Order null first, then numbers, then text, as SQLite does.
+    fn sort_cmp(&self, other: &Self) -> Ordering {
+        let rank = |value: &Self| match value {
+            Self::Null => 0,
+            Self::Integer(_) | Self::Real(_) => 1,
+            Self::Text(_) => 2,
+        };
+        match (self, other) {
+            (Self::Integer(a), Self::Integer(b)) => a.cmp(b),
+            (Self::Integer(a), Self::Real(b)) => (*a as f64).total_cmp(b),
+            (Self::Real(a), Self::Integer(b)) => a.total_cmp(&(*b as f64)),
+            (Self::Real(a), Self::Real(b)) => a.total_cmp(b),
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
+}
+
+macro_rules! impl_from_integer {
+    ($($t:ty),*) => {
+        $(impl From<$t> for FieldValue {
+            fn from(value: $t) -> Self {
+                Self::Integer(value.into())
+            }
+        })*
+    };
+}
+
+impl_from_integer!(i8, i16, i32, i64, u8, u16, u32, bool);
+
+impl From<u64> for FieldValue {
+    fn from(value: u64) -> Self {
+        match i64::try_from(value) {
+            Ok(integer) => Self::Integer(integer),
+            Err(_) => Self::Real(value as f64),
+        }
+    }
+}
+
+impl From<f32> for FieldValue {
+    fn from(value: f32) -> Self {
+        Self::real(value.into())
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        Self::real(value)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        Self::Text(value.into())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl PartialEq for FieldValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Null, Self::Null) => true,
+            (Self::Integer(a), Self::Integer(b)) => a == b,
+            (Self::Real(a), Self::Real(b)) => a.to_bits() == b.to_bits(),
+            (Self::Text(a), Self::Text(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for FieldValue {}
+
+impl Hash for FieldValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Null => {}
+            Self::Integer(integer) => integer.hash(state),
+            Self::Real(real) => real.to_bits().hash(state),
+            Self::Text(text) => text.hash(state),
+        }
+    }
+}
+
+/// The field at `path`, to build [`Predicate`]s on or order a [`Query`] by.
+pub fn field(path: &str) -> Result<Field> {
+    Ok(Field {
+        path: FieldPath::parse(path)?,
+    })
+}
+
+/// A field of the values being queried, created with [`field`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    path: FieldPath,
+}
+
+impl Field {
+    fn compare(self, comparison: Comparison, value: impl Into<FieldValue>) -> Predicate {
+        Predicate::Compare {
+            path: self.path,
+            comparison,
+            value: value.into(),
+        }
+    }
+
+    pub fn eq(self, value: impl Into<FieldValue>) -> Predicate {
+        self.compare(Comparison::Equal, value)
+    }
+
+    pub fn ne(self, value: impl Into<FieldValue>) -> Predicate {
+        self.compare(Comparison::NotEqual, value)
+    }
+
+    pub fn lt(self, value: impl Into<FieldValue>) -> Predicate {
+        self.compare(Comparison::Less, value)
+    }
+
+    pub fn le(self, value: impl Into<FieldValue>) -> Predicate {
+        self.compare(Comparison::LessOrEqual, value)
+    }
+
+    pub fn gt(self, value: impl Into<FieldValue>) -> Predicate {
+        self.compare(Comparison::Greater, value)
+    }
+
+    pub fn ge(self, value: impl Into<FieldValue>) -> Predicate {
+        self.compare(Comparison::GreaterOrEqual, value)
+    }
+
+    /// Between `low` and `high`, inclusive.
+    pub fn between(self, low: impl Into<FieldValue>, high: impl Into<FieldValue>) -> Predicate {
+        self.clone().ge(low).and(self.le(high))
+    }
+
+    /// Null or missing.
+    pub fn is_null(self) -> Predicate {
+        Predicate::IsNull(self.path)
+    }
+
+    pub fn is_not_null(self) -> Predicate {
+        !self.is_null()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    pub(crate) fn sql(&self) -> &'static str {
+        match self {
+            Self::Equal => "=",
+            Self::NotEqual => "!=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+        }
+    }
+
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            Self::Equal => ordering.is_eq(),
+            Self::NotEqual => ordering.is_ne(),
+            Self::Less => ordering.is_lt(),
+            Self::LessOrEqual => ordering.is_le(),
+            Self::Greater => ordering.is_gt(),
+            Self::GreaterOrEqual => ordering.is_ge(),
+        }
+    }
+}
+
+/// A condition on the fields of a value. Combine predicates with [`Predicate::and`], [`Predicate::or`] and `!`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Predicate {
+    Compare {
+        path: FieldPath,
+        comparison: Comparison,
+        value: FieldValue,
+    },
+    IsNull(FieldPath),
+    /// True if every predicate is, so an empty `All` is always true.
+    All(Vec<Predicate>),
+    /// True if any predicate is, so an empty `Any` is always false.
+    Any(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    pub fn and(self, other: Predicate) -> Predicate {
+        match self {
+            Predicate::All(mut predicates) => {
+                predicates.push(other);
+                Predicate::All(predicates)
+            }
+            predicate => Predicate::All(vec![predicate, other]),
+        }
+    }
+
+    pub fn or(self, other: Predicate) -> Predicate {
+        match self {
+            Predicate::Any(mut predicates) => {
+                predicates.push(other);
+                Predicate::Any(predicates)
+            }
+            predicate => Predicate::Any(vec![predicate, other]),
+        }
+    }
+
+    /// Evaluate the predicate on `value`, returning `None` if the result is unknown because of a comparison with null.
+    pub(crate) fn evaluate(&self, value: &Value) -> Option<bool> {
+        match self {
+            Predicate::Compare {
+                path,
+                comparison,
+                value: expected,
+            } => match (FieldValue::of(value, path), expected) {
+                (FieldValue::Null, _) | (_, FieldValue::Null) => None,
+                (actual, expected) => Some(comparison.holds(actual.sort_cmp(expected))),
+            },
+            Predicate::IsNull(path) => Some(FieldValue::of(value, path) == FieldValue::Null),
+            Predicate::All(predicates) => {
+                let results: Vec<_> = predicates.iter().map(|predicate| predicate.evaluate(value)).collect();
+                if results.contains(&Some(false)) {
+                    Some(false)
+                } else if results.contains(&None) {
+                    None
+                } else {
+                    Some(true)
+                }
+            }
+            Predicate::Any(predicates) => {
+                let results: Vec<_> = predicates.iter().map(|predicate| predicate.evaluate(value)).collect();
+                if results.contains(&Some(true)) {
+                    Some(true)
+                } else if results.contains(&None) {
+                    None
+                } else {
+                    Some(false)
+                }
+            }
+            Predicate::Not(predicate) => predicate.evaluate(value).map(|result| !result),
+        }
+    }
+}
+
+impl std::ops::Not for Predicate {
+    type Output = Predicate;
+
+    fn not(self) -> Predicate {
+        Predicate::Not(Box::new(self))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Order {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// A filtered, ordered read of the values of a type, run with [`Store::query`](crate::store::Store::query).
+///
+/// Values are ordered by each of `order_by` in turn and then by hash, and `offset` and `limit` are applied after
+/// ordering.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    pub filter: Option<Predicate>,
+    pub order_by: Vec<(FieldPath, Order)>,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only return values matching `predicate`, as well as any filter already set.
+    pub fn filter(mut self, predicate: Predicate) -> Self {
+        self.filter = Some(match self.filter.take() {
+            Some(filter) => filter.and(predicate),
+            None => predicate,
+        });
+        self
+    }
+
+    pub fn order_by(mut self, field: Field, order: Order) -> Self {
+        self.order_by.push((field.path, order));
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub(crate) fn matches(&self, value: &Value) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.evaluate(value) == Some(true))
+    }
+
+    /// Order values that match the query and apply its offset and limit.
+    pub(crate) fn arrange<V>(&self, matches: Vec<(ContentHash, Value, V)>) -> Vec<(ContentHash, V)> {
+        let mut keyed: Vec<_> = matches
+            .into_iter()
+            .map(|(content_hash, value, item)| {
+                let keys: Vec<FieldValue> = self
+                    .order_by
+                    .iter()
+                    .map(|(path, _)| FieldValue::of(&value, path))
+                    .collect();
+                (keys, content_hash, item)
+            })
+            .collect();
+        keyed.sort_by(|(a_keys, a_hash, _), (b_keys, b_hash, _)| {
+            a_keys
+                .iter()
+                .zip(b_keys)
+                .zip(&self.order_by)
+                .map(|((a, b), (_, order))| match order {
+                    Order::Ascending => a.sort_cmp(b),
+                    Order::Descending => b.sort_cmp(a),
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a_hash.cmp(b_hash))
+        });
+        keyed
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(|(_, content_hash, item)| (content_hash, item))
+            .collect()
+    }
+}
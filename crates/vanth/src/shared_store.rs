@@ -0,0 +1,299 @@
+//! A thread-safe handle to a [`Store`].
+//!
+//! [`SharedStore`] can be cloned and sent between threads, and takes `&self` for every operation. Writes are serialized
+//! through a single [`Store`]. With an SQLite database, reads are served by a pool of read-only connections, so they
+//! run concurrently with each other. With other backends, reads share the writer.
+
+use std::{
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    ContentHash, Ty, Vanth,
+    query::Query,
+    store::{Error, Store, StoreParams, Transaction, TypeInfo},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A [`Store`] that can be used from several threads at once. Clones refer to the same store.
+///
+/// Reads from the connection pool only see committed writes, so a read made while a [`SharedStore::transaction`] is
+/// open on another thread sees the store as it was before the transaction.
+#[derive(Clone, Debug)]
+pub struct SharedStore {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    writer: Mutex<Store>,
+    readers: Option<Pool>,
+    /// Every alias registered with [`SharedStore::alias`], applied to readers before they are used.
+    aliases: Mutex<Vec<(Ty, Ty)>>,
+}
+
+impl SharedStore {
+    /// Use an SQLite database at the provided path, read through at most [`StoreParams::max_readers`] read-only
+    /// connections.
+    pub fn sqlite_from_path(path: PathBuf, params: StoreParams) -> Result<Self> {
+        let writer = Store::sqlite_from_path(path.clone(), params.clone())?;
+        let readers = (params.max_readers > 0).then(|| Pool::new(path, params));
+        Ok(Self::new(writer, readers))
+    }
+
+    /// Use an in-memory backend.
+    pub fn in_memory() -> Result<Self> {
+        Ok(Self::from_store(Store::in_memory()?))
+    }
+
+    /// Share any [`Store`]. Reads and writes both go through `store`, one at a time.
+    pub fn from_store(store: Store) -> Self {
+        Self::new(store, None)
+    }
+
+    fn new(writer: Store, readers: Option<Pool>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                writer: Mutex::new(writer),
+                readers,
+                aliases: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Run `f` with a store that reads committed values. Writing through it fails if the store has a connection pool.
+    pub fn with_reader<R>(&self, f: impl FnOnce(&mut Store) -> R) -> Result<R> {
+        let Some(pool) = &self.inner.readers else {
+            return Ok(f(&mut self.writer()));
+        };
+        let mut reader = pool.get()?;
+        let aliases = lock(&self.inner.aliases);
+        for (old, new) in &aliases[reader.aliases..] {
+            reader.store.alias_raw(old.clone(), new.clone());
+        }
+        reader.aliases = aliases.len();
+        drop(aliases);
+        Ok(f(&mut reader.store))
+    }
+
+    /// Run `f` with the store all writes go through, blocking other writers until it returns.
+    pub fn with_writer<R>(&self, f: impl FnOnce(&mut Store) -> R) -> R {
+        f(&mut self.writer())
+    }
+
+    fn writer(&self) -> MutexGuard<'_, Store> {
+        lock(&self.inner.writer)
+    }
+
+    pub fn alias<T: Vanth>(&self, old: Ty) {
+        self.alias_raw(old, T::ty());
+    }
+
+    pub fn alias_raw(&self, old: Ty, new: Ty) {
+        // Holding the writer stops the alias being seen by readers before the writer.
+        let mut writer = self.writer();
+        writer.alias_raw(old.clone(), new.clone());
+        lock(&self.inner.aliases).push((old, new));
+    }
+
+    pub fn get_from_hash<T: Vanth + Serialize + DeserializeOwned>(
+        &self,
+        content_hash: ContentHash,
+    ) -> Result<Option<T>> {
+        self.with_reader(|store| store.get_from_hash(content_hash))?
+    }
+
+    pub fn get_from_hash_raw(&self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        self.with_reader(|store| store.get_from_hash_raw(ty, content_hash))?
+    }
+
+    pub fn get_all_of_type<T: Vanth + DeserializeOwned>(&self) -> Result<Vec<(ContentHash, T)>> {
+        self.with_reader(|store| store.get_all_of_type())?
+    }
+
+    pub fn get_all_of_type_raw(&self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        self.with_reader(|store| store.get_all_of_type_raw(ty))?
+    }
+
+    pub fn find_by_field<T: Vanth + Serialize + DeserializeOwned>(
+        &self,
+        path: &str,
+        value: impl Serialize,
+    ) -> Result<Vec<(ContentHash, T)>> {
+        self.with_reader(|store| store.find_by_field(path, value))?
+    }
+
+    pub fn query<T: Vanth + Serialize + DeserializeOwned>(&self, query: &Query) -> Result<Vec<(ContentHash, T)>> {
+        self.with_reader(|store| store.query(query))?
+    }
+
+    pub fn find_by_hash(&self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        // The hash index is built on first use, which read-only connections can't do.
+        self.writer().find_by_hash(content_hash)
+    }
+
+    pub fn list_types(&self) -> Result<Vec<TypeInfo>> {
+        self.with_reader(|store| store.list_types())?
+    }
+
+    pub fn type_info<T: Vanth>(&self) -> Result<Option<TypeInfo>> {
+        self.with_reader(|store| store.type_info::<T>())?
+    }
+
+    pub fn type_info_raw(&self, ty: Ty) -> Result<Option<TypeInfo>> {
+        self.with_reader(|store| store.type_info_raw(ty))?
+    }
+
+    pub fn write<T: Vanth + Serialize>(&self, value: &T) -> Result<ContentHash> {
+        self.writer().write(value)
+    }
+
+    pub fn write_raw(&self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        self.writer().write_raw(ty, content_hash, content)
+    }
+
+    pub fn delete<T: Vanth>(&self, content_hash: ContentHash) -> Result<()> {
+        self.writer().delete::<T>(content_hash)
+    }
+
+    pub fn delete_raw(&self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        self.writer().delete_raw(ty, content_hash)
+    }
+
+    pub fn delete_all<T: Vanth>(&self) -> Result<()> {
+        self.writer().delete_all::<T>()
+    }
+
+    pub fn delete_all_raw(&self, ty: Ty) -> Result<()> {
+        self.writer().delete_all_raw(ty)
+    }
+
+    pub fn delete_everywhere(&self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        self.writer().delete_everywhere(content_hash)
+    }
+
+    pub fn create_index<T: Vanth>(&self, path: &str) -> Result<()> {
+        self.writer().create_index::<T>(path)
+    }
+
+    /// Run `f` in a transaction on the writer, as with [`Store::transaction`].
+    pub fn transaction<R, E: From<Error>>(
+        &self,
+        f: impl FnOnce(&mut Transaction<'_>) -> std::result::Result<R, E>,
+    ) -> std::result::Result<R, E> {
+        self.writer().transaction(f)
+    }
+}
+
+/// Lock `mutex`, ignoring poisoning. A panic while a store is locked leaves it as consistent as an error would.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Read-only connections to an SQLite database, opened as they are needed.
+#[derive(Debug)]
+struct Pool {
+    path: PathBuf,
+    params: StoreParams,
+    state: Mutex<PoolState>,
+    released: Condvar,
+}
+
+#[derive(Debug)]
+struct PoolState {
+    idle: Vec<Reader>,
+    /// Readers either idle or in use.
+    open: usize,
+}
+
+#[derive(Debug)]
+struct Reader {
+    store: Store,
+    /// How many of the shared aliases have been applied to `store`.
+    aliases: usize,
+}
+
+impl Pool {
+    fn new(path: PathBuf, params: StoreParams) -> Self {
+        Self {
+            path,
+            // Readers don't see deletes made by the writer, so they can't keep a cache.
+            params: StoreParams {
+                read_only: true,
+                create_if_not_exists: false,
+                cache_size_bytes: 0,
+                ..params
+            },
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                open: 0,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Take an idle reader, or open one if fewer than the maximum are open, or else wait for one to be released.
+    fn get(&self) -> Result<PooledReader<'_>> {
+        let mut state = lock(&self.state);
+        loop {
+            if let Some(reader) = state.idle.pop() {
+                return Ok(PooledReader {
+                    pool: self,
+                    reader: Some(reader),
+                });
+            }
+            if state.open < self.params.max_readers {
+                break;
+            }
+            state = self.released.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+        state.open += 1;
+        drop(state);
+
+        match Store::sqlite_from_path(self.path.clone(), self.params.clone()) {
+            Ok(store) => Ok(PooledReader {
+                pool: self,
+                reader: Some(Reader { store, aliases: 0 }),
+            }),
+            Err(e) => {
+                lock(&self.state).open -= 1;
+                self.released.notify_one();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A reader taken from a [`Pool`], returned to it when dropped.
+struct PooledReader<'a> {
+    pool: &'a Pool,
+    reader: Option<Reader>,
+}
+
+impl Deref for PooledReader<'_> {
+    type Target = Reader;
+
+    fn deref(&self) -> &Reader {
+        self.reader.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledReader<'_> {
+    fn deref_mut(&mut self) -> &mut Reader {
+        self.reader.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledReader<'_> {
+    fn drop(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            lock(&self.pool.state).idle.push(reader);
+            self.pool.released.notify_one();
+        }
+    }
+}
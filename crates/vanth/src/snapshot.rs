@@ -0,0 +1,304 @@
+//! Snapshots of in-memory stores.
+//!
+//! [`Store::memory_from_path`] opens an in-memory store loaded from a snapshot file, and saves it back there when
+//! [`Store::flush`] is called and as set by [`StoreParams::autosave`]. Reads and writes run at in-memory speed, and
+//! only committed values are saved. A snapshot is laid out as:
+//!
+//! - [`MAGIC`], followed by the snapshot format version as a little-endian `u32`.
+//! - The [`Memory`] backend, encoded with bincode.
+//!
+//! Snapshots are written to a temporary file that is then renamed over the old one, so a crash while saving leaves the
+//! previous snapshot intact.
+//!
+//! [`StoreParams::autosave`]: crate::store::StoreParams::autosave
+
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    ContentHash, Ty,
+    query::{FieldPath, FieldValue, Query},
+    store::{Backend, CacheStats, Encoding, Error, Memory, Store, StoreParams, TypeInfo, Values},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The first bytes of every snapshot.
+pub const MAGIC: [u8; 8] = *b"VANTHMEM";
+
+/// The version of the snapshot format written by [`Memory::save`].
+pub const VERSION: u32 = 1;
+
+/// When an in-memory store opened with [`Store::memory_from_path`] saves its snapshot, besides when [`Store::flush`]
+/// is called.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub enum Autosave {
+    /// Never.
+    #[default]
+    Manual,
+    /// When the store is dropped with unsaved changes.
+    OnDrop,
+    /// When a change is committed at least this long after the last save, and when the store is dropped with unsaved
+    /// changes.
+    Every(Duration),
+}
+
+impl Memory {
+    /// Load a snapshot written by [`Memory::save`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        let mut version = [0; 4];
+        reader.read_exact(&mut magic).map_err(truncated)?;
+        reader.read_exact(&mut version).map_err(truncated)?;
+        if magic != MAGIC {
+            return Err(Error::InvalidSnapshot("not a vanth snapshot".into()));
+        }
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(Error::InvalidSnapshot(format!(
+                "unsupported snapshot version {version}"
+            )));
+        }
+        bincode::serde::decode_from_std_read(&mut reader, bincode::config::standard())
+            .map_err(|e| Error::InvalidSnapshot(e.to_string()))
+    }
+
+    /// Write the committed values and metadata to a snapshot at `path`, replacing any snapshot already there.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut temp_path = OsString::from(path.as_os_str());
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let result = (|| {
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            writer.write_all(&MAGIC)?;
+            writer.write_all(&VERSION.to_le_bytes())?;
+            bincode::serde::encode_into_std_write(self, &mut writer, bincode::config::standard())
+                .map_err(|e| Error::Serializiation(e.to_string()))?;
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+            fs::rename(&temp_path, path)?;
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+}
+
+fn truncated(err: std::io::Error) -> Error {
+    match err.kind() {
+        std::io::ErrorKind::UnexpectedEof => Error::InvalidSnapshot("snapshot is truncated".into()),
+        _ => err.into(),
+    }
+}
+
+/// A [`Memory`] backend that is saved to a snapshot file.
+#[derive(Debug)]
+pub(crate) struct Snapshotted {
+    memory: Memory,
+    path: PathBuf,
+    autosave: Autosave,
+    read_only: bool,
+    in_transaction: bool,
+    /// Whether there are changes that haven't been saved.
+    dirty: bool,
+    last_saved: Instant,
+}
+
+impl Snapshotted {
+    /// Load the snapshot at `path`, or start empty if there is none and `create_if_not_exists` is set.
+    pub(crate) fn open(path: PathBuf, params: &StoreParams) -> Result<Self> {
+        let memory = if path.exists() {
+            Memory::load(&path)?
+        } else if params.create_if_not_exists && !params.read_only {
+            Memory::new()
+        } else {
+            return Err(Error::Io(format!("snapshot {} does not exist", path.display())));
+        };
+        Ok(Self {
+            memory,
+            path,
+            autosave: params.autosave,
+            read_only: params.read_only,
+            in_transaction: false,
+            dirty: false,
+            last_saved: Instant::now(),
+        })
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<()> {
+        self.memory.save(&self.path)?;
+        self.dirty = false;
+        self.last_saved = Instant::now();
+        Ok(())
+    }
+
+    /// Record a change, saving if it is committed and [`Autosave::Every`] is due.
+    fn changed(&mut self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.dirty = true;
+        match self.autosave {
+            Autosave::Every(interval) if !self.in_transaction && self.last_saved.elapsed() >= interval => self.save(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Snapshotted {
+    fn drop(&mut self) {
+        if self.dirty
+            && self.autosave != Autosave::Manual
+            && let Err(e) = self.save()
+        {
+            warn!("Failed to save snapshot to {}: {:?}", self.path.display(), e);
+        }
+    }
+}
+
+impl Backend for Snapshotted {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        self.memory.get_from_hash(ty, content_hash)
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Values> {
+        self.memory.get_all_of_ty(ty)
+    }
+
+    fn get_page_of_ty(&mut self, ty: Ty, after: Option<ContentHash>, offset: usize, limit: usize) -> Result<Values> {
+        self.memory.get_page_of_ty(ty, after, offset, limit)
+    }
+
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        self.check_writable()?;
+        self.memory.write(ty, content_hash, content)?;
+        self.changed()
+    }
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        self.check_writable()?;
+        self.memory.delete_by_hash(ty, content_hash)?;
+        self.changed()
+    }
+
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
+        self.check_writable()?;
+        self.memory.delete_all_of_ty(ty)?;
+        self.changed()
+    }
+
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        self.memory.list_tys()
+    }
+
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        self.memory.type_info(ty)
+    }
+
+    fn list_types(&mut self) -> Result<Vec<TypeInfo>> {
+        self.memory.list_types()
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        self.memory.find_by_hash(content_hash)
+    }
+
+    fn create_index(&mut self, ty: Ty, path: &FieldPath, encoding: Encoding) -> Result<()> {
+        self.memory.create_index(ty, path, encoding)
+    }
+
+    fn drop_indexes(&mut self, ty: Ty) -> Result<()> {
+        self.check_writable()?;
+        self.memory.drop_indexes(ty)
+    }
+
+    fn find_by_field(
+        &mut self,
+        ty: Ty,
+        path: &FieldPath,
+        value: &FieldValue,
+        encoding: Encoding,
+    ) -> Result<Option<Values>> {
+        self.memory.find_by_field(ty, path, value, encoding)
+    }
+
+    fn query(&mut self, ty: Ty, query: &Query, encoding: Encoding) -> Result<Option<Values>> {
+        self.memory.query(ty, query, encoding)
+    }
+
+    fn storage_name(&self, ty: &Ty) -> String {
+        self.memory.storage_name(ty)
+    }
+
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        self.memory.foreign_tables()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.memory.cache_stats()
+    }
+
+    /// Save the snapshot.
+    fn flush(&mut self) -> Result<()> {
+        self.memory.flush()?;
+        if self.read_only {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        self.memory.get_metadata(key)
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        self.check_writable()?;
+        self.memory.set_metadata(key, value)?;
+        self.changed()
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        self.memory.begin_transaction()?;
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        self.memory.commit_transaction()?;
+        self.in_transaction = false;
+        self.changed()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.memory.rollback_transaction()?;
+        self.in_transaction = false;
+        Ok(())
+    }
+}
+
+impl Store {
+    /// Use an in-memory backend loaded from the snapshot at `path`, or empty if there is none and
+    /// [`StoreParams::create_if_not_exists`] is set. The snapshot is saved by [`Store::flush`] and as set by
+    /// [`StoreParams::autosave`].
+    pub fn memory_from_path(path: PathBuf, params: StoreParams) -> Result<Self> {
+        Self::from_backend(Box::new(Snapshotted::open(path, &params)?), &params)
+    }
+}
@@ -1,25 +1,126 @@
-use std::{collections::HashMap, marker::PhantomData, path::PathBuf};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    marker::PhantomData,
+    ops::Bound,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use rusqlite::{Connection, named_params, params};
+use rusqlite::{Connection, DatabaseName, OptionalExtension, named_params, params};
 
 use bevy_ecs::prelude::*;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
-use tracing::trace;
+use tracing::{trace, warn};
 
-use crate::{ComponentContents, ContentHash, Ty, Vanth, hash};
+use crate::{
+    ComponentContents, ContentHash, Ty, Vanth,
+    changes::{ChangeFeed, Feed},
+    chunking::{Chunked, Chunking},
+    compression::{Compressed, Compression},
+    encryption::{Encrypted, EncryptionKey},
+    fs::Filesystem,
+    hash,
+    pins::{PinGuard, PinnedDeletes},
+    query::{FieldPath, FieldValue, Order, Predicate, Query},
+    shared_store::SharedStore,
+    snapshot::Autosave,
+};
 
 #[derive(Debug)]
 pub struct Store {
-    backend: Box<dyn Backend>,
+    pub(crate) backend: Box<dyn Backend>,
+    pub(crate) encoding: Encoding,
+    pub(crate) verify_on_read: bool,
+    /// Old types whose values are also read as values of the keyed type, registered with [`Store::alias`].
+    aliases: HashMap<String, Vec<Ty>>,
+    /// The subscribers to changes made through the store's [`ChangeFeed`].
+    pub(crate) feed: Arc<Mutex<Feed>>,
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Raw values and their hashes.
+pub(crate) type Values = Vec<(ContentHash, Vec<u8>)>;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub enum Error {
     Serializiation(String),
-    SqliteTableDoesNotExist { table_name: String },
+    SqliteTableDoesNotExist {
+        table_name: String,
+    },
+    /// A stored value of type `ty` could not be deserialized.
+    Deserialization {
+        ty: Ty,
+        content_hash: ContentHash,
+        message: String,
+    },
     SqliteUnknown(String),
+    TransactionAlreadyOpen,
+    NoTransactionOpen,
+    UnknownEncoding(String),
+    /// The store was opened with a different [`Encoding`] to the one it was created with.
+    EncodingMismatch {
+        stored: Encoding,
+        requested: Encoding,
+    },
+    /// A value read with [`StoreParams::verify_on_read`] set does not hash to the hash it was stored under.
+    HashMismatch {
+        ty: Ty,
+        expected: ContentHash,
+        actual: ContentHash,
+    },
+    Io(String),
+    /// A write was attempted on a store opened with [`StoreParams::read_only`].
+    ReadOnly,
+    /// A [`FieldPath`] was empty or had a segment with characters other than ASCII letters, digits and `_`.
+    InvalidFieldPath(String),
+    /// The worker thread of an [`crate::async_store::AsyncStore`] stopped before running an operation, because an
+    /// earlier operation panicked or the operation outlived the store.
+    WorkerStopped,
+    /// The store was opened with a different [`EncryptionKey`] to the one it was created with.
+    WrongEncryptionKey,
+    /// The store is encrypted but was opened without [`StoreParams::encryption`].
+    EncryptionKeyRequired,
+    /// [`StoreParams::encryption`] was set for a store that was created unencrypted and already has values.
+    StoreNotEncrypted,
+    /// A stored value failed to decrypt, because it was modified or moved from another row.
+    Decryption {
+        ty: Ty,
+        content_hash: ContentHash,
+    },
+    /// A stored value failed to decompress, because it is corrupt or larger than
+    /// [`crate::compression::MAX_DECOMPRESSED_BYTES`] once decompressed.
+    Decompression {
+        ty: Ty,
+        content_hash: ContentHash,
+    },
+    /// [`StoreParams::chunking`] has chunk sizes FastCDC doesn't accept.
+    InvalidChunking(String),
+    /// A chunk of a chunked value is not in the store.
+    MissingChunk {
+        ty: Ty,
+        content_hash: ContentHash,
+        chunk: ContentHash,
+    },
+    /// A chunked value reassembled to a different length to the one recorded in its manifest.
+    ChunkedLengthMismatch {
+        ty: Ty,
+        content_hash: ContentHash,
+        expected: u64,
+        actual: u64,
+    },
+    /// An archive read by [`Store::import`] is truncated, corrupted or of an unsupported version.
+    InvalidArchive(String),
+    /// A value that is pinned was deleted, with [`StoreParams::pinned_deletes`] set to refuse such deletes.
+    Pinned {
+        ty: Ty,
+        content_hash: ContentHash,
+    },
+    /// A snapshot read by [`Store::memory_from_path`] is truncated, corrupted or of an unsupported version.
+    InvalidSnapshot(String),
 }
 
 impl From<serde_json::Error> for Error {
@@ -41,10 +142,48 @@ impl From<rusqlite::Error> for Error {
     }
 }
 
+/// Treat a backend without a table for a type as having no values of it.
+pub(crate) fn or_missing<T>(result: Result<T>, missing: T) -> Result<T> {
+    match result {
+        Err(Error::SqliteTableDoesNotExist { .. }) => Ok(missing),
+        result => result,
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StoreParams {
     pub create_if_not_exists: bool,
     pub read_only: bool,
+    /// The encoding values are stored with. A new store records this encoding, defaulting to JSON if it is `None`.
+    /// Opening an existing store with a different encoding fails with [`Error::EncodingMismatch`].
+    pub encoding: Option<Encoding>,
+    /// Re-hash values read by [`Store::get_from_hash`] and fail with [`Error::HashMismatch`] if they don't match.
+    pub verify_on_read: bool,
+    /// The most read-only connections a [`SharedStore`] opens to an SQLite database at once.
+    pub max_readers: usize,
+    /// If non-zero, put a [`Cache`] of this many bytes in front of the backend.
+    pub cache_size_bytes: u64,
+    /// Compress values before storing them. See [`crate::compression`].
+    pub compression: Option<Compression>,
+    /// Split large values into deduplicated chunks before storing them. See [`crate::chunking`].
+    pub chunking: Option<Chunking>,
+    /// Record changes in the store, so they can be read with [`Store::changes_since`]. See [`crate::changes`].
+    pub change_log: bool,
+    /// What happens when a pinned value is deleted. See [`crate::pins`].
+    pub pinned_deletes: PinnedDeletes,
+    /// When an in-memory store opened with [`Store::memory_from_path`] saves its snapshot. See [`crate::snapshot`].
+    pub autosave: Autosave,
+    /// The journal mode SQLite databases opened writable are switched to. Read-only connections use the database's.
+    pub journal_mode: JournalMode,
+    /// How thoroughly SQLite waits for writes to reach the disk.
+    pub synchronous: Synchronous,
+    /// How long an SQLite connection waits for another connection to release its lock before failing.
+    pub busy_timeout: Duration,
+    /// If non-zero, the size of each SQLite connection's page cache. Otherwise, SQLite's default of about 2 MB is used.
+    pub sqlite_cache_size_bytes: u64,
+    /// Encrypt values with this key before storing them. See [`crate::encryption`]. Never serialized.
+    #[serde(skip)]
+    pub encryption: Option<EncryptionKey>,
 }
 
 impl Default for StoreParams {
@@ -52,64 +191,421 @@ impl Default for StoreParams {
         Self {
             create_if_not_exists: true,
             read_only: false,
+            encoding: None,
+            verify_on_read: false,
+            max_readers: 4,
+            cache_size_bytes: 0,
+            compression: None,
+            chunking: None,
+            change_log: false,
+            pinned_deletes: PinnedDeletes::Refuse,
+            autosave: Autosave::Manual,
+            journal_mode: JournalMode::Wal,
+            synchronous: Synchronous::Full,
+            busy_timeout: Duration::from_secs(5),
+            sqlite_cache_size_bytes: 0,
+            encryption: None,
         }
     }
 }
 
-impl Store {
-    /// Use an SQLite backend with a database file at the provided path.
-    pub fn sqlite_from_path(path: PathBuf, params: StoreParams) -> Result<Self> {
-        use rusqlite::OpenFlags;
-        // Base flags for URI handling and disabling mutexes.
-        let mut flags = OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+/// How SQLite keeps transactions atomic. See <https://www.sqlite.org/pragma.html#pragma_journal_mode>.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    /// Write-ahead logging, which lets readers in other connections and processes carry on while a write is made.
+    #[default]
+    Wal,
+    Off,
+}
 
-        // Read‑only takes precedence over read‑write.
-        if params.read_only {
-            flags |= OpenFlags::SQLITE_OPEN_READ_ONLY;
-        } else {
-            flags |= OpenFlags::SQLITE_OPEN_READ_WRITE;
+impl JournalMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+            JournalMode::Persist => "persist",
+            JournalMode::Memory => "memory",
+            JournalMode::Wal => "wal",
+            JournalMode::Off => "off",
         }
+    }
+}
 
-        // Create the file if allowed.
-        if params.create_if_not_exists {
-            flags |= OpenFlags::SQLITE_OPEN_CREATE;
+/// How thoroughly SQLite waits for writes to reach the disk. See <https://www.sqlite.org/pragma.html#pragma_synchronous>.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Synchronous {
+    Off,
+    /// Safe from corruption in [`JournalMode::Wal`], but the last transactions may be lost on power failure.
+    Normal,
+    #[default]
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "off",
+            Synchronous::Normal => "normal",
+            Synchronous::Full => "full",
+            Synchronous::Extra => "extra",
         }
+    }
+}
 
-        // Open the SQLite connection with the computed flags.
-        let connection = rusqlite::Connection::open_with_flags(path, flags)?;
-        Ok(Self {
-            backend: Box::new(Sqlite { connection }),
-        })
+/// The format values are serialized in before being passed to a [`Backend`].
+///
+/// Hashes are computed over the values themselves, so the same value has the same [`ContentHash`] in every encoding.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    #[default]
+    Json,
+    /// Compact binary encoding. Not self-describing, so values can only be decoded into their original type.
+    Bincode,
+    Cbor,
+}
+
+impl Encoding {
+    /// The metadata key the encoding of a store is recorded under.
+    pub(crate) const METADATA_KEY: &'static str = "encoding";
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::Bincode => "bincode",
+            Encoding::Cbor => "cbor",
+        }
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Json => Ok(serde_json::to_vec(value)?),
+            Encoding::Bincode => bincode::serde::encode_to_vec(value, bincode::config::standard())
+                .map_err(|e| Error::Serializiation(e.to_string())),
+            Encoding::Cbor => {
+                let mut data = Vec::new();
+                ciborium::into_writer(value, &mut data).map_err(|e| Error::Serializiation(e.to_string()))?;
+                Ok(data)
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        self.try_decode(data).map_err(Error::Serializiation)
+    }
+
+    fn try_decode<T: DeserializeOwned>(&self, data: &[u8]) -> std::result::Result<T, String> {
+        match self {
+            Encoding::Json => serde_json::from_slice(data).map_err(|e| e.to_string()),
+            Encoding::Bincode => bincode::serde::decode_from_slice(data, bincode::config::standard())
+                .map(|(value, _)| value)
+                .map_err(|e| e.to_string()),
+            Encoding::Cbor => ciborium::from_reader(data).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Convert an encoded value to JSON without knowing its type. JSON values are returned unchanged.
+    ///
+    /// This fails for [`Encoding::Bincode`], which cannot be decoded without the type.
+    pub fn transcode_to_json(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Json => Ok(data.to_vec()),
+            Encoding::Bincode => Err(Error::Serializiation(
+                "bincode values cannot be transcoded without their type".into(),
+            )),
+            Encoding::Cbor => Ok(serde_json::to_vec(&self.decode::<serde_json::Value>(data)?)?),
+        }
+    }
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Encoding::Json),
+            "bincode" => Ok(Encoding::Bincode),
+            "cbor" => Ok(Encoding::Cbor),
+            _ => Err(Error::UnknownEncoding(s.into())),
+        }
+    }
+}
+
+impl<T: Vanth + DeserializeOwned> ComponentContents<T> {
+    /// Deserialize the stored value.
+    pub fn decode(&self) -> Result<T> {
+        decode(self.encoding, self.content_hash, &self.data)
+    }
+}
+
+pub(crate) fn decode<T: Vanth + DeserializeOwned>(
+    encoding: Encoding,
+    content_hash: ContentHash,
+    data: &[u8],
+) -> Result<T> {
+    encoding.try_decode(data).map_err(|message| Error::Deserialization {
+        ty: T::ty(),
+        content_hash,
+        message,
+    })
+}
+
+/// Options for iterating over the values of a type with [`Store::iter_of_type`].
+///
+/// Values are yielded in order of their [`ContentHash`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IterParams {
+    /// Only yield values with a hash greater than this one. Use the hash of the last value seen to resume an earlier
+    /// iteration.
+    pub after: Option<ContentHash>,
+    /// Number of values to skip before yielding any.
+    pub offset: usize,
+    /// Maximum number of values to yield.
+    pub limit: Option<usize>,
+    /// Number of values fetched from the backend at a time.
+    pub page_size: usize,
+}
+
+impl Default for IterParams {
+    fn default() -> Self {
+        Self {
+            after: None,
+            offset: 0,
+            limit: None,
+            page_size: 256,
+        }
+    }
+}
+
+impl Store {
+    /// Use an SQLite backend with a database file at the provided path.
+    pub fn sqlite_from_path(path: PathBuf, params: StoreParams) -> Result<Self> {
+        Self::from_backend(Box::new(Sqlite::open(path, &params)?), &params)
+    }
+
+    /// Use a [`Filesystem`] backend with its root directory at the provided path.
+    pub fn fs_from_path(path: PathBuf, params: StoreParams) -> Result<Self> {
+        let backend = Filesystem::new(path, params.create_if_not_exists, params.read_only)?;
+        Self::from_backend(Box::new(backend), &params)
     }
 
-    /// Use an in-memory backend.
+    /// Use an in-memory backend. See [`Store::memory_from_path`] to keep its values in a snapshot file.
     pub fn in_memory() -> Result<Self> {
+        Self::from_backend(Box::new(Memory::new()), &StoreParams::default())
+    }
+
+    /// Use any [`Backend`]. The encoding recorded in the backend is checked against or initialized from `params`.
+    ///
+    /// The backend is wrapped in [`Encrypted`], [`Compressed`] and [`Chunked`] backends, in that order, as set by
+    /// `params` or as recorded in its metadata when the store was opened before. Each of them hides the metadata it
+    /// records, so that a store built on the wrapped backend doesn't wrap it again. None of them can look inside the
+    /// values they pass on, so they leave field lookups and queries to the [`Backend`] defaults, and the [`Store`]
+    /// evaluates them instead.
+    pub fn from_backend(mut backend: Box<dyn Backend>, params: &StoreParams) -> Result<Self> {
+        match &params.encryption {
+            Some(key) => backend = Box::new(Encrypted::new(backend, key, params.read_only)?),
+            None if Encrypted::is_encrypted(backend.as_mut())? => return Err(Error::EncryptionKeyRequired),
+            None => {}
+        }
+        // Stores that have been compressed before keep being decompressed.
+        let mut compressed = backend.get_metadata(Compression::METADATA_KEY)?.is_some();
+        if params.compression.is_some() && !compressed && !params.read_only {
+            Compressed::enable(backend.as_mut())?;
+            compressed = true;
+        }
+        if compressed {
+            backend = Box::new(Compressed::new(backend, params.compression));
+        }
+        // Likewise, stores that have been chunked before keep being reassembled.
+        if let Some(chunking) = &params.chunking {
+            chunking.validate()?;
+        }
+        if params.chunking.is_some() || backend.get_metadata(Chunking::METADATA_KEY)?.is_some() {
+            if params.chunking.is_some() && !params.read_only {
+                backend.set_metadata(Chunking::METADATA_KEY, "fastcdc")?;
+            }
+            backend = Box::new(Chunked::new(backend, params.chunking));
+        }
+        let feed = Arc::default();
+        backend = Box::new(ChangeFeed::new(backend, Arc::clone(&feed), params.change_log)?);
+        backend = Box::new(PinGuard::new(backend, params.pinned_deletes));
+        if params.cache_size_bytes > 0 {
+            backend = Box::new(Cache::new(backend, params.cache_size_bytes));
+        }
+        let stored = backend
+            .get_metadata(Encoding::METADATA_KEY)?
+            .map(|name| name.parse::<Encoding>())
+            .transpose()?;
+        let encoding = match (stored, params.encoding) {
+            (Some(stored), Some(requested)) if stored != requested => {
+                return Err(Error::EncodingMismatch { stored, requested });
+            }
+            (Some(stored), _) => stored,
+            (None, requested) => {
+                // A store with values but no recorded encoding was written before encodings were, so holds JSON.
+                let legacy = !backend.list_tys()?.is_empty();
+                let encoding = match requested {
+                    Some(requested) if legacy && requested != Encoding::Json => {
+                        return Err(Error::EncodingMismatch {
+                            stored: Encoding::Json,
+                            requested,
+                        });
+                    }
+                    requested => requested.unwrap_or_default(),
+                };
+                if !params.read_only {
+                    backend.set_metadata(Encoding::METADATA_KEY, encoding.name())?;
+                }
+                encoding
+            }
+        };
         Ok(Self {
-            backend: Box::new(Memory::new()),
+            backend,
+            encoding,
+            verify_on_read: params.verify_on_read,
+            aliases: HashMap::new(),
+            feed,
         })
     }
 
-    pub fn get_from_hash<T: Vanth + DeserializeOwned>(&mut self, content_hash: ContentHash) -> Result<Option<T>> {
-        let Some(raw) = self.get_from_hash_raw(T::ty(), content_hash)? else {
+    /// Move changes held separately by the backend into permanent storage, such as the upper layer of an
+    /// [`crate::overlay::Overlay`] into its base, or the values of a store opened with [`Store::memory_from_path`] into
+    /// its snapshot.
+    pub fn flush(&mut self) -> Result<()> {
+        self.backend.flush()
+    }
+
+    /// Hits, misses and usage of the store's [`Cache`], or `None` if it has none.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.backend.cache_stats()
+    }
+
+    /// The encoding values in this store are serialized with.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Treat values stored under `old` as values of `T`, e.g. after `T` has been moved to another module.
+    ///
+    /// Reading, iterating and deleting values of `T` also covers the values stored under `old`, and writing a value of
+    /// `T` that is already stored under `old` does nothing. New values are only written under `T`'s own type. Aliases
+    /// only apply to the typed methods; the `_raw` methods always address a single type's table.
+    pub fn alias<T: Vanth>(&mut self, old: Ty) {
+        self.alias_raw(old, T::ty());
+    }
+
+    pub fn alias_raw(&mut self, old: Ty, new: Ty) {
+        let olds = self.aliases.entry(new.to_string()).or_default();
+        if !olds.contains(&old) {
+            olds.push(old);
+        }
+    }
+
+    /// Get the value with `content_hash` from the first type aliased to `ty` that has it, skipping types without a
+    /// table.
+    fn get_from_aliases(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        for old in self.resolve_ty(ty).into_iter().skip(1) {
+            match self.backend.get_from_hash(old, content_hash) {
+                Ok(Some(raw)) => return Ok(Some(raw)),
+                Ok(None) | Err(Error::SqliteTableDoesNotExist { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// `ty` followed by every type aliased to it, directly or through other aliases.
+    fn resolve_ty(&self, ty: Ty) -> Vec<Ty> {
+        let mut tys = vec![ty];
+        let mut i = 0;
+        while let Some(ty) = tys.get(i) {
+            for old in self.aliases.get(&ty.to_string()).into_iter().flatten() {
+                if !tys.contains(old) {
+                    tys.push(old.clone());
+                }
+            }
+            i += 1;
+        }
+        tys
+    }
+
+    pub fn get_from_hash<T: Vanth + Serialize + DeserializeOwned>(
+        &mut self,
+        content_hash: ContentHash,
+    ) -> Result<Option<T>> {
+        let raw = match self.backend.get_from_hash(T::ty(), content_hash) {
+            Ok(Some(raw)) => Some(raw),
+            // With aliases, the values may all be stored under an old type.
+            Ok(None) | Err(Error::SqliteTableDoesNotExist { .. })
+                if self.aliases.contains_key(&T::ty().to_string()) =>
+            {
+                self.get_from_aliases(T::ty(), content_hash)?
+            }
+            result => result?,
+        };
+        let Some(raw) = raw else {
             return Ok(None);
         };
 
-        let deserialized: T = serde_json::from_slice(&raw)?;
-        Ok(Some(deserialized))
+        let value: T = decode(self.encoding, content_hash, &raw)?;
+        if self.verify_on_read {
+            let actual = hash(&value);
+            if actual != content_hash {
+                return Err(Error::HashMismatch {
+                    ty: T::ty(),
+                    expected: content_hash,
+                    actual,
+                });
+            }
+        }
+        Ok(Some(value))
     }
 
     pub fn get_from_hash_raw(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
         self.backend.get_from_hash(ty, content_hash)
     }
 
-    pub fn get_all_of_type<T: Vanth>(&mut self) -> Result<Vec<ComponentContents<T>>> {
-        let raw_items = self.backend.get_all_of_ty(T::ty())?;
+    /// Get and deserialize every value of `T`. Fails with [`Error::Deserialization`] on the first value that cannot be
+    /// deserialized; use [`Store::get_all_contents_of_type`] to handle such values individually.
+    pub fn get_all_of_type<T: Vanth + DeserializeOwned>(&mut self) -> Result<Vec<(ContentHash, T)>> {
+        self.get_all_contents_of_type::<T>()?
+            .into_iter()
+            .map(|contents| Ok((contents.content_hash(), contents.decode()?)))
+            .collect()
+    }
+
+    /// Get every value of `T` without deserializing it.
+    pub fn get_all_contents_of_type<T: Vanth>(&mut self) -> Result<Vec<ComponentContents<T>>> {
+        let tys = self.resolve_ty(T::ty());
+        let raw_items = if tys.len() == 1 {
+            self.backend.get_all_of_ty(T::ty())?
+        } else {
+            // A value stored under more than one of the types is only returned once.
+            let mut merged = BTreeMap::new();
+            for ty in tys {
+                for (content_hash, data) in self.backend.get_all_of_ty(ty)? {
+                    merged.entry(content_hash).or_insert(data);
+                }
+            }
+            merged.into_iter().collect()
+        };
         let mut results = Vec::new();
         for (content_hash, data) in raw_items {
             results.push(ComponentContents {
                 _marker: PhantomData,
                 content_hash,
                 data,
+                encoding: self.encoding,
             });
         }
         Ok(results)
@@ -117,7 +613,10 @@ impl Store {
 
     pub fn write<T: Vanth + Serialize>(&mut self, value: &T) -> Result<ContentHash> {
         let content_hash = hash(&value);
-        let data = serde_json::to_vec(&value)?;
+        if self.get_from_aliases(T::ty(), content_hash)?.is_some() {
+            return Ok(content_hash);
+        }
+        let data = self.encoding.encode(value)?;
         self.backend.write(T::ty(), content_hash, data)?;
         Ok(content_hash)
     }
@@ -127,17 +626,62 @@ impl Store {
     }
 
     pub fn delete<T: Vanth>(&mut self, content_hash: ContentHash) -> Result<()> {
-        self.backend.delete_by_hash(T::ty(), content_hash)
+        let tys = self.resolve_ty(T::ty());
+        if tys.len() == 1 {
+            return self.backend.delete_by_hash(T::ty(), content_hash);
+        }
+        for ty in tys {
+            match self.backend.delete_by_hash(ty, content_hash) {
+                Ok(()) | Err(Error::SqliteTableDoesNotExist { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
     }
 
     pub fn delete_all<T: Vanth>(&mut self) -> Result<()> {
-        self.backend.delete_all_of_ty(T::ty())
+        for ty in self.resolve_ty(T::ty()) {
+            self.backend.delete_all_of_ty(ty)?;
+        }
+        Ok(())
     }
 
     pub fn get_all_of_type_raw(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
         self.backend.get_all_of_ty(ty)
     }
 
+    /// Lazily iterate over the values of `T`, holding at most one page of values in memory at a time.
+    ///
+    /// A value that cannot be deserialized is yielded as an [`Error::Deserialization`] and iteration continues with the
+    /// next value.
+    pub fn iter_of_type<T: Vanth + DeserializeOwned>(
+        &mut self,
+        params: IterParams,
+    ) -> impl Iterator<Item = Result<(ContentHash, T)>> + '_ {
+        let encoding = self.encoding;
+        let tys = self.resolve_ty(T::ty());
+        self.iter_of_tys(tys, params).map(move |item| {
+            item.and_then(|(content_hash, data)| Ok((content_hash, decode(encoding, content_hash, &data)?)))
+        })
+    }
+
+    pub fn iter_of_type_raw(&mut self, ty: Ty, params: IterParams) -> TypeIter<'_> {
+        self.iter_of_tys(vec![ty], params)
+    }
+
+    fn iter_of_tys(&mut self, tys: Vec<Ty>, params: IterParams) -> TypeIter<'_> {
+        TypeIter {
+            store: self,
+            tys,
+            page: VecDeque::new(),
+            after: params.after,
+            offset: params.offset,
+            remaining: params.limit,
+            page_size: params.page_size.max(1),
+            exhausted: false,
+        }
+    }
+
     pub fn delete_raw(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
         self.backend.delete_by_hash(ty, content_hash)
     }
@@ -145,173 +689,1940 @@ impl Store {
     pub fn delete_all_raw(&mut self, ty: Ty) -> Result<()> {
         self.backend.delete_all_of_ty(ty)
     }
-}
-
-#[derive(Debug, Deserialize, Component, Serialize)]
-pub struct Cache {
-    size_limit_bytes: u64,
-    // backend: Backend,
-}
-
-pub trait Backend: std::fmt::Debug + Send {
-    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>>;
 
-    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>>;
+    /// Index the field at `path` of values of `T`, e.g. `"position.x"`, to speed up [`Store::find_by_field`].
+    ///
+    /// SQLite can only index stores using [`Encoding::Json`], and in-memory stores can't index [`Encoding::Bincode`].
+    /// Other backends don't support indexes. Without an index, lookups still work but read every value of `T`.
+    pub fn create_index<T: Vanth>(&mut self, path: &str) -> Result<()> {
+        let path = FieldPath::parse(path)?;
+        for ty in self.resolve_ty(T::ty()) {
+            self.backend.create_index(ty, &path, self.encoding)?;
+        }
+        Ok(())
+    }
 
-    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()>;
+    /// Every value of `T` whose field at `path` equals `value`, in hash order. See [`crate::query`] for how fields are
+    /// compared.
+    pub fn find_by_field<T: Vanth + Serialize + DeserializeOwned>(
+        &mut self,
+        path: &str,
+        value: impl Serialize,
+    ) -> Result<Vec<(ContentHash, T)>> {
+        let path = FieldPath::parse(path)?;
+        let value = FieldValue::from_json(&serde_json::to_value(value)?);
+        let mut found = BTreeMap::new();
+        for ty in self.resolve_ty(T::ty()) {
+            let Some(values) = self.backend.find_by_field(ty, &path, &value, self.encoding)? else {
+                return self.scan_by_field(&path, &value);
+            };
+            for (content_hash, data) in values {
+                found.entry(content_hash).or_insert(data);
+            }
+        }
+        found
+            .into_iter()
+            .map(|(content_hash, data)| Ok((content_hash, decode(self.encoding, content_hash, &data)?)))
+            .collect()
+    }
 
-    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()>;
+    /// [`Store::find_by_field`] for backends that can't look values up by field themselves.
+    fn scan_by_field<T: Vanth + Serialize + DeserializeOwned>(
+        &mut self,
+        path: &FieldPath,
+        value: &FieldValue,
+    ) -> Result<Vec<(ContentHash, T)>> {
+        let mut found = Vec::new();
+        for item in self.iter_of_type::<T>(IterParams::default()) {
+            let (content_hash, candidate) = item?;
+            if FieldValue::of(&serde_json::to_value(&candidate)?, path) == *value {
+                found.push((content_hash, candidate));
+            }
+        }
+        Ok(found)
+    }
 
-    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()>;
-}
+    /// The values of `T` matching `query`. See [`crate::query`] for how fields are compared.
+    pub fn query<T: Vanth + Serialize + DeserializeOwned>(&mut self, query: &Query) -> Result<Vec<(ContentHash, T)>> {
+        // Values stored under aliases have to be ordered together with the rest, so they're matched here.
+        if !self.aliases.contains_key(&T::ty().to_string())
+            && let Some(values) = self.backend.query(T::ty(), query, self.encoding)?
+        {
+            return values
+                .into_iter()
+                .map(|(content_hash, data)| Ok((content_hash, decode(self.encoding, content_hash, &data)?)))
+                .collect();
+        }
 
-/// One table per type. Keys and values are both blobs.
-#[derive(Debug)]
-pub struct Sqlite {
-    connection: Connection,
-}
+        let mut matches = Vec::new();
+        for item in self.iter_of_type::<T>(IterParams::default()) {
+            let (content_hash, candidate) = item?;
+            let value = serde_json::to_value(&candidate)?;
+            if query.matches(&value) {
+                matches.push((content_hash, value, candidate));
+            }
+        }
+        Ok(query.arrange(matches))
+    }
 
-impl Sqlite {
-    // TODO: Use this instead of directly constructing the connection in `Store`.
-    pub fn new(path: PathBuf) -> Result<Self> {
-        use rusqlite::OpenFlags;
-        // Remove the `SQLITE_OPEN_CREATE` flag because we do not want to create databases if they don't exist.
-        let connection = Connection::open_with_flags(
-            path,
-            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
-        )?;
-        Ok(Self { connection })
+    /// Every type with a value stored under `content_hash`. Aliases are not resolved.
+    pub fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        let mut tys = self.backend.find_by_hash(content_hash)?;
+        tys.sort_by_cached_key(|ty| ty.to_string());
+        Ok(tys)
     }
 
-    fn table_name(ty: &Ty) -> String {
-        format!("ty_{}", ty.to_string())
+    /// Delete the value stored under `content_hash` from every type that has it, returning those types. Either every
+    /// copy is deleted or, if any delete fails, none are.
+    pub fn delete_everywhere(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        self.transaction(|tx| tx.delete_everywhere(content_hash))
     }
-}
 
-impl Backend for Sqlite {
-    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
-        let table_name = Self::table_name(&ty);
-        let query = format!("SELECT content FROM \"{}\" WHERE content_hash = :hash", table_name);
+    /// Every type the store holds a table for, including empty ones, sorted by name.
+    pub fn list_types(&mut self) -> Result<Vec<TypeInfo>> {
+        let mut types = self.backend.list_types()?;
+        types.sort_by_cached_key(|info| info.ty.to_string());
+        Ok(types)
+    }
 
-        match self
-            .connection
-            .query_row(&query, named_params! {":hash": content_hash.hash.as_slice()}, |row| {
-                row.get::<_, Vec<u8>>(0)
-            }) {
-            Ok(content) => Ok(Some(content)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+    /// What the store holds of `T`, or `None` if it has no table for it.
+    pub fn type_info<T: Vanth>(&mut self) -> Result<Option<TypeInfo>> {
+        self.backend.type_info(T::ty())
     }
 
-    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
-        let transaction = self.connection.transaction()?;
-        let table_name = Self::table_name(&ty);
-        let query = format!("SELECT content_hash, content FROM \"{}\"", table_name);
+    pub fn type_info_raw(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        self.backend.type_info(ty)
+    }
 
-        let mut statement = match transaction.prepare(&query).map_err(Into::into) {
-            Err(Error::SqliteTableDoesNotExist { .. }) => return Ok(Vec::new()),
-            other => other?,
+    /// Check that every value in the store hashes to the hash it is stored under.
+    ///
+    /// Values can only be re-hashed if their type is registered with `verifier`. Values of other types are not checked,
+    /// and their types are listed in [`VerifyReport::unchecked_tys`].
+    pub fn verify(&mut self, verifier: &Verifier) -> Result<VerifyReport> {
+        let mut report = VerifyReport {
+            foreign_tables: self.backend.foreign_tables()?,
+            ..Default::default()
         };
 
-        let rows = statement.query_map([], |row| {
-            let hash_bytes: Vec<u8> = row.get(0)?;
-            let content: Vec<u8> = row.get(1)?;
-            let mut hash_array = [0u8; 32];
-            hash_array.copy_from_slice(&hash_bytes);
-            Ok((ContentHash { hash: hash_array }, content))
-        })?;
+        let mut by_storage_name: BTreeMap<String, Vec<Ty>> = BTreeMap::new();
+        for (ty, _) in verifier.rehashers.values() {
+            by_storage_name
+                .entry(self.backend.storage_name(ty))
+                .or_default()
+                .push(ty.clone());
+        }
+        report.duplicate_tables = by_storage_name.into_values().filter(|tys| tys.len() > 1).collect();
 
-        let mut results = Vec::new();
-        for row in rows {
-            results.push(row?);
+        let encoding = self.encoding;
+        for ty in self.backend.list_tys()? {
+            let Some((_, rehash)) = verifier.rehashers.get(&ty.to_string()) else {
+                report.unchecked_tys.push(ty);
+                continue;
+            };
+            for item in self.iter_of_type_raw(ty.clone(), IterParams::default()) {
+                let (content_hash, data) = item?;
+                report.checked += 1;
+                match rehash(encoding, &data) {
+                    Ok(actual) if actual != content_hash => report.mismatches.push(HashMismatch {
+                        ty: ty.clone(),
+                        expected: content_hash,
+                        actual,
+                    }),
+                    Ok(_) => {}
+                    Err(message) => report.undecodable.push(Undecodable {
+                        ty: ty.clone(),
+                        content_hash,
+                        message,
+                    }),
+                }
+            }
         }
+        Ok(report)
+    }
 
-        drop(statement);
-        transaction.commit()?;
-        
-        Ok(results)
+    /// Begin a [`Transaction`]. Nothing written or deleted through it is visible outside of it until
+    /// [`Transaction::commit`] is called. Dropping it without committing rolls it back.
+    pub fn begin(&mut self) -> Result<Transaction<'_>> {
+        self.backend.begin_transaction()?;
+        Ok(Transaction {
+            store: self,
+            finished: false,
+        })
     }
 
-    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
-        let table_name = Self::table_name(&ty);
-        let create_table_query = format!(
-            "CREATE TABLE IF NOT EXISTS \"{}\" (
-                content_hash BLOB PRIMARY KEY,
-                content BLOB NOT NULL
-            )",
-            table_name
-        );
-        self.connection.execute(&create_table_query, [])?;
-        let query = format!(
-            "INSERT OR REPLACE INTO \"{}\" (content_hash, content) VALUES (:hash, :content)",
-            table_name
-        );
-        self.connection.execute(
-            &query,
-            named_params! {":hash": content_hash.hash.as_slice(), ":content": content},
-        )?;
-        Ok(())
+    /// Run `f` inside a [`Transaction`], committing it if `f` returns `Ok` and rolling it back if `f` returns `Err` or
+    /// panics.
+    pub fn transaction<R, E: From<Error>>(
+        &mut self,
+        f: impl FnOnce(&mut Transaction<'_>) -> std::result::Result<R, E>,
+    ) -> std::result::Result<R, E> {
+        let mut transaction = self.begin()?;
+        let result = f(&mut transaction)?;
+        transaction.commit()?;
+        Ok(result)
     }
+}
 
-    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
-        let table_name = Self::table_name(&ty);
-        let query = format!("DELETE FROM \"{}\" WHERE content_hash = :hash", table_name);
-        self.connection
-            .execute(&query, named_params! {":hash": content_hash.hash.as_slice()})?;
-        Ok(())
+/// What a store holds of one type, as reported by [`Store::list_types`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TypeInfo {
+    pub ty: Ty,
+    /// The number of values stored.
+    pub count: u64,
+    /// The total size of the stored values in bytes, as encoded.
+    pub total_bytes: u64,
+    /// When a value of the type was first written, in milliseconds since the Unix epoch, if known.
+    pub first_write: Option<u64>,
+    /// When a value of the type was last written, in milliseconds since the Unix epoch, if known.
+    pub last_write: Option<u64>,
+}
+
+/// Milliseconds since the Unix epoch, as recorded in [`TypeInfo`].
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+/// An iterator over the raw values of a type, created by [`Store::iter_of_type_raw`].
+///
+/// Values are fetched from the backend a page at a time. Each page resumes after the last hash of the previous one, so
+/// values written or deleted during iteration do not cause others to be skipped or repeated.
+#[derive(Debug)]
+pub struct TypeIter<'a> {
+    store: &'a mut Store,
+    /// The types whose values are merged, in order of precedence when a value is stored under more than one.
+    tys: Vec<Ty>,
+    page: VecDeque<(ContentHash, Vec<u8>)>,
+    after: Option<ContentHash>,
+    /// Values still to be skipped. Only applied to the first page fetched.
+    offset: usize,
+    remaining: Option<usize>,
+    page_size: usize,
+    exhausted: bool,
+}
+
+impl TypeIter<'_> {
+    /// The hash of the last value yielded, or the starting point if none have been. Pass this as
+    /// [`IterParams::after`] to resume iteration later.
+    pub fn cursor(&self) -> Option<ContentHash> {
+        self.after
     }
 
-    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
-        let table_name = Self::table_name(&ty);
-        let query = format!("DROP TABLE IF EXISTS \"{}\"", table_name);
-        self.connection.execute(&query, [])?;
+    fn fetch_page(&mut self) -> Result<()> {
+        let limit = self
+            .remaining
+            .map_or(self.page_size, |remaining| remaining.min(self.page_size));
+        let page = if let [ty] = self.tys.as_slice() {
+            self.store
+                .backend
+                .get_page_of_ty(ty.clone(), self.after, self.offset, limit)?
+        } else {
+            // The first `offset + limit` values overall are among the first `offset + limit` values of each type.
+            let wanted = self.offset.saturating_add(limit);
+            let mut merged = BTreeMap::new();
+            for ty in &self.tys {
+                for (content_hash, data) in self.store.backend.get_page_of_ty(ty.clone(), self.after, 0, wanted)? {
+                    merged.entry(content_hash).or_insert(data);
+                }
+            }
+            merged.into_iter().take(wanted).skip(self.offset).collect()
+        };
+        // When merging, fewer values than asked for also means every type has run out.
+        self.exhausted = page.len() < limit;
+        self.offset = 0;
+        self.page = page.into();
         Ok(())
     }
 }
 
-/// In-memory storage with one table per type.
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Memory {
-    tables: HashMap<Ty, HashMap<ContentHash, Vec<u8>>>,
-}
+impl Iterator for TypeIter<'_> {
+    type Item = Result<(ContentHash, Vec<u8>)>;
 
-impl Memory {
-    pub fn new() -> Self {
-        Self { tables: HashMap::new() }
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+        if self.page.is_empty() {
+            if self.exhausted {
+                return None;
+            }
+            if let Err(e) = self.fetch_page() {
+                self.exhausted = true;
+                return Some(Err(e));
+            }
+        }
+        let (content_hash, data) = self.page.pop_front()?;
+        self.after = Some(content_hash);
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= 1;
+        }
+        Some(Ok((content_hash, data)))
     }
 }
 
-impl Backend for Memory {
-    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
-        Ok(self.tables.get(&ty).and_then(|table| table.get(&content_hash)).cloned())
-    }
+/// The types [`Store::verify`] knows how to re-hash.
+#[derive(Clone, Debug, Default)]
+pub struct Verifier {
+    /// Keyed by the string form of the type, since types read back from a backend may be split into path segments
+    /// differently.
+    pub(crate) rehashers: HashMap<String, (Ty, Rehash)>,
+}
 
-    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
-        Ok(self
-            .tables
-            .get(&ty)
-            .map(|table| table.iter().map(|(k, v)| (*k, v.clone())).collect())
-            .unwrap_or_else(Vec::new))
+type Rehash = fn(Encoding, &[u8]) -> std::result::Result<ContentHash, String>;
+
+impl Verifier {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
-        self.tables
-            .entry(ty)
-            .or_insert_with(HashMap::new)
-            .insert(content_hash, content);
-        Ok(())
+    pub fn with<T: Vanth + Serialize + DeserializeOwned>(mut self) -> Self {
+        self.register::<T>();
+        self
     }
 
-    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
-        if let Some(table) = self.tables.get_mut(&ty) {
-            table.remove(&content_hash);
+    pub fn register<T: Vanth + Serialize + DeserializeOwned>(&mut self) {
+        fn rehash<T: Serialize + DeserializeOwned>(
+            encoding: Encoding,
+            data: &[u8],
+        ) -> std::result::Result<ContentHash, String> {
+            encoding.try_decode::<T>(data).map(|value| hash(&value))
         }
-        Ok(())
+        self.rehashers.insert(T::ty().to_string(), (T::ty(), rehash::<T>));
+    }
+}
+
+/// The problems found by [`Store::verify`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct VerifyReport {
+    /// Number of values that were re-hashed.
+    pub checked: usize,
+    pub mismatches: Vec<HashMismatch>,
+    pub undecodable: Vec<Undecodable>,
+    /// Types in the store that are not registered with the [`Verifier`], so their values were not checked.
+    pub unchecked_tys: Vec<Ty>,
+    /// Groups of registered types that the backend would store in the same table, e.g. because SQLite table names are
+    /// case-insensitive.
+    pub duplicate_tables: Vec<Vec<Ty>>,
+    /// Tables in the backend's storage that don't belong to any type.
+    pub foreign_tables: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Whether no problems were found. Unchecked types are not counted as problems.
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+            && self.undecodable.is_empty()
+            && self.duplicate_tables.is_empty()
+            && self.foreign_tables.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct HashMismatch {
+    pub ty: Ty,
+    /// The hash the value is stored under.
+    pub expected: ContentHash,
+    /// The hash of the stored value.
+    pub actual: ContentHash,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Undecodable {
+    pub ty: Ty,
+    pub content_hash: ContentHash,
+    pub message: String,
+}
+
+/// A set of writes and deletes on a [`Store`] which are either all applied or not applied at all.
+///
+/// Created with [`Store::begin`] or [`Store::transaction`]. Reads made through a transaction see its own uncommitted
+/// changes.
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    store: &'a mut Store,
+    finished: bool,
+}
+
+impl Transaction<'_> {
+    pub(crate) fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        self.store.backend.get_metadata(key)
+    }
+
+    pub(crate) fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        self.store.backend.set_metadata(key, value)
+    }
+
+    pub fn get_from_hash<T: Vanth + Serialize + DeserializeOwned>(
+        &mut self,
+        content_hash: ContentHash,
+    ) -> Result<Option<T>> {
+        self.store.get_from_hash(content_hash)
+    }
+
+    pub fn get_from_hash_raw(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        self.store.get_from_hash_raw(ty, content_hash)
+    }
+
+    pub fn get_all_of_type<T: Vanth + DeserializeOwned>(&mut self) -> Result<Vec<(ContentHash, T)>> {
+        self.store.get_all_of_type()
+    }
+
+    pub fn get_all_contents_of_type<T: Vanth>(&mut self) -> Result<Vec<ComponentContents<T>>> {
+        self.store.get_all_contents_of_type()
+    }
+
+    pub fn get_all_of_type_raw(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        self.store.get_all_of_type_raw(ty)
+    }
+
+    pub fn iter_of_type<T: Vanth + DeserializeOwned>(
+        &mut self,
+        params: IterParams,
+    ) -> impl Iterator<Item = Result<(ContentHash, T)>> + '_ {
+        self.store.iter_of_type(params)
+    }
+
+    pub fn iter_of_type_raw(&mut self, ty: Ty, params: IterParams) -> TypeIter<'_> {
+        self.store.iter_of_type_raw(ty, params)
+    }
+
+    pub fn write<T: Vanth + Serialize>(&mut self, value: &T) -> Result<ContentHash> {
+        self.store.write(value)
+    }
+
+    pub fn write_raw(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        self.store.write_raw(ty, content_hash, content)
+    }
+
+    pub fn delete<T: Vanth>(&mut self, content_hash: ContentHash) -> Result<()> {
+        self.store.delete::<T>(content_hash)
+    }
+
+    pub fn delete_all<T: Vanth>(&mut self) -> Result<()> {
+        self.store.delete_all::<T>()
+    }
+
+    pub fn delete_raw(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        self.store.delete_raw(ty, content_hash)
+    }
+
+    pub fn delete_all_raw(&mut self, ty: Ty) -> Result<()> {
+        self.store.delete_all_raw(ty)
+    }
+
+    pub fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        self.store.find_by_hash(content_hash)
+    }
+
+    pub fn create_index<T: Vanth>(&mut self, path: &str) -> Result<()> {
+        self.store.create_index::<T>(path)
+    }
+
+    pub fn query<T: Vanth + Serialize + DeserializeOwned>(&mut self, query: &Query) -> Result<Vec<(ContentHash, T)>> {
+        self.store.query(query)
+    }
+
+    pub fn find_by_field<T: Vanth + Serialize + DeserializeOwned>(
+        &mut self,
+        path: &str,
+        value: impl Serialize,
+    ) -> Result<Vec<(ContentHash, T)>> {
+        self.store.find_by_field(path, value)
+    }
+
+    pub fn delete_everywhere(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        let tys = self.store.find_by_hash(content_hash)?;
+        for ty in &tys {
+            self.store.backend.delete_by_hash(ty.clone(), content_hash)?;
+        }
+        Ok(tys)
+    }
+
+    pub fn list_types(&mut self) -> Result<Vec<TypeInfo>> {
+        self.store.list_types()
+    }
+
+    pub fn type_info<T: Vanth>(&mut self) -> Result<Option<TypeInfo>> {
+        self.store.type_info::<T>()
+    }
+
+    pub fn type_info_raw(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        self.store.type_info_raw(ty)
+    }
+
+    /// Apply all changes made in this transaction.
+    pub fn commit(mut self) -> Result<()> {
+        // If committing fails the transaction is still open, so leave it for `drop` to roll back.
+        self.store.backend.commit_transaction()?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Discard all changes made in this transaction.
+    pub fn rollback(mut self) -> Result<()> {
+        self.finished = true;
+        self.store.backend.rollback_transaction()
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished
+            && let Err(e) = self.store.backend.rollback_transaction()
+        {
+            warn!("Failed to roll back dropped transaction: {:?}", e);
+        }
+    }
+}
+
+/// A read-through cache in front of another [`Backend`], holding at most `size_limit_bytes` of values read with
+/// [`Backend::get_from_hash`] and evicting the least recently used first.
+///
+/// Values are stored under the hash of their contents and so never change, which means cached values only have to be
+/// invalidated when they are deleted. Deletes made other than through the cache, such as by another process using the
+/// same database, are not seen.
+#[derive(Debug)]
+pub struct Cache {
+    size_limit_bytes: u64,
+    backend: Box<dyn Backend>,
+    /// Keyed by storage name rather than type, so that types sharing a table share entries.
+    entries: HashMap<(String, ContentHash), CacheEntry>,
+    /// The key of every entry by when it was last used, least recent first.
+    recency: BTreeMap<u64, (String, ContentHash)>,
+    clock: u64,
+    stats: CacheStats,
+    /// Values read inside a transaction are not cached, as the transaction may be rolled back.
+    in_transaction: bool,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    data: Vec<u8>,
+    last_used: u64,
+}
+
+/// How well a [`Cache`] is doing, as reported by [`Store::cache_stats`].
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries removed to make room for others.
+    pub evictions: u64,
+    pub entries: u64,
+    /// The total size of the cached values.
+    pub bytes: u64,
+}
+
+impl Cache {
+    pub fn new(backend: Box<dyn Backend>, size_limit_bytes: u64) -> Self {
+        Self {
+            size_limit_bytes,
+            backend,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            stats: CacheStats::default(),
+            in_transaction: false,
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    fn key(&self, ty: &Ty, content_hash: ContentHash) -> (String, ContentHash) {
+        (self.backend.storage_name(ty), content_hash)
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn insert(&mut self, key: (String, ContentHash), data: Vec<u8>) {
+        let size = data.len() as u64;
+        if size > self.size_limit_bytes {
+            return;
+        }
+        while self.stats.bytes + size > self.size_limit_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.stats.bytes -= entry.data.len() as u64;
+                self.stats.entries -= 1;
+                self.stats.evictions += 1;
+            }
+        }
+        let last_used = self.tick();
+        self.recency.insert(last_used, key.clone());
+        self.stats.bytes += size;
+        self.stats.entries += 1;
+        self.entries.insert(key, CacheEntry { data, last_used });
+    }
+
+    fn remove(&mut self, key: &(String, ContentHash)) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+            self.stats.bytes -= entry.data.len() as u64;
+            self.stats.entries -= 1;
+        }
+    }
+}
+
+impl Backend for Cache {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        let key = self.key(&ty, content_hash);
+        let now = self.tick();
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.recency.remove(&entry.last_used);
+            self.recency.insert(now, key);
+            entry.last_used = now;
+            self.stats.hits += 1;
+            return Ok(Some(entry.data.clone()));
+        }
+        self.stats.misses += 1;
+        let data = self.backend.get_from_hash(ty, content_hash)?;
+        // Absent values aren't cached, since they may be written later.
+        if let Some(data) = &data
+            && !self.in_transaction
+        {
+            self.insert(key, data.clone());
+        }
+        Ok(data)
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        self.backend.get_all_of_ty(ty)
+    }
+
+    fn get_page_of_ty(
+        &mut self,
+        ty: Ty,
+        after: Option<ContentHash>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        self.backend.get_page_of_ty(ty, after, offset, limit)
+    }
+
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        self.backend.write(ty, content_hash, content)
+    }
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        let key = self.key(&ty, content_hash);
+        self.remove(&key);
+        self.backend.delete_by_hash(ty, content_hash)
+    }
+
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
+        let name = self.backend.storage_name(&ty);
+        let keys: Vec<_> = self.entries.keys().filter(|(n, _)| *n == name).cloned().collect();
+        for key in &keys {
+            self.remove(key);
+        }
+        self.backend.delete_all_of_ty(ty)
+    }
+
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        self.backend.list_tys()
+    }
+
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        self.backend.type_info(ty)
+    }
+
+    fn list_types(&mut self) -> Result<Vec<TypeInfo>> {
+        self.backend.list_types()
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        self.backend.find_by_hash(content_hash)
+    }
+
+    fn create_index(&mut self, ty: Ty, path: &FieldPath, encoding: Encoding) -> Result<()> {
+        self.backend.create_index(ty, path, encoding)
+    }
+
+    fn drop_indexes(&mut self, ty: Ty) -> Result<()> {
+        self.backend.drop_indexes(ty)
+    }
+
+    fn find_by_field(
+        &mut self,
+        ty: Ty,
+        path: &FieldPath,
+        value: &FieldValue,
+        encoding: Encoding,
+    ) -> Result<Option<Values>> {
+        self.backend.find_by_field(ty, path, value, encoding)
+    }
+
+    fn query(&mut self, ty: Ty, query: &Query, encoding: Encoding) -> Result<Option<Values>> {
+        self.backend.query(ty, query, encoding)
+    }
+
+    fn storage_name(&self, ty: &Ty) -> String {
+        self.backend.storage_name(ty)
+    }
+
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        self.backend.foreign_tables()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.stats)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.backend.flush()
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        self.backend.get_metadata(key)
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        self.backend.set_metadata(key, value)
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        self.backend.begin_transaction()?;
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        self.backend.commit_transaction()?;
+        self.in_transaction = false;
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.backend.rollback_transaction()?;
+        self.in_transaction = false;
+        Ok(())
+    }
+}
+
+pub trait Backend: std::fmt::Debug + Send {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>>;
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>>;
+
+    /// Get up to `limit` values of `ty` in order of their hashes, starting after the hash `after` if provided and
+    /// skipping the first `offset` values.
+    fn get_page_of_ty(
+        &mut self,
+        ty: Ty,
+        after: Option<ContentHash>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(ContentHash, Vec<u8>)>>;
+
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()>;
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()>;
+
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()>;
+
+    /// All types that have a table, including empty ones.
+    fn list_tys(&mut self) -> Result<Vec<Ty>>;
+
+    /// Statistics about the values of `ty`, or `None` if it has no table.
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>>;
+
+    /// [`Backend::type_info`] for every type in [`Backend::list_tys`].
+    fn list_types(&mut self) -> Result<Vec<TypeInfo>> {
+        let mut types = Vec::new();
+        for ty in self.list_tys()? {
+            types.extend(self.type_info(ty)?);
+        }
+        Ok(types)
+    }
+
+    /// Every type with a value stored under `content_hash`.
+    ///
+    /// The default implementation looks the hash up in each type's table in turn.
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        let mut tys = Vec::new();
+        for ty in self.list_tys()? {
+            if self.get_from_hash(ty.clone(), content_hash)?.is_some() {
+                tys.push(ty);
+            }
+        }
+        Ok(tys)
+    }
+
+    /// Index the field at `path` of values of `ty`, if the backend can index values in `encoding`. Backends that can't
+    /// do nothing, and [`Store::find_by_field`] reads every value instead.
+    fn create_index(&mut self, ty: Ty, path: &FieldPath, encoding: Encoding) -> Result<()> {
+        Ok(())
+    }
+
+    /// Drop every index created with [`Backend::create_index`] on values of `ty`.
+    fn drop_indexes(&mut self, ty: Ty) -> Result<()> {
+        Ok(())
+    }
+
+    /// The values of `ty` whose field at `path` equals `value`, in hash order, or `None` if the backend can't look values
+    /// in `encoding` up by field.
+    fn find_by_field(
+        &mut self,
+        ty: Ty,
+        path: &FieldPath,
+        value: &FieldValue,
+        encoding: Encoding,
+    ) -> Result<Option<Values>> {
+        Ok(None)
+    }
+
+    /// The values of `ty` matching `query`, or `None` if the backend can't query values in `encoding` and they have to be
+    /// read and matched by the caller.
+    fn query(&mut self, ty: Ty, query: &Query, encoding: Encoding) -> Result<Option<Values>> {
+        Ok(None)
+    }
+
+    /// The name of the table `ty` is stored in. Types with the same storage name share a table.
+    fn storage_name(&self, ty: &Ty) -> String {
+        ty.to_string()
+    }
+
+    /// Tables in the backend's storage that were not created by it.
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Statistics for backends that are a [`Cache`].
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Move changes held by the backend into permanent storage, for backends such as [`crate::overlay::Overlay`] that
+    /// hold them separately.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get a store-wide setting such as the [`Encoding`].
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>>;
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()>;
+
+    /// Start staging changes. Only one transaction can be open at a time.
+    fn begin_transaction(&mut self) -> Result<()>;
+
+    fn commit_transaction(&mut self) -> Result<()>;
+
+    fn rollback_transaction(&mut self) -> Result<()>;
+}
+
+/// One table per type. Keys and values are both blobs.
+#[derive(Debug)]
+pub struct Sqlite {
+    connection: Connection,
+}
+
+impl Sqlite {
+    // TODO: Use this instead of directly constructing the connection in `Store`.
+    pub fn new(path: PathBuf) -> Result<Self> {
+        use rusqlite::OpenFlags;
+        // Remove the `SQLITE_OPEN_CREATE` flag because we do not want to create databases if they don't exist.
+        let connection = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        Ok(Self { connection })
+    }
+
+    /// Open the database at `path` with the flags and connection settings in `params`.
+    pub fn open(path: PathBuf, params: &StoreParams) -> Result<Self> {
+        use rusqlite::OpenFlags;
+        // Base flags for URI handling and disabling mutexes.
+        let mut flags = OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+
+        // Read‑only takes precedence over read‑write.
+        if params.read_only {
+            flags |= OpenFlags::SQLITE_OPEN_READ_ONLY;
+        } else {
+            flags |= OpenFlags::SQLITE_OPEN_READ_WRITE;
+        }
+
+        // Create the file if allowed. SQLite rejects creating a database read-only.
+        if params.create_if_not_exists && !params.read_only {
+            flags |= OpenFlags::SQLITE_OPEN_CREATE;
+        }
+
+        // Open the SQLite connection with the computed flags.
+        let connection = Connection::open_with_flags(path, flags)?;
+        Self::configure(&connection, params)?;
+        Ok(Self { connection })
+    }
+
+    /// Apply the connection settings in `params`.
+    fn configure(connection: &Connection, params: &StoreParams) -> Result<()> {
+        // Set first, so that changing the journal mode also waits for other connections.
+        connection.busy_timeout(params.busy_timeout)?;
+        if params.sqlite_cache_size_bytes > 0 {
+            // Negative sizes are in KiB rather than pages.
+            let kib = (params.sqlite_cache_size_bytes / 1024).max(1) as i64;
+            connection.pragma_update(None, "cache_size", -kib)?;
+        }
+        connection.pragma_update(None, "synchronous", params.synchronous.name())?;
+        if !params.read_only {
+            // Returns the new mode, which stays `memory` for in-memory databases.
+            connection.pragma_update_and_check(None, "journal_mode", params.journal_mode.name(), |_| Ok(()))?;
+        }
+        Ok(())
+    }
+
+    /// Key-value table holding metadata. Type tables are all prefixed with `ty_`, so this can't clash with them.
+    const METADATA_TABLE: &'static str = "vanth_metadata";
+
+    /// Count, size and write times of each type, kept up to date as values are written and deleted.
+    const CATALOG_TABLE: &'static str = "vanth_catalog";
+
+    /// The types each content hash is stored under, kept up to date as values are written and deleted.
+    const HASH_INDEX_TABLE: &'static str = "vanth_hash_index";
+
+    /// Set once the hash index covers every value, including those written before it existed.
+    const HASH_INDEX_METADATA_KEY: &'static str = "hash_index";
+
+    fn create_internal_tables(&mut self) -> Result<()> {
+        self.connection.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS \"{catalog}\" (
+                ty TEXT PRIMARY KEY,
+                count INTEGER NOT NULL,
+                total_bytes INTEGER NOT NULL,
+                first_write INTEGER,
+                last_write INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS \"{hash_index}\" (
+                content_hash BLOB NOT NULL,
+                ty TEXT NOT NULL,
+                PRIMARY KEY (content_hash, ty)
+            ) WITHOUT ROWID;
+            CREATE INDEX IF NOT EXISTS \"{hash_index}_ty\" ON \"{hash_index}\" (ty);",
+            catalog = Self::CATALOG_TABLE,
+            hash_index = Self::HASH_INDEX_TABLE,
+        ))?;
+        Ok(())
+    }
+
+    /// Add every value to the hash index, so that values written before it existed can be found.
+    fn rebuild_hash_index(&mut self) -> Result<()> {
+        self.create_internal_tables()?;
+        for ty in self.list_tys()? {
+            self.connection.execute(
+                &format!(
+                    "INSERT OR IGNORE INTO \"{}\" (content_hash, ty) SELECT content_hash, :ty FROM \"{}\"",
+                    Self::HASH_INDEX_TABLE,
+                    Self::table_name(&ty)
+                ),
+                named_params! {":ty": ty.to_string()},
+            )?;
+        }
+        self.set_metadata(Self::HASH_INDEX_METADATA_KEY, "complete")
+    }
+
+    fn table_name(ty: &Ty) -> String {
+        format!("ty_{}", ty)
+    }
+
+    fn create_ty_table(&mut self, ty: &Ty) -> Result<()> {
+        let create_table_query = format!(
+            "CREATE TABLE IF NOT EXISTS \"{}\" (
+                content_hash BLOB PRIMARY KEY,
+                content BLOB NOT NULL
+            )",
+            Self::table_name(ty)
+        );
+        self.connection.execute(&create_table_query, [])?;
+        Ok(())
+    }
+
+    /// The SQL expression for the field at `path` of the `content` column. Indexes are only used by queries with exactly
+    /// the same expression, so the path is inlined rather than bound.
+    fn field_expression(path: &FieldPath) -> String {
+        // `FieldPath` only allows characters that need no escaping. The cast stops SQLite treating the blob as JSONB.
+        format!("json_extract(CAST(content AS TEXT), '{}')", path.json_path())
+    }
+
+    fn sql_value(value: &FieldValue) -> rusqlite::types::Value {
+        match value {
+            FieldValue::Null => rusqlite::types::Value::Null,
+            FieldValue::Integer(integer) => rusqlite::types::Value::Integer(*integer),
+            FieldValue::Real(real) => rusqlite::types::Value::Real(*real),
+            FieldValue::Text(text) => rusqlite::types::Value::Text(text.clone()),
+        }
+    }
+
+    /// `predicate` as an SQL condition, with its values appended to `params`.
+    fn predicate_sql(predicate: &Predicate, params: &mut Vec<rusqlite::types::Value>) -> String {
+        let join = |predicates: &[Predicate], params: &mut Vec<_>, operator: &str, empty: &str| {
+            if predicates.is_empty() {
+                return empty.to_string();
+            }
+            predicates
+                .iter()
+                .map(|predicate| format!("({})", Self::predicate_sql(predicate, params)))
+                .collect::<Vec<_>>()
+                .join(operator)
+        };
+        match predicate {
+            Predicate::Compare {
+                path,
+                comparison,
+                value,
+            } => {
+                params.push(Self::sql_value(value));
+                format!("{} {} ?", Self::field_expression(path), comparison.sql())
+            }
+            Predicate::IsNull(path) => format!("{} IS NULL", Self::field_expression(path)),
+            Predicate::All(predicates) => join(predicates, params, " AND ", "1"),
+            Predicate::Any(predicates) => join(predicates, params, " OR ", "0"),
+            Predicate::Not(predicate) => format!("NOT ({})", Self::predicate_sql(predicate, params)),
+        }
+    }
+
+    /// Make sure the catalog has an entry for `ty`, counting any values written before the catalog existed.
+    fn ensure_catalog_entry(&mut self, ty: &Ty) -> Result<()> {
+        self.create_internal_tables()?;
+        let exists = self
+            .connection
+            .query_row(
+                &format!("SELECT 1 FROM \"{}\" WHERE ty = :ty", Self::CATALOG_TABLE),
+                named_params! {":ty": ty.to_string()},
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if exists {
+            return Ok(());
+        }
+        self.connection.execute(
+            &format!(
+                "INSERT OR IGNORE INTO \"{}\" (ty, count, total_bytes)
+                SELECT :ty, count(*), COALESCE(sum(length(content)), 0) FROM \"{}\"",
+                Self::CATALOG_TABLE,
+                Self::table_name(ty)
+            ),
+            named_params! {":ty": ty.to_string()},
+        )?;
+        Ok(())
+    }
+
+    /// Run `f` atomically. Outside a transaction, the write lock is taken up front, so that no other connection writes
+    /// between the reads and writes of `f`. Inside one, `f` runs in a savepoint.
+    fn atomically<R>(&mut self, f: impl FnOnce(&mut Self) -> Result<R>) -> Result<R> {
+        let (begin, commit, rollback) = if self.connection.is_autocommit() {
+            ("BEGIN IMMEDIATE", "COMMIT", "ROLLBACK")
+        } else {
+            (
+                "SAVEPOINT vanth_change",
+                "RELEASE vanth_change",
+                "ROLLBACK TO vanth_change; RELEASE vanth_change",
+            )
+        };
+        self.connection.execute_batch(begin)?;
+        let result = f(self).and_then(|result| {
+            self.connection.execute_batch(commit)?;
+            Ok(result)
+        });
+        if result.is_err()
+            && let Err(e) = self.connection.execute_batch(rollback)
+        {
+            warn!("Failed to roll back a failed change: {:?}", e);
+        }
+        result
+    }
+
+    /// The size of the stored value, if there is one.
+    fn stored_len(&mut self, ty: &Ty, content_hash: ContentHash) -> Result<Option<i64>> {
+        let query = format!(
+            "SELECT length(content) FROM \"{}\" WHERE content_hash = :hash",
+            Self::table_name(ty)
+        );
+        Ok(self
+            .connection
+            .query_row(&query, named_params! {":hash": content_hash.hash.as_slice()}, |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn write_row(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        let table_name = Self::table_name(&ty);
+        self.create_ty_table(&ty)?;
+        self.ensure_catalog_entry(&ty)?;
+        let replaced_len = self.stored_len(&ty, content_hash)?;
+        let len = content.len() as i64;
+        let query = format!(
+            "INSERT OR REPLACE INTO \"{}\" (content_hash, content) VALUES (:hash, :content)",
+            table_name
+        );
+        self.connection.execute(
+            &query,
+            named_params! {":hash": content_hash.hash.as_slice(), ":content": content},
+        )?;
+        self.connection.execute(
+            &format!(
+                "UPDATE \"{}\" SET
+                    count = count + :added,
+                    total_bytes = total_bytes + :bytes,
+                    first_write = COALESCE(first_write, :now),
+                    last_write = :now
+                WHERE ty = :ty",
+                Self::CATALOG_TABLE
+            ),
+            named_params! {
+                ":added": replaced_len.is_none() as i64,
+                ":bytes": len - replaced_len.unwrap_or(0),
+                ":now": unix_millis(SystemTime::now()) as i64,
+                ":ty": ty.to_string(),
+            },
+        )?;
+        self.connection.execute(
+            &format!(
+                "INSERT OR IGNORE INTO \"{}\" (content_hash, ty) VALUES (:hash, :ty)",
+                Self::HASH_INDEX_TABLE
+            ),
+            named_params! {":hash": content_hash.hash.as_slice(), ":ty": ty.to_string()},
+        )?;
+        Ok(())
+    }
+
+    fn delete_row(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        self.ensure_catalog_entry(&ty)?;
+        let Some(len) = self.stored_len(&ty, content_hash)? else {
+            return Ok(());
+        };
+        let table_name = Self::table_name(&ty);
+        let query = format!("DELETE FROM \"{}\" WHERE content_hash = :hash", table_name);
+        self.connection
+            .execute(&query, named_params! {":hash": content_hash.hash.as_slice()})?;
+        self.connection.execute(
+            &format!(
+                "UPDATE \"{}\" SET count = count - 1, total_bytes = total_bytes - :bytes WHERE ty = :ty",
+                Self::CATALOG_TABLE
+            ),
+            named_params! {":bytes": len, ":ty": ty.to_string()},
+        )?;
+        self.connection.execute(
+            &format!(
+                "DELETE FROM \"{}\" WHERE content_hash = :hash AND ty = :ty",
+                Self::HASH_INDEX_TABLE
+            ),
+            named_params! {":hash": content_hash.hash.as_slice(), ":ty": ty.to_string()},
+        )?;
+        Ok(())
+    }
+
+    fn drop_ty(&mut self, ty: Ty) -> Result<()> {
+        let table_name = Self::table_name(&ty);
+        let query = format!("DROP TABLE IF EXISTS \"{}\"", table_name);
+        self.connection.execute(&query, [])?;
+        for table in [Self::CATALOG_TABLE, Self::HASH_INDEX_TABLE] {
+            let query = format!("DELETE FROM \"{}\" WHERE ty = :ty", table);
+            match self.connection.execute(&query, named_params! {":ty": ty.to_string()}) {
+                Ok(_) => {}
+                Err(e) => match e.into() {
+                    Error::SqliteTableDoesNotExist { .. } => {}
+                    e => return Err(e),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of all tables in the database, excluding SQLite's internal ones.
+    fn table_names(&mut self) -> Result<Vec<String>> {
+        let mut statement = self.connection.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\'",
+        )?;
+        let names = statement.query_map([], |row| row.get(0))?;
+        Ok(names.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn read_row(row: &rusqlite::Row) -> rusqlite::Result<(ContentHash, Vec<u8>)> {
+        let hash_bytes: Vec<u8> = row.get(0)?;
+        let content: Vec<u8> = row.get(1)?;
+        let mut hash_array = [0u8; 32];
+        hash_array.copy_from_slice(&hash_bytes);
+        Ok((ContentHash { hash: hash_array }, content))
+    }
+}
+
+impl Backend for Sqlite {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        let table_name = Self::table_name(&ty);
+        let query = format!("SELECT content FROM \"{}\" WHERE content_hash = :hash", table_name);
+
+        match self
+            .connection
+            .query_row(&query, named_params! {":hash": content_hash.hash.as_slice()}, |row| {
+                row.get::<_, Vec<u8>>(0)
+            }) {
+            Ok(content) => Ok(Some(content)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        // A savepoint rather than a transaction so this also works inside a transaction opened by `begin_transaction`.
+        let transaction = self.connection.savepoint()?;
+        let table_name = Self::table_name(&ty);
+        let query = format!("SELECT content_hash, content FROM \"{}\"", table_name);
+
+        let mut statement = match transaction.prepare(&query).map_err(Into::into) {
+            Err(Error::SqliteTableDoesNotExist { .. }) => return Ok(Vec::new()),
+            other => other?,
+        };
+
+        let rows = statement.query_map([], Self::read_row)?;
+
+        let mut results = Vec::new();
+        for row in rows {
+            results.push(row?);
+        }
+
+        drop(statement);
+        transaction.commit()?;
+
+        Ok(results)
+    }
+
+    fn get_page_of_ty(
+        &mut self,
+        ty: Ty,
+        after: Option<ContentHash>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        let table_name = Self::table_name(&ty);
+        let query = format!(
+            "SELECT content_hash, content FROM \"{}\"
+            WHERE :after IS NULL OR content_hash > :after
+            ORDER BY content_hash LIMIT :limit OFFSET :offset",
+            table_name
+        );
+
+        let mut statement = match self.connection.prepare(&query).map_err(Into::into) {
+            Err(Error::SqliteTableDoesNotExist { .. }) => return Ok(Vec::new()),
+            other => other?,
+        };
+        let rows = statement.query_map(
+            named_params! {
+                ":after": after.as_ref().map(|content_hash| content_hash.hash.as_slice()),
+                ":limit": limit as i64,
+                ":offset": offset as i64,
+            },
+            Self::read_row,
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        self.atomically(|this| this.write_row(ty, content_hash, content))
+    }
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        self.atomically(|this| this.delete_row(ty, content_hash))
+    }
+
+    fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
+        self.atomically(|this| this.drop_ty(ty))
+    }
+
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        Ok(self
+            .table_names()?
+            .into_iter()
+            .filter_map(|name| name.strip_prefix("ty_").map(Ty::from))
+            .collect())
+    }
+
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        let table_name = Self::table_name(&ty);
+        let exists = self
+            .connection
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = :name",
+                named_params! {":name": table_name},
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !exists {
+            return Ok(None);
+        }
+
+        let query = format!(
+            "SELECT count, total_bytes, first_write, last_write FROM \"{}\" WHERE ty = :ty",
+            Self::CATALOG_TABLE
+        );
+        let entry = match self
+            .connection
+            .query_row(&query, named_params! {":ty": ty.to_string()}, |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .optional()
+        {
+            Ok(entry) => entry,
+            Err(e) => match e.into() {
+                Error::SqliteTableDoesNotExist { .. } => None,
+                e => return Err(e),
+            },
+        };
+        // Tables written before the catalog existed have no entry until they are next written to.
+        let (count, total_bytes, first_write, last_write): (i64, i64, Option<i64>, Option<i64>) = match entry {
+            Some(entry) => entry,
+            None => {
+                let query = format!(
+                    "SELECT count(*), COALESCE(sum(length(content)), 0) FROM \"{}\"",
+                    table_name
+                );
+                let (count, total_bytes) = self
+                    .connection
+                    .query_row(&query, [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                (count, total_bytes, None, None)
+            }
+        };
+        Ok(Some(TypeInfo {
+            ty,
+            count: count as u64,
+            total_bytes: total_bytes as u64,
+            first_write: first_write.map(|time| time as u64),
+            last_write: last_write.map(|time| time as u64),
+        }))
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        if self.get_metadata(Self::HASH_INDEX_METADATA_KEY)?.is_none() {
+            // A read-only database can't have its index rebuilt, so look in every table instead.
+            if let Err(e) = self.rebuild_hash_index() {
+                warn!(
+                    "Could not rebuild the hash index, looking in every table instead: {:?}",
+                    e
+                );
+                let mut tys = Vec::new();
+                for ty in self.list_tys()? {
+                    if self.stored_len(&ty, content_hash)?.is_some() {
+                        tys.push(ty);
+                    }
+                }
+                return Ok(tys);
+            }
+        }
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT ty FROM \"{}\" WHERE content_hash = :hash ORDER BY ty",
+            Self::HASH_INDEX_TABLE
+        ))?;
+        let tys = statement.query_map(named_params! {":hash": content_hash.hash.as_slice()}, |row| {
+            row.get::<_, String>(0)
+        })?;
+        tys.map(|ty| Ok(Ty::from(ty?.as_str()))).collect()
+    }
+
+    fn create_index(&mut self, ty: Ty, path: &FieldPath, encoding: Encoding) -> Result<()> {
+        // SQLite can only read fields of JSON.
+        if encoding != Encoding::Json {
+            return Ok(());
+        }
+        self.create_ty_table(&ty)?;
+        self.connection.execute(
+            &format!(
+                "CREATE INDEX IF NOT EXISTS \"vanth_index_{}_{}\" ON \"{}\" ({})",
+                ty,
+                path,
+                Self::table_name(&ty),
+                Self::field_expression(path)
+            ),
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn drop_indexes(&mut self, ty: Ty) -> Result<()> {
+        let names: Vec<String> = {
+            let mut statement = self.connection.prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = ?1 AND name GLOB 'vanth_index_*'",
+            )?;
+            let names = statement.query_map([Self::table_name(&ty)], |row| row.get(0))?;
+            names.collect::<rusqlite::Result<_>>()?
+        };
+        for name in names {
+            self.connection
+                .execute(&format!("DROP INDEX IF EXISTS \"{}\"", name), [])?;
+        }
+        Ok(())
+    }
+
+    fn find_by_field(
+        &mut self,
+        ty: Ty,
+        path: &FieldPath,
+        value: &FieldValue,
+        encoding: Encoding,
+    ) -> Result<Option<Values>> {
+        if encoding != Encoding::Json {
+            return Ok(None);
+        }
+        let query = format!(
+            "SELECT content_hash, content FROM \"{}\" WHERE {} IS :value ORDER BY content_hash",
+            Self::table_name(&ty),
+            Self::field_expression(path)
+        );
+        let mut statement = match self.connection.prepare(&query).map_err(Into::into) {
+            Err(Error::SqliteTableDoesNotExist { .. }) => return Ok(Some(Vec::new())),
+            other => other?,
+        };
+        let rows = statement.query_map(named_params! {":value": Self::sql_value(value)}, Self::read_row)?;
+        Ok(Some(rows.collect::<rusqlite::Result<_>>()?))
+    }
+
+    fn query(&mut self, ty: Ty, query: &Query, encoding: Encoding) -> Result<Option<Values>> {
+        if encoding != Encoding::Json {
+            return Ok(None);
+        }
+        let mut params = Vec::new();
+        let filter = match &query.filter {
+            Some(predicate) => Self::predicate_sql(predicate, &mut params),
+            None => "1".into(),
+        };
+        let mut order_by: Vec<String> = query
+            .order_by
+            .iter()
+            .map(|(path, order)| {
+                let direction = match order {
+                    Order::Ascending => "ASC",
+                    Order::Descending => "DESC",
+                };
+                format!("{} {}", Self::field_expression(path), direction)
+            })
+            .collect();
+        order_by.push("content_hash".into());
+        let sql = format!(
+            "SELECT content_hash, content FROM \"{}\" WHERE {} ORDER BY {} LIMIT {} OFFSET {}",
+            Self::table_name(&ty),
+            filter,
+            order_by.join(", "),
+            query.limit.map_or(-1, |limit| limit.min(i64::MAX as usize) as i64),
+            query.offset.min(i64::MAX as usize),
+        );
+
+        let mut statement = match self.connection.prepare(&sql).map_err(Into::into) {
+            Err(Error::SqliteTableDoesNotExist { .. }) => return Ok(Some(Vec::new())),
+            other => other?,
+        };
+        let rows = statement.query_map(rusqlite::params_from_iter(params), Self::read_row)?;
+        Ok(Some(rows.collect::<rusqlite::Result<_>>()?))
+    }
+
+    fn storage_name(&self, ty: &Ty) -> String {
+        // SQLite table names are case-insensitive for ASCII characters.
+        Self::table_name(ty).to_ascii_lowercase()
+    }
+
+    fn foreign_tables(&mut self) -> Result<Vec<String>> {
+        Ok(self
+            .table_names()?
+            .into_iter()
+            .filter(|name| {
+                !name.starts_with("ty_")
+                    && ![Self::METADATA_TABLE, Self::CATALOG_TABLE, Self::HASH_INDEX_TABLE].contains(&name.as_str())
+            })
+            .collect())
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        let query = format!("SELECT value FROM \"{}\" WHERE key = :key", Self::METADATA_TABLE);
+        match self
+            .connection
+            .query_row(&query, named_params! {":key": key}, |row| row.get(0))
+        {
+            Ok(value) => Ok(Some(value)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => match e.into() {
+                Error::SqliteTableDoesNotExist { .. } => Ok(None),
+                e => Err(e),
+            },
+        }
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        self.connection.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS \"{}\" (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
+                Self::METADATA_TABLE
+            ),
+            [],
+        )?;
+        self.connection.execute(
+            &format!(
+                "INSERT OR REPLACE INTO \"{}\" (key, value) VALUES (:key, :value)",
+                Self::METADATA_TABLE
+            ),
+            named_params! {":key": key, ":value": value},
+        )?;
+        Ok(())
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        // Writable connections take the write lock up front, so a transaction that reads before writing waits for
+        // other writers for `StoreParams::busy_timeout`, rather than failing as soon as it tries to write.
+        if self.connection.is_readonly(DatabaseName::Main)? {
+            self.connection.execute_batch("BEGIN")?;
+        } else {
+            self.connection.execute_batch("BEGIN IMMEDIATE")?;
+        }
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        self.connection.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.connection.execute_batch("ROLLBACK")?;
+        Ok(())
+    }
+}
+
+/// In-memory storage with one table per type.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Memory {
+    tables: HashMap<Ty, BTreeMap<ContentHash, Vec<u8>>>,
+    metadata: HashMap<String, String>,
+    /// When values of each type were first and last committed, in milliseconds since the Unix epoch.
+    #[serde(default)]
+    write_times: HashMap<Ty, (u64, u64)>,
+    /// Indexes of committed values, created with [`Backend::create_index`].
+    #[serde(skip)]
+    indexes: HashMap<Ty, Vec<FieldIndex>>,
+    /// Changes made in the currently open transaction, if any.
+    #[serde(skip)]
+    staged: Option<Staged>,
+}
+
+/// The hashes of the values of a type with each value of one of their fields.
+#[derive(Debug)]
+struct FieldIndex {
+    path: FieldPath,
+    encoding: Encoding,
+    entries: HashMap<FieldValue, BTreeSet<ContentHash>>,
+}
+
+impl FieldIndex {
+    /// The indexed field of `content`, or `None` if it can't be decoded.
+    fn key(&self, content: &[u8]) -> Option<FieldValue> {
+        let value: serde_json::Value = self.encoding.decode(content).ok()?;
+        Some(FieldValue::of(&value, &self.path))
+    }
+
+    fn insert(&mut self, content_hash: ContentHash, content: &[u8]) {
+        if let Some(key) = self.key(content) {
+            self.entries.entry(key).or_default().insert(content_hash);
+        }
+    }
+
+    fn remove(&mut self, content_hash: ContentHash, content: &[u8]) {
+        if let Some(key) = self.key(content)
+            && let Some(hashes) = self.entries.get_mut(&key)
+        {
+            hashes.remove(&content_hash);
+            if hashes.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+/// Changes made in an open transaction, staged on top of a backend's committed values until they are committed.
+#[derive(Debug, Default)]
+pub(crate) struct Staged {
+    /// Types whose committed tables are dropped by this transaction.
+    pub(crate) cleared: HashSet<Ty>,
+    /// Writes and deletes made after any clearing. `None` marks a deleted value.
+    pub(crate) changes: HashMap<Ty, BTreeMap<ContentHash, Option<Vec<u8>>>>,
+    /// Metadata set by this transaction.
+    pub(crate) metadata: HashMap<String, String>,
+}
+
+impl Staged {
+    /// The staged state of a value: `Some(Some(_))` if it was written, `Some(None)` if it was deleted and `None` if it
+    /// is unchanged from the committed value.
+    pub(crate) fn get(&self, ty: &Ty, content_hash: ContentHash) -> Option<Option<&Vec<u8>>> {
+        if let Some(change) = self.changes.get(ty).and_then(|table| table.get(&content_hash)) {
+            return Some(change.as_ref());
+        }
+        self.cleared.contains(ty).then_some(None)
+    }
+
+    pub(crate) fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) {
+        self.changes.entry(ty).or_default().insert(content_hash, Some(content));
+    }
+
+    pub(crate) fn delete(&mut self, ty: Ty, content_hash: ContentHash) {
+        self.changes.entry(ty).or_default().insert(content_hash, None);
+    }
+
+    pub(crate) fn delete_all(&mut self, ty: Ty) {
+        self.changes.remove(&ty);
+        self.cleared.insert(ty);
+    }
+
+    /// Staged changes to values of `ty` within `range`, in hash order.
+    pub(crate) fn changes_in(
+        &self,
+        ty: &Ty,
+        range: (Bound<ContentHash>, Bound<ContentHash>),
+    ) -> impl Iterator<Item = (ContentHash, Option<&Vec<u8>>)> {
+        self.changes
+            .get(ty)
+            .into_iter()
+            .flat_map(move |table| table.range(range))
+            .map(|(content_hash, change)| (*content_hash, change.as_ref()))
+    }
+}
+
+/// Merge hash-ordered committed values with hash-ordered staged changes. A staged change replaces the committed value
+/// with the same hash, and staged deletes are dropped.
+pub(crate) fn merge_staged<V>(
+    committed: impl Iterator<Item = Result<(ContentHash, V)>>,
+    changes: impl Iterator<Item = (ContentHash, Option<V>)>,
+) -> impl Iterator<Item = Result<(ContentHash, V)>> {
+    let mut committed = committed.peekable();
+    let mut changes = changes.peekable();
+
+    std::iter::from_fn(move || {
+        loop {
+            let next_committed = match committed.peek() {
+                Some(Err(_)) => return committed.next(),
+                Some(Ok((content_hash, _))) => Some(*content_hash),
+                None => None,
+            };
+            let order = match (next_committed, changes.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(a), Some((b, _))) => a.cmp(b),
+            };
+            if order == Ordering::Equal {
+                committed.next();
+            }
+            if order == Ordering::Less {
+                return committed.next();
+            }
+            if let (content_hash, Some(content)) = changes.next()? {
+                return Some(Ok((content_hash, content)));
+            }
+        }
+    })
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Iterate in hash order over the values of `ty` within `range`, including changes staged in the open transaction.
+    fn range_of_ty(
+        &self,
+        ty: &Ty,
+        range: (Bound<ContentHash>, Bound<ContentHash>),
+    ) -> impl Iterator<Item = Result<(ContentHash, &Vec<u8>)>> {
+        let cleared = self.staged.as_ref().is_some_and(|staged| staged.cleared.contains(ty));
+        let committed = self
+            .tables
+            .get(ty)
+            .filter(|_| !cleared)
+            .into_iter()
+            .flat_map(move |table| table.range(range))
+            .map(|(content_hash, content)| Ok((*content_hash, content)));
+        let changes = self
+            .staged
+            .as_ref()
+            .into_iter()
+            .flat_map(move |staged| staged.changes_in(ty, range));
+        merge_staged(committed, changes)
+    }
+
+    /// Write a committed value, keeping indexes up to date.
+    fn insert_committed(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) {
+        self.remove_committed(&ty, content_hash);
+        for index in self.indexes.get_mut(&ty).into_iter().flatten() {
+            index.insert(content_hash, &content);
+        }
+        self.tables.entry(ty).or_default().insert(content_hash, content);
+    }
+
+    fn remove_committed(&mut self, ty: &Ty, content_hash: ContentHash) {
+        let Some(content) = self.tables.get_mut(ty).and_then(|table| table.remove(&content_hash)) else {
+            return;
+        };
+        for index in self.indexes.get_mut(ty).into_iter().flatten() {
+            index.remove(content_hash, &content);
+        }
+    }
+
+    fn clear_committed(&mut self, ty: &Ty) {
+        self.tables.remove(ty);
+        self.write_times.remove(ty);
+        for index in self.indexes.get_mut(ty).into_iter().flatten() {
+            index.entries.clear();
+        }
+    }
+
+    fn record_write(&mut self, ty: Ty, now: u64) {
+        self.write_times
+            .entry(ty)
+            .and_modify(|(_, last_write)| *last_write = now)
+            .or_insert((now, now));
+    }
+}
+
+impl Backend for Memory {
+    fn get_from_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<Option<Vec<u8>>> {
+        if let Some(change) = self.staged.as_ref().and_then(|staged| staged.get(&ty, content_hash)) {
+            return Ok(change.cloned());
+        }
+        Ok(self.tables.get(&ty).and_then(|table| table.get(&content_hash)).cloned())
+    }
+
+    fn get_all_of_ty(&mut self, ty: Ty) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        self.range_of_ty(&ty, (Bound::Unbounded, Bound::Unbounded))
+            .map(|item| item.map(|(content_hash, content)| (content_hash, content.clone())))
+            .collect()
+    }
+
+    fn get_page_of_ty(
+        &mut self,
+        ty: Ty,
+        after: Option<ContentHash>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<(ContentHash, Vec<u8>)>> {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        self.range_of_ty(&ty, (start, Bound::Unbounded))
+            .skip(offset)
+            .take(limit)
+            .map(|item| item.map(|(content_hash, content)| (content_hash, content.clone())))
+            .collect()
+    }
+
+    fn write(&mut self, ty: Ty, content_hash: ContentHash, content: Vec<u8>) -> Result<()> {
+        match &mut self.staged {
+            Some(staged) => staged.write(ty, content_hash, content),
+            None => {
+                self.insert_committed(ty.clone(), content_hash, content);
+                self.record_write(ty, unix_millis(SystemTime::now()));
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_by_hash(&mut self, ty: Ty, content_hash: ContentHash) -> Result<()> {
+        match &mut self.staged {
+            Some(staged) => staged.delete(ty, content_hash),
+            None => self.remove_committed(&ty, content_hash),
+        }
+        Ok(())
     }
 
     fn delete_all_of_ty(&mut self, ty: Ty) -> Result<()> {
-        self.tables.remove(&ty);
+        match &mut self.staged {
+            Some(staged) => staged.delete_all(ty),
+            None => self.clear_committed(&ty),
+        }
+        Ok(())
+    }
+
+    fn list_tys(&mut self) -> Result<Vec<Ty>> {
+        let mut tys: HashSet<&Ty> = self.tables.keys().collect();
+        if let Some(staged) = &self.staged {
+            tys.retain(|ty| !staged.cleared.contains(*ty));
+            tys.extend(staged.changes.keys());
+        }
+        Ok(tys.into_iter().cloned().collect())
+    }
+
+    fn find_by_hash(&mut self, content_hash: ContentHash) -> Result<Vec<Ty>> {
+        // Tables are keyed by hash, so this is a single lookup per type.
+        let mut tys = self.list_tys()?;
+        tys.retain(
+            |ty| match self.staged.as_ref().and_then(|staged| staged.get(ty, content_hash)) {
+                Some(change) => change.is_some(),
+                None => self
+                    .tables
+                    .get(ty)
+                    .is_some_and(|table| table.contains_key(&content_hash)),
+            },
+        );
+        Ok(tys)
+    }
+
+    fn create_index(&mut self, ty: Ty, path: &FieldPath, encoding: Encoding) -> Result<()> {
+        // Bincode can't be decoded without knowing the type.
+        let indexes = self.indexes.entry(ty.clone()).or_default();
+        if encoding == Encoding::Bincode || indexes.iter().any(|index| index.path == *path) {
+            return Ok(());
+        }
+        let mut index = FieldIndex {
+            path: path.clone(),
+            encoding,
+            entries: HashMap::new(),
+        };
+        for (content_hash, content) in self.tables.get(&ty).into_iter().flatten() {
+            index.insert(*content_hash, content);
+        }
+        indexes.push(index);
+        Ok(())
+    }
+
+    fn drop_indexes(&mut self, ty: Ty) -> Result<()> {
+        self.indexes.remove(&ty);
+        Ok(())
+    }
+
+    fn find_by_field(
+        &mut self,
+        ty: Ty,
+        path: &FieldPath,
+        value: &FieldValue,
+        encoding: Encoding,
+    ) -> Result<Option<Values>> {
+        let Some(index) = self.indexes.get(&ty).and_then(|indexes| {
+            indexes
+                .iter()
+                .find(|index| index.path == *path && index.encoding == encoding)
+        }) else {
+            return Ok(None);
+        };
+        let cleared = self.staged.as_ref().is_some_and(|staged| staged.cleared.contains(&ty));
+        let mut found: BTreeSet<ContentHash> = match index.entries.get(value) {
+            Some(hashes) if !cleared => hashes.clone(),
+            _ => BTreeSet::new(),
+        };
+        // The index only covers committed values, so check staged ones individually.
+        let changes = self
+            .staged
+            .as_ref()
+            .into_iter()
+            .flat_map(|staged| staged.changes_in(&ty, (Bound::Unbounded, Bound::Unbounded)));
+        for (content_hash, change) in changes {
+            found.remove(&content_hash);
+            if change.and_then(|content| index.key(content)).as_ref() == Some(value) {
+                found.insert(content_hash);
+            }
+        }
+
+        let mut values = Vec::new();
+        for content_hash in found {
+            values.extend(
+                self.get_from_hash(ty.clone(), content_hash)?
+                    .map(|content| (content_hash, content)),
+            );
+        }
+        Ok(Some(values))
+    }
+
+    fn query(&mut self, ty: Ty, query: &Query, encoding: Encoding) -> Result<Option<Values>> {
+        // Bincode can't be decoded without knowing the type.
+        if encoding == Encoding::Bincode {
+            return Ok(None);
+        }
+        let mut matches = Vec::new();
+        for item in self.range_of_ty(&ty, (Bound::Unbounded, Bound::Unbounded)) {
+            let (content_hash, content) = item?;
+            let value: serde_json::Value = encoding.try_decode(content).map_err(|message| Error::Deserialization {
+                ty: ty.clone(),
+                content_hash,
+                message,
+            })?;
+            if query.matches(&value) {
+                matches.push((content_hash, value, content.clone()));
+            }
+        }
+        Ok(Some(query.arrange(matches)))
+    }
+
+    fn type_info(&mut self, ty: Ty) -> Result<Option<TypeInfo>> {
+        let cleared = self.staged.as_ref().is_some_and(|staged| staged.cleared.contains(&ty));
+        let staged = self
+            .staged
+            .as_ref()
+            .is_some_and(|staged| staged.changes.contains_key(&ty));
+        if !staged && (cleared || !self.tables.contains_key(&ty)) {
+            return Ok(None);
+        }
+        let (mut count, mut total_bytes) = (0, 0);
+        for item in self.range_of_ty(&ty, (Bound::Unbounded, Bound::Unbounded)) {
+            let (_, content) = item?;
+            count += 1;
+            total_bytes += content.len() as u64;
+        }
+        // Only committed writes are timed.
+        let times = self.write_times.get(&ty).filter(|_| !cleared);
+        Ok(Some(TypeInfo {
+            count,
+            total_bytes,
+            first_write: times.map(|(first_write, _)| *first_write),
+            last_write: times.map(|(_, last_write)| *last_write),
+            ty,
+        }))
+    }
+
+    fn get_metadata(&mut self, key: &str) -> Result<Option<String>> {
+        let staged = self.staged.as_ref().and_then(|staged| staged.metadata.get(key));
+        Ok(staged.or_else(|| self.metadata.get(key)).cloned())
+    }
+
+    fn set_metadata(&mut self, key: &str, value: &str) -> Result<()> {
+        let metadata = match &mut self.staged {
+            Some(staged) => &mut staged.metadata,
+            None => &mut self.metadata,
+        };
+        metadata.insert(key.into(), value.into());
+        Ok(())
+    }
+
+    fn begin_transaction(&mut self) -> Result<()> {
+        if self.staged.is_some() {
+            return Err(Error::TransactionAlreadyOpen);
+        }
+        self.staged = Some(Staged::default());
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<()> {
+        let staged = self.staged.take().ok_or(Error::NoTransactionOpen)?;
+        let now = unix_millis(SystemTime::now());
+        for ty in staged.cleared {
+            self.clear_committed(&ty);
+        }
+        for (ty, changes) in staged.changes {
+            if changes.values().any(Option::is_some) {
+                self.record_write(ty.clone(), now);
+            }
+            // Keep a table for a type that was written to, even if everything written to it was deleted again.
+            self.tables.entry(ty.clone()).or_default();
+            for (content_hash, change) in changes {
+                match change {
+                    Some(content) => self.insert_committed(ty.clone(), content_hash, content),
+                    None => self.remove_committed(&ty, content_hash),
+                }
+            }
+        }
+        self.metadata.extend(staged.metadata);
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<()> {
+        self.staged.take().ok_or(Error::NoTransactionOpen)?;
         Ok(())
     }
 }
@@ -0,0 +1,67 @@
+use vanth::{
+    Ty, Vanth, hash,
+    store::{IterParams, Store, StoreParams},
+};
+
+use crate::{for_each_backend, store::Foo};
+
+fn check_aliases(store: &mut Store) {
+    let old_ty = Ty::from("old::module::Foo");
+    let older_ty = Ty::from("older::module::Foo");
+    let foo_1 = Foo { inner: 1 };
+    let foo_2 = Foo { inner: 2 };
+    let foo_3 = Foo { inner: 3 };
+    let encoding = store.encoding();
+    store
+        .write_raw(old_ty.clone(), hash(&foo_1), encoding.encode(&foo_1).unwrap())
+        .unwrap();
+    store
+        .write_raw(older_ty.clone(), hash(&foo_2), encoding.encode(&foo_2).unwrap())
+        .unwrap();
+
+    // Before aliasing the old values are invisible.
+    assert!(store.get_all_of_type::<Foo>().unwrap().is_empty());
+
+    store.alias::<Foo>(old_ty.clone());
+    store.alias_raw(older_ty.clone(), old_ty.clone());
+    assert_eq!(store.get_from_hash(hash(&foo_1)).unwrap(), Some(foo_1.clone()));
+    assert_eq!(store.get_from_hash(hash(&foo_2)).unwrap(), Some(foo_2.clone()));
+
+    // Writing a value already stored under an old type doesn't copy it.
+    store.write(&foo_1).unwrap();
+    store.write(&foo_3).unwrap();
+    assert_eq!(store.get_all_of_type_raw(Foo::ty()).unwrap().len(), 1);
+
+    let mut all: Vec<Foo> = store
+        .get_all_of_type()
+        .unwrap()
+        .into_iter()
+        .map(|(_, value)| value)
+        .collect();
+    all.sort_by_key(|value| value.inner);
+    assert_eq!(all, vec![foo_1.clone(), foo_2.clone(), foo_3.clone()]);
+
+    // Iteration merges the types in hash order across pages.
+    let params = IterParams {
+        page_size: 1,
+        ..Default::default()
+    };
+    let hashes: Vec<_> = store.iter_of_type::<Foo>(params).map(|item| item.unwrap().0).collect();
+    let mut expected = vec![hash(&foo_1), hash(&foo_2), hash(&foo_3)];
+    expected.sort();
+    assert_eq!(hashes, expected);
+
+    // Deleting removes the value from wherever it is stored.
+    store.delete::<Foo>(hash(&foo_2)).unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(hash(&foo_2)).unwrap(), None);
+    assert!(store.get_all_of_type_raw(older_ty).unwrap().is_empty());
+
+    store.delete_all::<Foo>().unwrap();
+    assert!(store.get_all_of_type::<Foo>().unwrap().is_empty());
+    assert!(store.get_all_of_type_raw(old_ty).unwrap().is_empty());
+}
+
+#[test]
+fn test_aliases() {
+    for_each_backend(StoreParams::default(), check_aliases);
+}
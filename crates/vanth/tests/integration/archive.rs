@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+use vanth::{
+    ContentHash, Ty, Vanth,
+    archive::{ExportParams, ImportParams},
+    gc::{Collector, References},
+    hash,
+    store::{Encoding, Error, Store, StoreParams, Verifier},
+};
+
+use crate::{
+    for_each_backend,
+    store::{Bar, Foo},
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Vanth)]
+struct Leaf {
+    value: i32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Vanth)]
+struct Node {
+    children: Vec<ContentHash>,
+}
+
+impl References for Node {
+    fn references(&self) -> Vec<(Ty, ContentHash)> {
+        self.children.iter().map(|child| (Leaf::ty(), *child)).collect()
+    }
+}
+
+fn source() -> Store {
+    let mut store = Store::in_memory().unwrap();
+    for inner in 1..=3 {
+        store.write(&Foo { inner }).unwrap();
+    }
+    store.write(&Bar { inner: "bar".into() }).unwrap();
+    store
+}
+
+fn export(store: &mut Store, params: &ExportParams) -> Vec<u8> {
+    let mut archive = Vec::new();
+    store.export(&mut archive, params).unwrap();
+    archive
+}
+
+fn verifier() -> Verifier {
+    Verifier::new().with::<Foo>().with::<Bar>()
+}
+
+fn check_import(store: &mut Store) {
+    let archive = export(&mut source(), &ExportParams::default());
+
+    let report = store.import(archive.as_slice(), &ImportParams::default()).unwrap();
+    assert_eq!((report.imported, report.skipped), (4, 0));
+    assert_eq!(report.header.unwrap().encoding, Encoding::Json);
+    assert_eq!(report.unchecked_tys.len(), 2);
+    let mut foos = store.get_all_of_type::<Foo>().unwrap();
+    foos.sort_by_key(|(_, value)| value.inner);
+    assert_eq!(
+        foos,
+        (1..=3)
+            .map(|inner| (hash(&Foo { inner }), Foo { inner }))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        store.get_from_hash(hash(&Bar { inner: "bar".into() })).unwrap(),
+        Some(Bar { inner: "bar".into() })
+    );
+
+    // Values already present are skipped.
+    let params = ImportParams { verifier: verifier() };
+    let report = store.import(archive.as_slice(), &params).unwrap();
+    assert_eq!((report.imported, report.skipped), (0, 4));
+    assert!(report.unchecked_tys.is_empty());
+}
+
+#[test]
+fn test_import() {
+    for_each_backend(StoreParams::default(), check_import);
+}
+
+#[test]
+fn test_export_by_type() {
+    let params = ExportParams {
+        tys: Some(vec![Bar::ty()]),
+        ..Default::default()
+    };
+    let mut source = source();
+    let mut archive = Vec::new();
+    let report = source.export(&mut archive, &params).unwrap();
+    assert_eq!(report.exported, 1);
+
+    let mut store = Store::in_memory().unwrap();
+    store.import(archive.as_slice(), &ImportParams::default()).unwrap();
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap(), []);
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap().len(), 1);
+}
+
+#[test]
+fn test_export_by_roots() {
+    let mut source = Store::in_memory().unwrap();
+    let leaf_1 = source.write(&Leaf { value: 1 }).unwrap();
+    let leaf_2 = source.write(&Leaf { value: 2 }).unwrap();
+    let missing = hash(&Leaf { value: 3 });
+    let node_1 = source
+        .write(&Node {
+            children: vec![leaf_1, missing],
+        })
+        .unwrap();
+    source.write(&Node { children: vec![leaf_2] }).unwrap();
+
+    let params = ExportParams {
+        roots: Some(vec![(Node::ty(), node_1)]),
+        collector: Collector::new().with::<Node>(),
+        ..Default::default()
+    };
+    let mut archive = Vec::new();
+    let report = source.export(&mut archive, &params).unwrap();
+    assert_eq!(report.exported, 2);
+    assert_eq!(report.missing, [(Leaf::ty(), missing)]);
+
+    let mut store = Store::in_memory().unwrap();
+    store.import(archive.as_slice(), &ImportParams::default()).unwrap();
+    assert_eq!(store.get_all_of_type::<Leaf>().unwrap(), [(leaf_1, Leaf { value: 1 })]);
+    assert_eq!(store.get_all_of_type::<Node>().unwrap().len(), 1);
+}
+
+/// A value that doesn't match its hash fails the import when its type is registered with the verifier.
+#[test]
+fn test_import_hash_mismatch() {
+    let mut source = Store::in_memory().unwrap();
+    let wrong_hash = hash(&Foo { inner: 1 });
+    source
+        .write_raw(Foo::ty(), wrong_hash, serde_json::to_vec(&Foo { inner: 2 }).unwrap())
+        .unwrap();
+    source.write(&Bar { inner: "bar".into() }).unwrap();
+    let archive = export(&mut source, &ExportParams::default());
+
+    let mut store = Store::in_memory().unwrap();
+    let params = ImportParams { verifier: verifier() };
+    let result = store.import(archive.as_slice(), &params);
+    assert!(
+        matches!(result, Err(Error::HashMismatch { expected, .. }) if expected == wrong_hash),
+        "{:?}",
+        result
+    );
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap(), []);
+
+    // Without verifying Foo, the archive is imported as it is.
+    let params = ImportParams {
+        verifier: Verifier::new().with::<Bar>(),
+    };
+    let report = store.import(archive.as_slice(), &params).unwrap();
+    assert_eq!(report.unchecked_tys, [Foo::ty()]);
+}
+
+#[test]
+fn test_import_invalid_archive() {
+    let archive = export(&mut source(), &ExportParams::default());
+    let mut store = Store::in_memory().unwrap();
+
+    let mut corrupted = archive.clone();
+    // The last byte of the last value, before the end marker, count and checksum.
+    let index = corrupted.len() - 42;
+    corrupted[index] ^= 1;
+    let truncated = &archive[..archive.len() - 10];
+    for invalid in [corrupted.as_slice(), truncated, b"not an archive"] {
+        let result = store.import(invalid, &ImportParams::default());
+        assert!(matches!(result, Err(Error::InvalidArchive(_))), "{:?}", result);
+        // Nothing is written from an invalid archive.
+        assert_eq!(store.list_types().unwrap(), []);
+    }
+
+    let mut bincode = Store::from_backend(
+        Box::new(vanth::store::Memory::new()),
+        &StoreParams {
+            encoding: Some(Encoding::Bincode),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let result = bincode.import(archive.as_slice(), &ImportParams::default());
+    assert!(matches!(result, Err(Error::EncodingMismatch { .. })), "{:?}", result);
+}
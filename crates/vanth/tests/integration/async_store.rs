@@ -0,0 +1,113 @@
+use std::{sync::Arc, thread};
+
+use futures_lite::future::block_on;
+use tempfile::TempDir;
+use vanth::{
+    Reference, ReferenceValue,
+    async_store::AsyncStore,
+    hash,
+    store::{Error, Store, StoreParams},
+};
+
+use crate::store::{Bar, Foo};
+
+#[test]
+fn test_async_store() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let store = AsyncStore::new(Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap());
+
+    block_on(async {
+        let foo_1_hash = store.write(Foo { inner: 1 }).await.unwrap();
+        let foo_2_hash = store.write(Foo { inner: 2 }).await.unwrap();
+        store.write(Bar { inner: "hello".into() }).await.unwrap();
+        assert_eq!(foo_1_hash, hash(&Foo { inner: 1 }));
+
+        let foo: Option<Foo> = store.get_from_hash(foo_2_hash).await.unwrap();
+        assert_eq!(foo, Some(Foo { inner: 2 }));
+        assert_eq!(store.get_all_of_type::<Foo>().await.unwrap().len(), 2);
+
+        store.delete::<Foo>(foo_1_hash).await.unwrap();
+        assert_eq!(store.get_from_hash::<Foo>(foo_1_hash).await.unwrap(), None);
+        let count = store
+            .run(|store| Ok(store.get_all_of_type::<Foo>()?.len()))
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    });
+
+    // Operations that are never awaited still run before the store is handed back.
+    let unawaited = store.write(Foo { inner: 3 });
+    drop(unawaited);
+    let mut store = store.into_inner().unwrap();
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 2);
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap().len(), 1);
+}
+
+#[test]
+fn test_async_store_shared() {
+    let store = Arc::new(AsyncStore::new(Store::in_memory().unwrap()));
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            let store = store.clone();
+            thread::spawn(move || {
+                block_on(async {
+                    for j in 0..25 {
+                        store.write(Foo { inner: i * 100 + j }).await.unwrap();
+                    }
+                })
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let store = Arc::into_inner(store).unwrap();
+    assert_eq!(block_on(store.get_all_of_type::<Foo>()).unwrap().len(), 100);
+}
+
+#[test]
+fn test_async_store_worker_stopped() {
+    let store = AsyncStore::new(Store::in_memory().unwrap());
+    let panicked = store.run::<()>(|_| panic!("operation failed"));
+    let after = store.write(Foo { inner: 1 });
+    assert!(matches!(block_on(panicked), Err(Error::WorkerStopped)));
+    assert!(matches!(block_on(after), Err(Error::WorkerStopped)));
+    assert!(matches!(
+        block_on(store.write(Foo { inner: 2 })),
+        Err(Error::WorkerStopped)
+    ));
+    assert!(matches!(store.into_inner(), Err(Error::WorkerStopped)));
+}
+
+#[test]
+fn test_reference() {
+    let store = AsyncStore::new(Store::in_memory().unwrap());
+    block_on(async {
+        let content_hash = store.write(Foo { inner: 5 }).await.unwrap();
+
+        let mut reference = Reference::<Foo>::new(content_hash);
+        assert!(matches!(reference.value(), ReferenceValue::Absent));
+        reference.retrieve(&store);
+        assert!(matches!(reference.value(), ReferenceValue::Retrieving(_)));
+
+        // A copy of a reference that is being retrieved retrieves the value again.
+        let serialized = serde_json::to_string(&reference).unwrap();
+        let copy: Reference<Foo> = serde_json::from_str(&serialized).unwrap();
+        let cloned = reference.clone();
+
+        assert_eq!(reference.get(&store).await.unwrap(), Some(Foo { inner: 5 }));
+        assert!(matches!(reference.value(), ReferenceValue::Present { .. }));
+        // Present values are not retrieved again.
+        store.delete::<Foo>(content_hash).await.unwrap();
+        assert_eq!(reference.get(&store).await.unwrap(), Some(Foo { inner: 5 }));
+
+        store.write(Foo { inner: 5 }).await.unwrap();
+        assert_eq!(copy.take(&store).await.unwrap(), Some(Foo { inner: 5 }));
+        assert_eq!(cloned.take(&store).await.unwrap(), Some(Foo { inner: 5 }));
+
+        let mut missing = Reference::<Foo>::new(hash(&Foo { inner: 6 }));
+        assert_eq!(missing.get(&store).await.unwrap(), None);
+        assert!(matches!(missing.value(), ReferenceValue::Absent));
+    });
+}
@@ -0,0 +1,90 @@
+use tempfile::TempDir;
+use vanth::{
+    Vanth, hash,
+    store::{CacheStats, Store, StoreParams},
+};
+
+use crate::{
+    for_each_backend,
+    store::{Bar, Foo},
+};
+
+/// Room for two values of `Foo`, which are 11 bytes each when encoded as JSON.
+const CACHE_SIZE: u64 = 25;
+
+fn params() -> StoreParams {
+    StoreParams {
+        cache_size_bytes: CACHE_SIZE,
+        ..Default::default()
+    }
+}
+
+fn check_cache(store: &mut Store) {
+    let [a, b, c] = [1, 2, 3].map(|inner| store.write(&Foo { inner }).unwrap());
+    // Writes don't fill the cache.
+    assert_eq!(store.cache_stats(), Some(CacheStats::default()));
+
+    for (content_hash, inner) in [(a, 1), (a, 1), (b, 2), (a, 1), (c, 3), (a, 1), (b, 2)] {
+        assert_eq!(store.get_from_hash(content_hash).unwrap(), Some(Foo { inner }));
+    }
+    // The least recently used value is evicted, which is `b` and then `c`, as `a` keeps being read.
+    let expected = CacheStats {
+        hits: 3,
+        misses: 4,
+        evictions: 2,
+        entries: 2,
+        bytes: 22,
+    };
+    assert_eq!(store.cache_stats(), Some(expected));
+
+    store.delete::<Foo>(a).unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(a).unwrap(), None);
+    assert_eq!(store.get_from_hash::<Foo>(hash(&Foo { inner: 4 })).unwrap(), None);
+    let stats = store.cache_stats().unwrap();
+    assert_eq!((stats.misses, stats.entries, stats.bytes), (6, 1, 11));
+
+    // Values read in a transaction aren't cached, since they may be rolled back.
+    let mut transaction = store.begin().unwrap();
+    let d = transaction.write(&Foo { inner: 5 }).unwrap();
+    assert_eq!(transaction.get_from_hash(d).unwrap(), Some(Foo { inner: 5 }));
+    transaction.rollback().unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(d).unwrap(), None);
+
+    // Values bigger than the cache aren't cached.
+    let bar = Bar {
+        inner: "too big to cache".into(),
+    };
+    let bar_hash = store.write(&bar).unwrap();
+    assert_eq!(store.get_from_hash(bar_hash).unwrap(), Some(bar));
+    assert_eq!(store.cache_stats().unwrap().entries, 1);
+
+    store.delete_all::<Foo>().unwrap();
+    let stats = store.cache_stats().unwrap();
+    assert_eq!((stats.entries, stats.bytes), (0, 0));
+}
+
+#[test]
+fn test_cache() {
+    for_each_backend(params(), check_cache);
+}
+
+/// Cached values are served without reading the database.
+#[test]
+fn test_sqlite_cache_hit() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::sqlite_from_path(path.clone(), params()).unwrap();
+    let content_hash = store.write(&Foo { inner: 1 }).unwrap();
+    store.get_from_hash::<Foo>(content_hash).unwrap();
+
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    connection
+        .execute(&format!("DELETE FROM \"ty_{}\"", Foo::ty()), [])
+        .unwrap();
+    assert_eq!(store.get_from_hash(content_hash).unwrap(), Some(Foo { inner: 1 }));
+    assert_eq!(store.cache_stats().unwrap().hits, 1);
+
+    let mut uncached = Store::sqlite_from_path(path, StoreParams::default()).unwrap();
+    assert_eq!(uncached.cache_stats(), None);
+    assert_eq!(uncached.get_from_hash::<Foo>(content_hash).unwrap(), None);
+}
@@ -0,0 +1,88 @@
+use tempfile::TempDir;
+use vanth::{
+    Vanth,
+    store::{Store, StoreParams, TypeInfo},
+};
+
+use crate::{
+    for_each_backend,
+    store::{Bar, Foo},
+};
+
+fn check_catalog(store: &mut Store) {
+    assert!(store.list_types().unwrap().is_empty());
+    assert_eq!(store.type_info::<Foo>().unwrap(), None);
+
+    let foo_1 = Foo { inner: 1 };
+    let foo_1_hash = store.write(&foo_1).unwrap();
+    store.write(&Foo { inner: 22 }).unwrap();
+    // Rewriting a value doesn't count it twice.
+    store.write(&foo_1).unwrap();
+    store.write(&Bar { inner: "hello".into() }).unwrap();
+
+    let foo_bytes = store.encoding().encode(&foo_1).unwrap().len() as u64
+        + store.encoding().encode(&Foo { inner: 22 }).unwrap().len() as u64;
+    let types = store.list_types().unwrap();
+    assert_eq!(types.iter().map(|info| info.ty.clone()).collect::<Vec<_>>(), vec![Bar::ty(), Foo::ty()]);
+    let foo_info = store.type_info::<Foo>().unwrap().unwrap();
+    assert_eq!(foo_info, types[1]);
+    assert_eq!((foo_info.count, foo_info.total_bytes), (2, foo_bytes));
+    let (first_write, last_write) = (foo_info.first_write.unwrap(), foo_info.last_write.unwrap());
+    assert!(first_write <= last_write);
+
+    // Staged changes are counted inside a transaction and dropped with it.
+    let mut transaction = store.begin().unwrap();
+    transaction.delete::<Foo>(foo_1_hash).unwrap();
+    transaction.delete_all::<Bar>().unwrap();
+    assert_eq!(transaction.type_info::<Foo>().unwrap().unwrap().count, 1);
+    assert_eq!(transaction.type_info::<Bar>().unwrap(), None);
+    transaction.rollback().unwrap();
+    assert_eq!(store.type_info::<Foo>().unwrap().unwrap(), foo_info);
+    assert_eq!(store.type_info::<Bar>().unwrap().unwrap().count, 1);
+
+    store.delete::<Foo>(foo_1_hash).unwrap();
+    let foo_info = store.type_info::<Foo>().unwrap().unwrap();
+    assert_eq!(foo_info.count, 1);
+    assert_eq!(foo_info.total_bytes, foo_bytes - store.encoding().encode(&foo_1).unwrap().len() as u64);
+
+    store.delete_all::<Bar>().unwrap();
+    assert_eq!(store.type_info::<Bar>().unwrap(), None);
+    assert_eq!(store.list_types().unwrap().len(), 1);
+}
+
+#[test]
+fn test_catalog() {
+    for_each_backend(StoreParams::default(), check_catalog);
+}
+
+/// Tables written before the catalog existed are still counted, and are tracked from their next write on.
+#[test]
+fn test_sqlite_catalog_without_entry() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    store.write(&Foo { inner: 1 }).unwrap();
+    drop(store);
+
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    connection.execute("DROP TABLE vanth_catalog", []).unwrap();
+    drop(connection);
+
+    let mut store = Store::sqlite_from_path(path, StoreParams::default()).unwrap();
+    let bytes = store.encoding().encode(&Foo { inner: 1 }).unwrap().len() as u64;
+    assert_eq!(
+        store.type_info::<Foo>().unwrap(),
+        Some(TypeInfo {
+            ty: Foo::ty(),
+            count: 1,
+            total_bytes: bytes,
+            first_write: None,
+            last_write: None,
+        })
+    );
+
+    store.write(&Foo { inner: 2 }).unwrap();
+    let foo_info = store.type_info::<Foo>().unwrap().unwrap();
+    assert_eq!((foo_info.count, foo_info.total_bytes), (2, bytes * 2));
+    assert!(foo_info.last_write.is_some());
+}
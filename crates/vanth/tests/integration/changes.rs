@@ -0,0 +1,190 @@
+use std::sync::{Arc, Mutex};
+
+use tempfile::TempDir;
+use vanth::{
+    ContentHash, Ty, Vanth,
+    changes::{Change, ChangeKind},
+    hash,
+    store::{Error, Memory, Store, StoreParams},
+};
+
+use crate::{
+    for_each_backend,
+    store::{Bar, Foo},
+};
+
+fn summary(changes: &[Change]) -> Vec<(u64, ChangeKind, String, ContentHash)> {
+    changes
+        .iter()
+        .map(|change| (change.seq, change.kind, change.ty.to_string(), change.content_hash))
+        .collect()
+}
+
+fn check_changes(store: &mut Store) {
+    let all = store.subscribe(None);
+    let foos = store.subscribe(Some(vec![Foo::ty()]));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let callback = store.on_change(Some(vec![Bar::ty()]), {
+        let seen = seen.clone();
+        move |change| seen.lock().unwrap().push(change.clone())
+    });
+    let start = store.last_seq();
+    let foo_ty = Foo::ty().to_string();
+    let bar_ty = Bar::ty().to_string();
+
+    let foo_hash = store.write(&Foo { inner: 1 }).unwrap();
+    let bar_hash = store.write(&Bar { inner: "bar".into() }).unwrap();
+    // Writing a value that is already stored and deleting one that isn't are not changes.
+    store.write(&Foo { inner: 1 }).unwrap();
+    store.delete::<Foo>(hash(&Foo { inner: 2 })).unwrap();
+    store.delete::<Foo>(foo_hash).unwrap();
+    store.delete_all::<Bar>().unwrap();
+    assert_eq!(store.last_seq(), start + 4);
+
+    let changes: Vec<Change> = all.try_iter().collect();
+    assert_eq!(
+        summary(&changes),
+        [
+            (start + 1, ChangeKind::Write, foo_ty.clone(), foo_hash),
+            (start + 2, ChangeKind::Write, bar_ty.clone(), bar_hash),
+            (start + 3, ChangeKind::Delete, foo_ty.clone(), foo_hash),
+            (start + 4, ChangeKind::Delete, bar_ty.clone(), bar_hash),
+        ]
+    );
+    assert_eq!(
+        foos.try_iter().collect::<Vec<_>>(),
+        [changes[0].clone(), changes[2].clone()]
+    );
+    assert_eq!(*seen.lock().unwrap(), [changes[1].clone(), changes[3].clone()]);
+
+    // Changes in a transaction are only published once it is committed.
+    let result: Result<(), Error> = store.transaction(|tx| {
+        tx.write(&Foo { inner: 3 })?;
+        Err(Error::Serializiation("abort".into()))
+    });
+    assert!(result.is_err());
+    assert_eq!(all.try_iter().count(), 0);
+    let mut tx = store.begin().unwrap();
+    tx.write(&Foo { inner: 4 }).unwrap();
+    tx.write(&Bar { inner: "bar".into() }).unwrap();
+    assert_eq!(all.try_iter().count(), 0);
+    tx.commit().unwrap();
+    let changes: Vec<Change> = all.try_iter().collect();
+    assert_eq!(
+        summary(&changes),
+        [
+            (start + 5, ChangeKind::Write, foo_ty.clone(), hash(&Foo { inner: 4 })),
+            (start + 6, ChangeKind::Write, bar_ty.clone(), bar_hash),
+        ]
+    );
+
+    assert!(store.unsubscribe(callback));
+    assert!(!store.unsubscribe(callback));
+    drop(foos);
+    store.write(&Bar { inner: "later".into() }).unwrap();
+    assert_eq!(seen.lock().unwrap().len(), 3);
+    assert_eq!(all.try_iter().count(), 1);
+}
+
+#[test]
+fn test_changes() {
+    for_each_backend(StoreParams::default(), check_changes);
+}
+
+#[test]
+fn test_memory_change_log() {
+    let params = StoreParams {
+        change_log: true,
+        ..Default::default()
+    };
+    let mut store = Store::from_backend(Box::new(Memory::new()), &params).unwrap();
+    check_changes(&mut store);
+    assert_eq!(store.changes_since(0, 100).unwrap().len(), 7);
+}
+
+/// A consumer can resume from the last change it saw after the store is reopened.
+#[test]
+fn test_sqlite_change_log() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let params = StoreParams {
+        change_log: true,
+        ..Default::default()
+    };
+    let mut store = Store::sqlite_from_path(path.clone(), params.clone()).unwrap();
+    let hashes: Vec<_> = (1..=3).map(|inner| store.write(&Foo { inner }).unwrap()).collect();
+    store.delete::<Foo>(hashes[0]).unwrap();
+    let result: Result<(), Error> = store.transaction(|tx| {
+        tx.write(&Foo { inner: 4 })?;
+        Err(Error::Serializiation("abort".into()))
+    });
+    assert!(result.is_err());
+    drop(store);
+
+    let mut store = Store::sqlite_from_path(path.clone(), params.clone()).unwrap();
+    assert_eq!(store.last_seq(), 4);
+    let foo_ty = Foo::ty().to_string();
+    assert_eq!(
+        summary(&store.changes_since(2, 100).unwrap()),
+        [
+            (3, ChangeKind::Write, foo_ty.clone(), hashes[2]),
+            (4, ChangeKind::Delete, foo_ty.clone(), hashes[0]),
+        ]
+    );
+    assert_eq!(store.changes_since(0, 2).unwrap().len(), 2);
+    assert_eq!(store.write(&Foo { inner: 5 }).unwrap(), hash(&Foo { inner: 5 }));
+    assert_eq!(store.last_seq(), 5);
+
+    // The log is hidden from the store's types.
+    let types: Vec<Ty> = store.list_types().unwrap().into_iter().map(|info| info.ty).collect();
+    assert_eq!(types, [Foo::ty()]);
+
+    store.prune_changes(3).unwrap();
+    let seqs: Vec<u64> = store
+        .changes_since(0, 100)
+        .unwrap()
+        .iter()
+        .map(|change| change.seq)
+        .collect();
+    assert_eq!(seqs, [4, 5]);
+    drop(store);
+
+    // Without the log, changes are still numbered from where it left off.
+    let mut store = Store::sqlite_from_path(path, StoreParams::default()).unwrap();
+    let changes = store.subscribe(None);
+    store.write(&Foo { inner: 6 }).unwrap();
+    assert_eq!(changes.try_recv().unwrap().seq, 6);
+    assert_eq!(store.changes_since(5, 100).unwrap(), []);
+}
+
+/// Stores sharing a database, as separate processes do, number their changes from the same sequence.
+#[test]
+fn test_shared_change_log() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let params = StoreParams {
+        change_log: true,
+        ..Default::default()
+    };
+    let mut first = Store::sqlite_from_path(path.clone(), params.clone()).unwrap();
+    let mut second = Store::sqlite_from_path(path, params).unwrap();
+    for inner in 0..3 {
+        first.write(&Foo { inner }).unwrap();
+        second
+            .write(&Bar {
+                inner: inner.to_string(),
+            })
+            .unwrap();
+    }
+    let mut tx = second.begin().unwrap();
+    tx.write(&Foo { inner: 10 }).unwrap();
+    tx.commit().unwrap();
+    first.write(&Foo { inner: 11 }).unwrap();
+
+    let changes = first.changes_since(0, 100).unwrap();
+    let seqs: Vec<u64> = changes.iter().map(|change| change.seq).collect();
+    assert_eq!(seqs, (1..=8).collect::<Vec<_>>());
+    assert_eq!(changes[1].ty, Bar::ty());
+    assert_eq!(changes[7].content_hash, hash(&Foo { inner: 11 }));
+    assert_eq!(second.changes_since(0, 100).unwrap(), changes);
+}
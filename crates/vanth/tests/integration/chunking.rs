@@ -0,0 +1,198 @@
+use tempfile::TempDir;
+use vanth::{
+    Ty, Vanth,
+    chunking::Chunking,
+    compression::Compression,
+    encryption::EncryptionKey,
+    hash,
+    query::{Query, field},
+    store::{Backend, Encoding, Error, Memory, Store, StoreParams},
+};
+
+use crate::{
+    for_each_backend,
+    store::{Bar, Foo},
+};
+
+const MANIFEST_MAGIC: [u8; 8] = *b"\0vanthcm";
+
+fn params() -> StoreParams {
+    StoreParams {
+        chunking: Some(Chunking {
+            threshold_bytes: 1024,
+            min_chunk_bytes: 256,
+            avg_chunk_bytes: 1024,
+            max_chunk_bytes: 4096,
+        }),
+        ..Default::default()
+    }
+}
+
+/// About 64 KiB of text that doesn't repeat, so chunk boundaries depend on its content.
+fn big_bar() -> Bar {
+    let mut state: u32 = 1;
+    let inner = (0..64 * 1024)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            char::from(b'a' + (state >> 16) as u8 % 26)
+        })
+        .collect();
+    Bar { inner }
+}
+
+/// [`big_bar`] with a few bytes in the middle changed.
+fn edited_bar() -> Bar {
+    let mut bar = big_bar();
+    bar.inner.replace_range(30_000..30_004, "EDIT");
+    bar
+}
+
+fn chunk_count(store: &mut Store) -> usize {
+    store.get_all_of_type_raw(Ty::from("vanth_chunk")).unwrap().len()
+}
+
+fn check_chunking(store: &mut Store) {
+    let foo_hash = store.write(&Foo { inner: 1 }).unwrap();
+    let big_hash = store.write(&big_bar()).unwrap();
+    assert_eq!(big_hash, hash(&big_bar()));
+    let chunks = chunk_count(store);
+    assert!(chunks > 8, "{} chunks", chunks);
+
+    // Near-duplicates share most of their chunks.
+    let edited_hash = store.write(&edited_bar()).unwrap();
+    let added = chunk_count(store) - chunks;
+    assert!((1..=3).contains(&added), "{} chunks added", added);
+    // Writing a value again stores nothing new.
+    store.write(&edited_bar()).unwrap();
+    assert_eq!(chunk_count(store), chunks + added);
+
+    assert_eq!(store.get_from_hash(foo_hash).unwrap(), Some(Foo { inner: 1 }));
+    assert_eq!(store.get_from_hash(big_hash).unwrap(), Some(big_bar()));
+    assert_eq!(store.get_from_hash(edited_hash).unwrap(), Some(edited_bar()));
+    let raw = store.get_from_hash_raw(Bar::ty(), big_hash).unwrap().unwrap();
+    assert_eq!(raw, store.encoding().encode(&big_bar()).unwrap());
+    let mut bars = store.get_all_of_type::<Bar>().unwrap();
+    bars.sort_by_key(|(content_hash, _)| *content_hash != big_hash);
+    assert_eq!(bars, [(big_hash, big_bar()), (edited_hash, edited_bar())]);
+
+    // Chunks are hidden, and sizes are of the manifests.
+    let types: Vec<String> = store
+        .list_types()
+        .unwrap()
+        .into_iter()
+        .map(|info| info.ty.to_string())
+        .collect();
+    assert!(types.iter().all(|ty| !ty.starts_with("vanth_chunk")), "{:?}", types);
+    assert_eq!(store.find_by_hash(big_hash).unwrap(), [Bar::ty()]);
+    let info = store.type_info::<Bar>().unwrap().unwrap();
+    assert!(info.total_bytes < raw.len() as u64 / 4, "{} bytes", info.total_bytes);
+
+    store.create_index::<Bar>("inner").unwrap();
+    let found = store.find_by_field::<Bar>("inner", big_bar().inner).unwrap();
+    assert_eq!(found, [(big_hash, big_bar())]);
+    let query = Query::new().filter(field("inner").unwrap().eq(edited_bar().inner));
+    assert_eq!(store.query::<Bar>(&query).unwrap(), [(edited_hash, edited_bar())]);
+
+    // Small values that look like a manifest are chunked anyway, so they read back as written.
+    let ty = Ty::from("test::Raw");
+    let looks_chunked = [MANIFEST_MAGIC.as_slice(), &[1, 2, 3]].concat();
+    store.write_raw(ty.clone(), foo_hash, looks_chunked.clone()).unwrap();
+    assert_eq!(
+        store.get_from_hash_raw(ty.clone(), foo_hash).unwrap(),
+        Some(looks_chunked)
+    );
+    store.delete_all_raw(ty).unwrap();
+
+    // Chunks are deleted with the last value using them.
+    store.delete::<Bar>(big_hash).unwrap();
+    assert_eq!(store.get_from_hash(edited_hash).unwrap(), Some(edited_bar()));
+    assert!(chunk_count(store) < chunks + added);
+    store.delete_all::<Bar>().unwrap();
+    assert_eq!(chunk_count(store), 0);
+
+    // Chunks written in a transaction that is rolled back are discarded with it.
+    let result: Result<(), Error> = store.transaction(|tx| {
+        tx.write(&big_bar())?;
+        tx.write(&edited_bar())?;
+        assert_eq!(tx.get_from_hash(edited_hash)?, Some(edited_bar()));
+        Err(Error::Serializiation("abort".into()))
+    });
+    assert!(result.is_err());
+    assert_eq!(chunk_count(store), 0);
+}
+
+#[test]
+fn test_chunking() {
+    for encoding in [Encoding::Json, Encoding::Bincode] {
+        let params = StoreParams {
+            encoding: Some(encoding),
+            ..params()
+        };
+        for_each_backend(params, check_chunking);
+    }
+}
+
+/// Chunked values are reassembled when the store is opened without chunking, and chunking combines with compression
+/// and encryption.
+#[test]
+fn test_sqlite_chunking_reopened() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let key = EncryptionKey::new([1; 32]);
+    let params = StoreParams {
+        compression: Some(Compression::default()),
+        encryption: Some(key.clone()),
+        ..params()
+    };
+    let mut store = Store::sqlite_from_path(path.clone(), params).unwrap();
+    let big_hash = store.write(&big_bar()).unwrap();
+    drop(store);
+
+    let params = StoreParams {
+        encryption: Some(key),
+        ..Default::default()
+    };
+    let mut store = Store::sqlite_from_path(path, params).unwrap();
+    assert_eq!(store.get_from_hash(big_hash).unwrap(), Some(big_bar()));
+    let edited_hash = store.write(&edited_bar()).unwrap();
+    assert_eq!(store.get_from_hash(edited_hash).unwrap(), Some(edited_bar()));
+    assert_eq!(store.list_types().unwrap().len(), 1);
+}
+
+#[test]
+fn test_invalid_chunking() {
+    let params = StoreParams {
+        chunking: Some(Chunking {
+            min_chunk_bytes: 4096,
+            avg_chunk_bytes: 1024,
+            ..Chunking::default()
+        }),
+        ..Default::default()
+    };
+    let result = Store::from_backend(Box::new(Memory::new()), &params);
+    assert!(matches!(result, Err(Error::InvalidChunking(_))));
+}
+
+/// A manifest whose chunks don't add up to its recorded length is reported rather than trusted.
+#[test]
+fn test_chunked_length_mismatch() {
+    let mut memory = Memory::new();
+    let mut manifest = MANIFEST_MAGIC.to_vec();
+    manifest.extend_from_slice(&u64::MAX.to_le_bytes());
+    let content_hash = hash(&Foo { inner: 1 });
+    memory.write(Foo::ty(), content_hash, manifest).unwrap();
+    let mut store = Store::from_backend(Box::new(memory), &params()).unwrap();
+    let result = store.get_from_hash_raw(Foo::ty(), content_hash);
+    assert!(
+        matches!(
+            result,
+            Err(Error::ChunkedLengthMismatch {
+                expected: u64::MAX,
+                actual: 0,
+                ..
+            })
+        ),
+        "{:?}",
+        result
+    );
+}
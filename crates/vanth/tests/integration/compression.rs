@@ -0,0 +1,175 @@
+use std::path::Path;
+
+use tempfile::TempDir;
+use vanth::{
+    Ty, Vanth,
+    compression::Compression,
+    hash,
+    query::{Query, field},
+    store::{Backend, Encoding, Error, Memory, Store, StoreParams},
+};
+
+use crate::{
+    for_each_backend,
+    store::{Bar, Foo},
+};
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The flag bytes stored values start with.
+const UNCOMPRESSED: u8 = 0;
+const ZSTD: u8 = 1;
+
+fn params() -> StoreParams {
+    StoreParams {
+        compression: Some(Compression {
+            level: 3,
+            threshold_bytes: 64,
+        }),
+        ..Default::default()
+    }
+}
+
+fn big_bar() -> Bar {
+    Bar {
+        inner: "compressible ".repeat(100),
+    }
+}
+
+fn check_compression(store: &mut Store) {
+    let foo_hash = store.write(&Foo { inner: 1 }).unwrap();
+    let bar_hash = store.write(&big_bar()).unwrap();
+    assert_eq!(bar_hash, hash(&big_bar()));
+
+    assert_eq!(store.get_from_hash(foo_hash).unwrap(), Some(Foo { inner: 1 }));
+    assert_eq!(store.get_from_hash(bar_hash).unwrap(), Some(big_bar()));
+    // Raw values are decompressed too.
+    let raw = store.get_from_hash_raw(Bar::ty(), bar_hash).unwrap().unwrap();
+    assert_eq!(raw, store.encoding().encode(&big_bar()).unwrap());
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap(), [(bar_hash, big_bar())]);
+
+    // Sizes are as stored.
+    let info = store.type_info::<Bar>().unwrap().unwrap();
+    assert!(info.total_bytes < raw.len() as u64 / 4, "{} bytes", info.total_bytes);
+
+    // Fields are still found, although the backend can't see them.
+    store.create_index::<Bar>("inner").unwrap();
+    let found = store.find_by_field::<Bar>("inner", big_bar().inner).unwrap();
+    assert_eq!(found, [(bar_hash, big_bar())]);
+    let query = Query::new().filter(field("inner").unwrap().ne(""));
+    assert_eq!(store.query::<Bar>(&query).unwrap(), [(bar_hash, big_bar())]);
+
+    // Small values that look compressed read back as written.
+    let ty = Ty::from("test::Raw");
+    let looks_compressed = [ZSTD_MAGIC.as_slice(), &[1, 2, 3]].concat();
+    store.write_raw(ty.clone(), foo_hash, looks_compressed.clone()).unwrap();
+    assert_eq!(store.get_from_hash_raw(ty, foo_hash).unwrap(), Some(looks_compressed));
+}
+
+#[test]
+fn test_compression() {
+    for encoding in [Encoding::Json, Encoding::Bincode] {
+        let params = StoreParams {
+            encoding: Some(encoding),
+            ..params()
+        };
+        for_each_backend(params, check_compression);
+    }
+}
+
+/// The stored content of the value of `ty` with `content_hash`.
+fn stored(path: &Path, ty: Ty, content_hash: vanth::ContentHash) -> Vec<u8> {
+    let connection = rusqlite::Connection::open(path).unwrap();
+    connection
+        .query_row(
+            &format!("SELECT content FROM \"ty_{}\" WHERE content_hash = ?1", ty),
+            [content_hash.hash.to_vec()],
+            |row| row.get(0),
+        )
+        .unwrap()
+}
+
+/// Compressed and uncompressed rows can be mixed, whichever way the store is opened.
+#[test]
+fn test_sqlite_mixed_compression() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    let plain = Bar {
+        inner: "uncompressed ".repeat(100),
+    };
+    let plain_hash = store.write(&plain).unwrap();
+    drop(store);
+
+    let mut store = Store::sqlite_from_path(path.clone(), params()).unwrap();
+    let small_hash = store.write(&Foo { inner: 1 }).unwrap();
+    let big_hash = store.write(&big_bar()).unwrap();
+    drop(store);
+    assert!(stored(&path, Bar::ty(), big_hash).starts_with(&[&[ZSTD], ZSTD_MAGIC.as_slice()].concat()));
+    // The value written before compression was enabled was rewritten with a flag.
+    assert_eq!(
+        stored(&path, Bar::ty(), plain_hash),
+        [vec![UNCOMPRESSED], serde_json::to_vec(&plain).unwrap()].concat()
+    );
+    assert_eq!(
+        stored(&path, Foo::ty(), small_hash),
+        [vec![UNCOMPRESSED], br#"{"inner":1}"#.to_vec()].concat()
+    );
+
+    // Opening without compression still decompresses, but stores new values uncompressed.
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    let mut bars = store.get_all_of_type::<Bar>().unwrap();
+    bars.sort_by(|(_, a), (_, b)| a.inner.cmp(&b.inner));
+    assert_eq!(bars, [(big_hash, big_bar()), (plain_hash, plain)]);
+    let later = Bar {
+        inner: "later ".repeat(100),
+    };
+    let later_hash = store.write(&later).unwrap();
+    assert_eq!(store.get_from_hash(later_hash).unwrap(), Some(later.clone()));
+    drop(store);
+    assert_eq!(
+        stored(&path, Bar::ty(), later_hash),
+        [vec![UNCOMPRESSED], serde_json::to_vec(&later).unwrap()].concat()
+    );
+}
+
+/// Stored values that aren't valid frames are reported rather than returned as they are.
+#[test]
+fn test_corrupt_compression() {
+    let mut memory = Memory::new();
+    memory.set_metadata("compression", "zstd").unwrap();
+    let foo_hash = hash(&Foo { inner: 1 });
+    let corrupt = [&[ZSTD], ZSTD_MAGIC.as_slice(), &[1, 2, 3]].concat();
+    memory.write(Foo::ty(), foo_hash, corrupt).unwrap();
+    memory.write(Bar::ty(), foo_hash, Vec::new()).unwrap();
+    let mut store = Store::from_backend(Box::new(memory), &params()).unwrap();
+    for ty in [Foo::ty(), Bar::ty()] {
+        let result = store.get_from_hash_raw(ty.clone(), foo_hash);
+        assert!(matches!(result, Err(Error::Decompression { .. })), "{:?}", result);
+        assert!(store.get_all_of_type_raw(ty).is_err());
+    }
+}
+
+/// Enabling compression on a store with indexes drops them, as they can't be evaluated on compressed values.
+#[test]
+fn test_sqlite_compression_with_index() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    let plain = Bar { inner: "plain".into() };
+    let plain_hash = store.write(&plain).unwrap();
+    store.create_index::<Bar>("inner").unwrap();
+    drop(store);
+
+    let mut store = Store::sqlite_from_path(path, params()).unwrap();
+    let big_hash = store.write(&big_bar()).unwrap();
+    assert_eq!(
+        store.find_by_field::<Bar>("inner", big_bar().inner).unwrap(),
+        [(big_hash, big_bar())]
+    );
+    assert_eq!(
+        store.find_by_field::<Bar>("inner", "plain").unwrap(),
+        [(plain_hash, plain)]
+    );
+}
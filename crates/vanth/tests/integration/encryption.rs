@@ -0,0 +1,199 @@
+use std::{fs, path::Path};
+
+use tempfile::TempDir;
+use vanth::{
+    Vanth,
+    compression::Compression,
+    encryption::EncryptionKey,
+    hash,
+    query::{Query, field},
+    store::{Error, Store, StoreParams},
+};
+
+use crate::{
+    for_each_backend,
+    store::{Bar, Foo},
+};
+
+fn params(key: u8) -> StoreParams {
+    StoreParams {
+        encryption: Some(EncryptionKey::new([key; 32])),
+        ..Default::default()
+    }
+}
+
+fn secret() -> Bar {
+    Bar {
+        inner: "top secret".into(),
+    }
+}
+
+fn check_encryption(store: &mut Store) {
+    let foo_hash = store.write(&Foo { inner: 1 }).unwrap();
+    let bar_hash = store.write(&secret()).unwrap();
+    assert_eq!(bar_hash, hash(&secret()));
+
+    assert_eq!(store.get_from_hash(foo_hash).unwrap(), Some(Foo { inner: 1 }));
+    assert_eq!(store.get_from_hash(bar_hash).unwrap(), Some(secret()));
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap(), [(bar_hash, secret())]);
+    assert_eq!(store.find_by_hash(bar_hash).unwrap(), [Bar::ty()]);
+    assert_eq!(
+        store.find_by_field::<Bar>("inner", "top secret").unwrap(),
+        [(bar_hash, secret())]
+    );
+    let query = Query::new().filter(field("inner").unwrap().gt("a"));
+    assert_eq!(store.query::<Bar>(&query).unwrap(), [(bar_hash, secret())]);
+
+    store.delete::<Foo>(foo_hash).unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(foo_hash).unwrap(), None);
+}
+
+#[test]
+fn test_encryption() {
+    for_each_backend(params(1), check_encryption);
+}
+
+/// Values are encrypted in the database file, and can be read when it is reopened with the same key.
+#[test]
+fn test_sqlite_encryption() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    Store::sqlite_from_path(path.clone(), params(1))
+        .unwrap()
+        .write(&secret())
+        .unwrap();
+    assert!(!contains(&fs::read(&path).unwrap(), b"top secret"));
+
+    let mut store = Store::sqlite_from_path(path, params(1)).unwrap();
+    assert_eq!(store.get_from_hash(hash(&secret())).unwrap(), Some(secret()));
+}
+
+/// No file in a filesystem store contains a value in plain text.
+#[test]
+fn test_fs_encryption() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("store");
+    Store::fs_from_path(root.clone(), params(1))
+        .unwrap()
+        .write(&secret())
+        .unwrap();
+    for file in files(&root) {
+        assert!(
+            !contains(&fs::read(&file).unwrap(), b"top secret"),
+            "{}",
+            file.display()
+        );
+    }
+}
+
+#[test]
+fn test_wrong_key() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    Store::sqlite_from_path(path.clone(), params(1))
+        .unwrap()
+        .write(&secret())
+        .unwrap();
+
+    assert!(matches!(
+        Store::sqlite_from_path(path.clone(), params(2)),
+        Err(Error::WrongEncryptionKey)
+    ));
+    assert!(matches!(
+        Store::sqlite_from_path(path.clone(), StoreParams::default()),
+        Err(Error::EncryptionKeyRequired)
+    ));
+    let read_only = StoreParams {
+        read_only: true,
+        ..params(1)
+    };
+    let mut store = Store::sqlite_from_path(path, read_only).unwrap();
+    assert_eq!(store.get_from_hash(hash(&secret())).unwrap(), Some(secret()));
+
+    assert_eq!(format!("{:?}", EncryptionKey::new([7; 32])), "EncryptionKey(..)");
+}
+
+#[test]
+fn test_encrypting_existing_store() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    store.write(&secret()).unwrap();
+    drop(store);
+    assert!(matches!(
+        Store::sqlite_from_path(path, params(1)),
+        Err(Error::StoreNotEncrypted)
+    ));
+
+    // A store without values can still be encrypted.
+    let path = dir.path().join("empty.db");
+    Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    let mut store = Store::sqlite_from_path(path, params(1)).unwrap();
+    check_encryption(&mut store);
+}
+
+/// Values moved to another row fail to decrypt rather than being read as the wrong value.
+#[test]
+fn test_sqlite_tampering() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::sqlite_from_path(path.clone(), params(1)).unwrap();
+    let hash_1 = store.write(&Foo { inner: 1 }).unwrap();
+    let hash_2 = store.write(&Foo { inner: 2 }).unwrap();
+    drop(store);
+
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    let table = format!("\"ty_{}\"", Foo::ty());
+    connection
+        .execute(
+            &format!(
+                "UPDATE {table} SET content = (SELECT content FROM {table} WHERE content_hash = ?1) WHERE content_hash = ?2"
+            ),
+            [hash_2.hash.to_vec(), hash_1.hash.to_vec()],
+        )
+        .unwrap();
+    drop(connection);
+
+    let mut store = Store::sqlite_from_path(path, params(1)).unwrap();
+    assert_eq!(store.get_from_hash(hash_2).unwrap(), Some(Foo { inner: 2 }));
+    let result = store.get_from_hash::<Foo>(hash_1);
+    assert!(
+        matches!(result, Err(Error::Decryption { content_hash, .. }) if content_hash == hash_1),
+        "{:?}",
+        result
+    );
+}
+
+/// Values are compressed before they are encrypted, since ciphertext doesn't compress.
+#[test]
+fn test_sqlite_compression_and_encryption() {
+    let dir = TempDir::new().unwrap();
+    let params = StoreParams {
+        compression: Some(Compression::default()),
+        ..params(1)
+    };
+    let mut store = Store::sqlite_from_path(dir.path().join("test.db"), params).unwrap();
+    let bar = Bar {
+        inner: "secret ".repeat(100),
+    };
+    let bar_hash = store.write(&bar).unwrap();
+    assert_eq!(store.get_from_hash(bar_hash).unwrap(), Some(bar));
+    assert!(store.type_info::<Bar>().unwrap().unwrap().total_bytes < 200);
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+fn files(dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(self::files(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
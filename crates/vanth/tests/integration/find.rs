@@ -0,0 +1,91 @@
+use tempfile::TempDir;
+use vanth::{
+    Vanth, hash,
+    store::{Error, Store, StoreParams},
+};
+
+use crate::{
+    for_each_backend,
+    store::{Bar, Foo},
+};
+
+fn check_find_by_hash(store: &mut Store) {
+    let value = Foo { inner: 1 };
+    let content_hash = store.write(&value).unwrap();
+    let data = store.encoding().encode(&value).unwrap();
+    store.write_raw(Bar::ty(), content_hash, data.clone()).unwrap();
+    store.write(&Foo { inner: 2 }).unwrap();
+
+    let mut expected = vec![Bar::ty(), Foo::ty()];
+    expected.sort_by_key(|ty| ty.to_string());
+    assert_eq!(store.find_by_hash(content_hash).unwrap(), expected);
+    assert!(store.find_by_hash(hash(&Foo { inner: 3 })).unwrap().is_empty());
+
+    // Staged changes are seen inside a transaction.
+    let mut transaction = store.begin().unwrap();
+    transaction.delete_all::<Bar>().unwrap();
+    assert_eq!(transaction.find_by_hash(content_hash).unwrap(), vec![Foo::ty()]);
+    transaction.rollback().unwrap();
+    assert_eq!(store.find_by_hash(content_hash).unwrap(), expected);
+
+    assert_eq!(store.delete_everywhere(content_hash).unwrap(), expected);
+    assert!(store.find_by_hash(content_hash).unwrap().is_empty());
+    assert_eq!(store.get_from_hash::<Foo>(content_hash).unwrap(), None);
+    assert!(store.get_all_of_type_raw(Bar::ty()).unwrap().is_empty());
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 1);
+
+    // Dropping a table removes its values from the index.
+    store.write_raw(Bar::ty(), content_hash, data).unwrap();
+    store.delete_all::<Bar>().unwrap();
+    assert!(store.find_by_hash(content_hash).unwrap().is_empty());
+}
+
+#[test]
+fn test_find_by_hash() {
+    for_each_backend(StoreParams::default(), check_find_by_hash);
+}
+
+/// Values written before the hash index existed are still found, including in a read-only database.
+#[test]
+fn test_sqlite_find_by_hash_without_index() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    let content_hash = store.write(&Foo { inner: 1 }).unwrap();
+    drop(store);
+
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    connection.execute("DROP TABLE vanth_hash_index", []).unwrap();
+    drop(connection);
+
+    let params = StoreParams {
+        read_only: true,
+        ..Default::default()
+    };
+    let mut store = Store::sqlite_from_path(path.clone(), params).unwrap();
+    assert_eq!(store.find_by_hash(content_hash).unwrap(), vec![Foo::ty()]);
+    drop(store);
+
+    let mut store = Store::sqlite_from_path(path, StoreParams::default()).unwrap();
+    assert_eq!(store.find_by_hash(content_hash).unwrap(), vec![Foo::ty()]);
+    assert_eq!(store.delete_everywhere(content_hash).unwrap(), vec![Foo::ty()]);
+    assert!(store.find_by_hash(content_hash).unwrap().is_empty());
+}
+
+/// A pinned copy stops every copy from being deleted, not just itself.
+#[test]
+fn test_delete_everywhere_pinned() {
+    let dir = TempDir::new().unwrap();
+    let mut store = Store::sqlite_from_path(dir.path().join("test.db"), StoreParams::default()).unwrap();
+    let value = Foo { inner: 1 };
+    let content_hash = store.write(&value).unwrap();
+    let data = store.encoding().encode(&value).unwrap();
+    store.write_raw(Bar::ty(), content_hash, data).unwrap();
+    let tys = store.find_by_hash(content_hash).unwrap();
+    // Pin the copy deleted last.
+    store.pin_raw(tys[1].clone(), content_hash).unwrap();
+
+    let result = store.delete_everywhere(content_hash);
+    assert!(matches!(result, Err(Error::Pinned { .. })), "{:?}", result);
+    assert_eq!(store.find_by_hash(content_hash).unwrap(), tys);
+}
@@ -1 +1,94 @@
+use std::fs;
 
+use tempfile::TempDir;
+use vanth::{
+    Ty, Vanth, hash,
+    store::{Error, Store, StoreParams, Verifier},
+};
+
+use crate::store::{Bar, Foo};
+
+#[test]
+fn test_fs_store() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("store");
+    let mut store = Store::fs_from_path(root.clone(), StoreParams::default()).unwrap();
+
+    let foo_1 = Foo { inner: 1 };
+    let foo_2 = Foo { inner: 2 };
+    let bar_1 = Bar { inner: "hello".into() };
+    store.write(&foo_1).unwrap();
+    let foo_2_hash = store.write(&foo_2).unwrap();
+    store.write(&bar_1).unwrap();
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 2);
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap().len(), 1);
+    assert_eq!(store.get_from_hash(foo_2_hash).unwrap(), Some(foo_2.clone()));
+
+    // Values are plain files at a predictable path.
+    let hex = foo_2_hash.hex();
+    let ty_dir = Foo::ty().to_string().replace(":", "%3A");
+    let path = root.join("types").join(ty_dir).join(&hex[..2]).join(&hex[2..]);
+    assert_eq!(fs::read(&path).unwrap(), serde_json::to_vec(&foo_2).unwrap());
+    assert_eq!(fs::read_dir(root.join("tmp")).unwrap().count(), 0);
+
+    let mut tys = store.verify(&Verifier::new()).unwrap().unchecked_tys;
+    tys.sort_by_key(|ty| ty.to_string());
+    assert_eq!(tys, vec![Bar::ty(), Foo::ty()]);
+
+    store.delete::<Foo>(foo_2_hash).unwrap();
+    assert!(!path.exists());
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 1);
+    drop(store);
+
+    let mut store = Store::fs_from_path(
+        root.clone(),
+        StoreParams {
+            read_only: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(store.get_from_hash(hash(&foo_1)).unwrap(), Some(foo_1));
+    assert!(matches!(store.write(&foo_2), Err(Error::ReadOnly)));
+    drop(store);
+
+    let mut store = Store::fs_from_path(root, StoreParams::default()).unwrap();
+    store.delete_all::<Foo>().unwrap();
+    store.delete_all::<Bar>().unwrap();
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 0);
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap().len(), 0);
+
+    let missing = dir.path().join("missing");
+    let params = StoreParams {
+        create_if_not_exists: false,
+        ..Default::default()
+    };
+    assert!(Store::fs_from_path(missing, params).is_err());
+}
+
+/// Types named like `.` or `..` get their own directories rather than the store's.
+#[test]
+fn test_fs_dot_types() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("store");
+    let mut store = Store::fs_from_path(root.clone(), StoreParams::default()).unwrap();
+    let foo_hash = store.write(&Foo { inner: 1 }).unwrap();
+    for name in [".", "..", ".hidden"] {
+        store.write_raw(Ty::from(name), foo_hash, b"{}".to_vec()).unwrap();
+        assert_eq!(store.get_all_of_type_raw(Ty::from(name)).unwrap().len(), 1);
+    }
+    let mut tys: Vec<String> = store
+        .list_types()
+        .unwrap()
+        .iter()
+        .map(|info| info.ty.to_string())
+        .collect();
+    tys.sort();
+    assert_eq!(tys, [".", "..", ".hidden", Foo::ty().to_string().as_str()]);
+
+    for name in [".", "..", ".hidden"] {
+        store.delete_all_raw(Ty::from(name)).unwrap();
+    }
+    assert!(root.join("types").is_dir());
+    assert_eq!(store.get_from_hash::<Foo>(foo_hash).unwrap(), Some(Foo { inner: 1 }));
+}
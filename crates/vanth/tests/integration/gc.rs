@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+use vanth::{
+    ContentHash, Ty, Vanth,
+    gc::{Collector, GcParams, References},
+    hash,
+    migration::HashMapping,
+    store::Store,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Vanth)]
+struct Leaf {
+    value: i32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Vanth)]
+struct Node {
+    children: Vec<ContentHash>,
+}
+
+impl References for Node {
+    fn references(&self) -> Vec<(Ty, ContentHash)> {
+        self.children.iter().map(|child| (Leaf::ty(), *child)).collect()
+    }
+}
+
+#[test]
+fn test_collect_garbage() {
+    let mut store = Store::in_memory().unwrap();
+    let leaf_1 = store.write(&Leaf { value: 1 }).unwrap();
+    let leaf_2 = store.write(&Leaf { value: 2 }).unwrap();
+    let leaf_3 = store.write(&Leaf { value: 3 }).unwrap();
+    let missing = hash(&Leaf { value: 4 });
+    let node_1 = store
+        .write(&Node {
+            children: vec![leaf_1, missing],
+        })
+        .unwrap();
+    let node_2 = store.write(&Node { children: vec![leaf_2] }).unwrap();
+
+    store.set_root("main", Node::ty(), node_1).unwrap();
+    assert_eq!(store.roots().unwrap().len(), 1);
+
+    let collector = Collector::new().with::<Node>();
+    let report = store
+        .collect_garbage(
+            &collector,
+            &GcParams {
+                dry_run: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(report.reachable, 2);
+    assert_eq!(report.missing, vec![(Leaf::ty(), missing)]);
+    let mut unreachable = report.unreachable.clone();
+    unreachable.sort_by_key(|(_, content_hash)| *content_hash);
+    let mut expected = vec![(Leaf::ty(), leaf_2), (Leaf::ty(), leaf_3), (Node::ty(), node_2)];
+    expected.sort_by_key(|(_, content_hash)| *content_hash);
+    assert_eq!(unreachable, expected);
+    assert!(report.freed_bytes > 0);
+    assert_eq!(store.get_all_of_type::<Leaf>().unwrap().len(), 3);
+
+    // An extra root keeps `node_2` and its child alive.
+    let report = store
+        .collect_garbage(
+            &collector,
+            &GcParams {
+                roots: vec![(Node::ty(), node_2)],
+                dry_run: false,
+            },
+        )
+        .unwrap();
+    assert_eq!(report.unreachable, vec![(Leaf::ty(), leaf_3)]);
+    assert_eq!(store.get_from_hash::<Leaf>(leaf_3).unwrap(), None);
+    assert_eq!(store.get_all_of_type::<Leaf>().unwrap().len(), 2);
+
+    assert!(store.remove_root("main").unwrap());
+    assert!(!store.remove_root("main").unwrap());
+    store.collect_garbage(&collector, &GcParams::default()).unwrap();
+    assert_eq!(store.get_all_of_type::<Leaf>().unwrap().len(), 0);
+    assert_eq!(store.get_all_of_type::<Node>().unwrap().len(), 0);
+}
+
+/// Migration records aren't referred to by anything, but are kept.
+#[test]
+fn test_collect_garbage_keeps_hash_mappings() {
+    let mut store = Store::in_memory().unwrap();
+    let mapping = HashMapping {
+        migration: "leaf_v2".into(),
+        from: Leaf::ty(),
+        old: hash(&Leaf { value: 1 }),
+        to: Leaf::ty(),
+        new: hash(&Leaf { value: 2 }),
+    };
+    store.write(&mapping).unwrap();
+    store.write(&Leaf { value: 2 }).unwrap();
+
+    let report = store.collect_garbage(&Collector::new(), &GcParams::default()).unwrap();
+    assert_eq!(report.unreachable.len(), 1);
+    assert_eq!(store.hash_mappings("leaf_v2").unwrap(), [mapping]);
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use vanth::{
+    Vanth,
+    store::{Encoding, Error, Store, StoreParams},
+};
+
+use crate::for_each_backend;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, Vanth)]
+struct Player {
+    name: String,
+    team: i32,
+    active: bool,
+    stats: Stats,
+    scores: Vec<i32>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct Stats {
+    level: f64,
+}
+
+fn player(name: &str, team: i32, active: bool, level: f64) -> Player {
+    Player {
+        name: name.into(),
+        team,
+        active,
+        stats: Stats { level },
+        scores: vec![team * 10],
+    }
+}
+
+fn names(found: Vec<(vanth::ContentHash, Player)>) -> Vec<String> {
+    let mut names: Vec<String> = found.into_iter().map(|(_, player)| player.name).collect();
+    names.sort();
+    names
+}
+
+/// Lookups give the same results whether or not the fields are indexed.
+fn check_find_by_field(store: &mut Store, indexed: bool) {
+    let alice = player("alice", 3, true, 2.0);
+    let bob = player("bob", 3, false, 2.5);
+    store.write(&alice).unwrap();
+    let bob_hash = store.write(&bob).unwrap();
+    store.write(&player("carol", 4, true, 2.0)).unwrap();
+    if indexed {
+        for path in ["team", "name", "stats.level", "active", "scores.0"] {
+            store.create_index::<Player>(path).unwrap();
+        }
+    }
+
+    assert_eq!(names(store.find_by_field("team", 3).unwrap()), ["alice", "bob"]);
+    assert_eq!(names(store.find_by_field("name", "carol").unwrap()), ["carol"]);
+    // Integers and reals with the same value are equal.
+    assert_eq!(
+        names(store.find_by_field("stats.level", 2).unwrap()),
+        ["alice", "carol"]
+    );
+    assert_eq!(names(store.find_by_field("active", true).unwrap()), ["alice", "carol"]);
+    assert_eq!(names(store.find_by_field("scores.0", 40).unwrap()), ["carol"]);
+    // Values of different kinds are never equal.
+    assert!(store.find_by_field::<Player>("team", "3").unwrap().is_empty());
+    // Missing fields are null.
+    assert_eq!(names(store.find_by_field("nickname", ()).unwrap()).len(), 3);
+    assert!(matches!(
+        store.find_by_field::<Player>("team..x", 3),
+        Err(Error::InvalidFieldPath(_))
+    ));
+
+    // Changes are seen by lookups, including those staged in a transaction.
+    store
+        .transaction(|tx| {
+            tx.write(&player("dave", 3, true, 1.0))?;
+            tx.delete::<Player>(bob_hash)?;
+            assert_eq!(names(tx.find_by_field("team", 3)?), ["alice", "dave"]);
+            Err::<(), _>(Error::NoTransactionOpen)
+        })
+        .unwrap_err();
+    assert_eq!(names(store.find_by_field("team", 3).unwrap()), ["alice", "bob"]);
+
+    store.delete::<Player>(bob_hash).unwrap();
+    store.write(&player("erin", 3, false, 3.0)).unwrap();
+    assert_eq!(names(store.find_by_field("team", 3).unwrap()), ["alice", "erin"]);
+
+    store.delete_all::<Player>().unwrap();
+    assert!(store.find_by_field::<Player>("team", 3).unwrap().is_empty());
+    store.write(&alice).unwrap();
+    assert_eq!(names(store.find_by_field("team", 3).unwrap()), ["alice"]);
+}
+
+#[test]
+fn test_find_by_field() {
+    for encoding in [Encoding::Json, Encoding::Cbor, Encoding::Bincode] {
+        for indexed in [false, true] {
+            let params = StoreParams {
+                encoding: Some(encoding),
+                ..Default::default()
+            };
+            for_each_backend(params, |store| check_find_by_field(store, indexed));
+        }
+    }
+}
+
+/// SQLite indexes are expression indexes on the type's table.
+#[test]
+fn test_sqlite_index() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    store.create_index::<Player>("stats.level").unwrap();
+
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    let sql: String = connection
+        .query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'index' AND name LIKE 'vanth_index_%'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert!(
+        sql.contains("json_extract(CAST(content AS TEXT), '$.stats.level')"),
+        "{}",
+        sql
+    );
+}
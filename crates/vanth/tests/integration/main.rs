@@ -1,7 +1,38 @@
 use serde::{Deserialize, Serialize};
-use vanth::{Node, Reference};
+use tempfile::TempDir;
+use vanth::{
+    Node, Reference,
+    store::{Memory, Store, StoreParams},
+};
 
+mod alias;
+mod archive;
+mod async_store;
+mod cache;
+mod catalog;
+mod changes;
+mod chunking;
+mod compression;
 mod derive;
+mod encryption;
+mod find;
 mod fs;
+mod gc;
+mod index;
+mod migration;
+mod overlay;
+mod pins;
+mod query;
+mod shared_store;
+mod snapshot;
+mod sqlite;
 mod reference;
 mod store;
+
+/// Run `check` on a new SQLite, in-memory and filesystem store, each opened with `params`.
+pub(crate) fn for_each_backend(params: StoreParams, check: impl Fn(&mut Store)) {
+    let dir = TempDir::new().unwrap();
+    check(&mut Store::sqlite_from_path(dir.path().join("test.db"), params.clone()).unwrap());
+    check(&mut Store::from_backend(Box::new(Memory::new()), &params).unwrap());
+    check(&mut Store::fs_from_path(dir.path().join("store"), params).unwrap());
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use vanth::{
+    Ty, Vanth, hash,
+    migration::{HashMapping, Migrations},
+    store::{Store, StoreParams},
+};
+
+use crate::for_each_backend;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct OldPosition {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Vanth)]
+struct Position {
+    coordinates: [i32; 2],
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Vanth)]
+struct Name {
+    value: String,
+}
+
+#[test]
+fn test_migrate() {
+    let dir = TempDir::new().unwrap();
+    let mut store = Store::sqlite_from_path(dir.path().join("test.db"), StoreParams::default()).unwrap();
+
+    let old_position_ty = Ty::from("old::module::Position");
+    let old_name_ty = Ty::from("old::module::Name");
+    let old_position = OldPosition { x: 1, y: 2 };
+    let old_position_hash = hash(&old_position);
+    store
+        .write_raw(
+            old_position_ty.clone(),
+            old_position_hash,
+            serde_json::to_vec(&old_position).unwrap(),
+        )
+        .unwrap();
+    let name = Name { value: "a".into() };
+    let name_hash = hash(&name);
+    store
+        .write_raw(old_name_ty.clone(), name_hash, serde_json::to_vec(&name).unwrap())
+        .unwrap();
+
+    let migrations = |old_name_ty: &Ty, old_position_ty: &Ty| {
+        Migrations::new()
+            .rename("rename-name", old_name_ty.clone(), Name::ty())
+            .transform("restructure-position", old_position_ty.clone(), |old: OldPosition| {
+                Position {
+                    coordinates: [old.x, old.y],
+                }
+            })
+    };
+    let report = store.migrate(&migrations(&old_name_ty, &old_position_ty)).unwrap();
+    assert_eq!(report.applied, vec!["rename-name", "restructure-position"]);
+    assert_eq!(report.values, 2);
+
+    assert_eq!(store.get_from_hash(name_hash).unwrap(), Some(name));
+    assert_eq!(store.get_all_of_type_raw(old_name_ty.clone()).unwrap().len(), 0);
+    assert_eq!(store.get_all_of_type_raw(old_position_ty.clone()).unwrap().len(), 0);
+
+    let position = Position { coordinates: [1, 2] };
+    let position_hash = hash(&position);
+    assert_eq!(store.get_from_hash(position_hash).unwrap(), Some(position));
+    assert_eq!(
+        store.hash_mappings("restructure-position").unwrap(),
+        vec![HashMapping {
+            migration: "restructure-position".into(),
+            from: old_position_ty.clone(),
+            old: old_position_hash,
+            to: Position::ty(),
+            new: position_hash,
+        }]
+    );
+    assert!(store.hash_mappings("rename-name").unwrap().is_empty());
+
+    // Migrations are only applied once.
+    let report = store.migrate(&migrations(&old_name_ty, &old_position_ty)).unwrap();
+    assert!(report.applied.is_empty());
+    assert_eq!(
+        store.applied_migrations().unwrap(),
+        vec!["rename-name", "restructure-position"]
+    );
+}
+
+#[test]
+fn test_migrate_in_place() {
+    let mut store = Store::in_memory().unwrap();
+    let names: Vec<_> = (0..5)
+        .map(|i| store.write(&Name { value: i.to_string() }).unwrap())
+        .collect();
+
+    let migrations = Migrations::new().transform("prefix-names", Name::ty(), |old: Name| Name {
+        value: format!("name {}", old.value),
+    });
+    let report = store.migrate(&migrations).unwrap();
+    assert_eq!(report.values, 5);
+
+    let mut values: Vec<_> = store
+        .get_all_of_type::<Name>()
+        .unwrap()
+        .into_iter()
+        .map(|(_, name)| name.value)
+        .collect();
+    values.sort();
+    assert_eq!(values, vec!["name 0", "name 1", "name 2", "name 3", "name 4"]);
+    assert_eq!(store.hash_mappings("prefix-names").unwrap().len(), names.len());
+}
+
+/// A migration that fails part way through changes nothing and isn't recorded, so it runs again once fixed.
+fn check_failed_migration(store: &mut Store) {
+    let old_position_ty = Ty::from("old::module::Position");
+    let old_position = OldPosition { x: 1, y: 2 };
+    store
+        .write_raw(
+            old_position_ty.clone(),
+            hash(&old_position),
+            serde_json::to_vec(&old_position).unwrap(),
+        )
+        .unwrap();
+    let invalid = hash(&"invalid");
+    store
+        .write_raw(old_position_ty.clone(), invalid, b"\"invalid\"".to_vec())
+        .unwrap();
+
+    let migrations =
+        Migrations::new().transform("restructure-position", old_position_ty.clone(), |old: OldPosition| {
+            Position {
+                coordinates: [old.x, old.y],
+            }
+        });
+    assert!(store.migrate(&migrations).is_err());
+    assert!(store.applied_migrations().unwrap().is_empty());
+    assert_eq!(store.get_all_of_type_raw(old_position_ty.clone()).unwrap().len(), 2);
+    assert!(store.get_all_of_type::<Position>().unwrap().is_empty());
+
+    store.delete_raw(old_position_ty, invalid).unwrap();
+    let report = store.migrate(&migrations).unwrap();
+    assert_eq!(report.applied, vec!["restructure-position"]);
+    assert_eq!(store.applied_migrations().unwrap(), vec!["restructure-position"]);
+}
+
+#[test]
+fn test_failed_migration() {
+    for_each_backend(StoreParams::default(), check_failed_migration);
+}
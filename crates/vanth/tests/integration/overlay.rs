@@ -0,0 +1,242 @@
+use std::path::Path;
+
+use tempfile::TempDir;
+use vanth::{
+    ContentHash, Ty, Vanth,
+    compression::Compression,
+    hash,
+    migration::Migrations,
+    store::{Backend, Encoding, Error, IterParams, Memory, Sqlite, Store, StoreParams},
+};
+
+use crate::store::{Bar, Foo};
+
+/// Create a base database with `Foo`s 1 to 5 and one `Bar`, and open it with `read_only`.
+fn base(path: &Path, read_only: bool) -> Box<dyn Backend> {
+    let mut store = Store::sqlite_from_path(path.into(), StoreParams::default()).unwrap();
+    for inner in 1..=5 {
+        store.write(&Foo { inner }).unwrap();
+    }
+    store.write(&Bar { inner: "base".into() }).unwrap();
+    drop(store);
+    let params = StoreParams {
+        read_only,
+        ..Default::default()
+    };
+    Box::new(Sqlite::open(path.into(), &params).unwrap())
+}
+
+/// Open the database at `path` read-only, to be the base of an overlay.
+fn base_backend(path: &Path) -> Box<dyn Backend> {
+    let params = StoreParams {
+        read_only: true,
+        ..Default::default()
+    };
+    Box::new(Sqlite::open(path.into(), &params).unwrap())
+}
+
+fn overlay(base: Box<dyn Backend>) -> Store {
+    Store::overlay(base, Box::new(Memory::new()), &StoreParams::default()).unwrap()
+}
+
+fn foos(store: &mut Store) -> Vec<i32> {
+    let mut foos: Vec<i32> = store
+        .get_all_of_type::<Foo>()
+        .unwrap()
+        .into_iter()
+        .map(|(_, value)| value.inner)
+        .collect();
+    foos.sort();
+    foos
+}
+
+fn foo_hash(inner: i32) -> ContentHash {
+    hash(&Foo { inner })
+}
+
+/// Make changes to an overlay over the base from [`base`], and check that reads see them.
+fn check_overlay(store: &mut Store) {
+    assert_eq!(foos(store), [1, 2, 3, 4, 5]);
+    assert_eq!(store.get_from_hash(foo_hash(1)).unwrap(), Some(Foo { inner: 1 }));
+
+    store.write(&Foo { inner: 6 }).unwrap();
+    store.delete::<Foo>(foo_hash(1)).unwrap();
+    store.delete::<Foo>(foo_hash(6)).unwrap();
+    store.write(&Foo { inner: 7 }).unwrap();
+    store.delete::<Foo>(foo_hash(2)).unwrap();
+    // Writing a deleted value again brings it back.
+    store.write(&Foo { inner: 2 }).unwrap();
+    store.delete_all::<Bar>().unwrap();
+
+    assert_eq!(foos(store), [2, 3, 4, 5, 7]);
+    assert_eq!(store.get_from_hash::<Foo>(foo_hash(1)).unwrap(), None);
+    assert_eq!(store.get_from_hash::<Foo>(foo_hash(6)).unwrap(), None);
+    assert!(store.get_all_of_type::<Bar>().unwrap().is_empty());
+    assert!(store.find_by_hash(foo_hash(1)).unwrap().is_empty());
+    assert_eq!(store.find_by_hash(foo_hash(2)).unwrap(), [Foo::ty()]);
+
+    let info = store.type_info::<Foo>().unwrap().unwrap();
+    let expected_bytes: usize = store
+        .get_all_of_type_raw(Foo::ty())
+        .unwrap()
+        .iter()
+        .map(|(_, data)| data.len())
+        .sum();
+    assert_eq!((info.count, info.total_bytes), (5, expected_bytes as u64));
+    // A type whose values have all been deleted is gone, as if its table had been dropped.
+    assert_eq!(store.type_info::<Bar>().unwrap(), None);
+    let tys: Vec<Ty> = store.list_types().unwrap().into_iter().map(|info| info.ty).collect();
+    assert_eq!(tys, [Foo::ty()]);
+
+    // Pages merge both layers in hash order.
+    let all: Vec<ContentHash> = store
+        .get_all_of_type_raw(Foo::ty())
+        .unwrap()
+        .into_iter()
+        .map(|(h, _)| h)
+        .collect();
+    let params = IterParams {
+        offset: 1,
+        page_size: 2,
+        ..Default::default()
+    };
+    let paged: Vec<ContentHash> = store.iter_of_type::<Foo>(params).map(|item| item.unwrap().0).collect();
+    assert_eq!(paged, all[1..]);
+
+    let mut transaction = store.begin().unwrap();
+    transaction.delete::<Foo>(foo_hash(3)).unwrap();
+    transaction.rollback().unwrap();
+    assert_eq!(foos(store), [2, 3, 4, 5, 7]);
+}
+
+#[test]
+fn test_overlay() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("base.db");
+    let params = StoreParams {
+        cache_size_bytes: 1 << 20,
+        ..Default::default()
+    };
+    let mut store = Store::overlay(base(&path, true), Box::new(Memory::new()), &params).unwrap();
+    check_overlay(&mut store);
+    assert!(store.cache_stats().is_some());
+
+    // The base is untouched, and can't be flushed to.
+    let mut reopened = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    assert_eq!(foos(&mut reopened), [1, 2, 3, 4, 5]);
+    assert!(store.flush().is_err());
+    assert_eq!(foos(&mut store), [2, 3, 4, 5, 7]);
+    assert_eq!(foos(&mut reopened), [1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_overlay_flush() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("base.db");
+    let mut store = overlay(base(&path, false));
+    check_overlay(&mut store);
+    store.flush().unwrap();
+    assert_eq!(foos(&mut store), [2, 3, 4, 5, 7]);
+    drop(store);
+
+    let mut reopened = Store::sqlite_from_path(path, StoreParams::default()).unwrap();
+    assert_eq!(foos(&mut reopened), [2, 3, 4, 5, 7]);
+    assert!(reopened.get_all_of_type::<Bar>().unwrap().is_empty());
+    // Whiteouts are not flushed as values.
+    let mut tys: Vec<String> = reopened
+        .list_types()
+        .unwrap()
+        .into_iter()
+        .map(|info| info.ty.to_string())
+        .collect();
+    tys.sort();
+    assert_eq!(tys, [Bar::ty().to_string(), Foo::ty().to_string()]);
+}
+
+/// Changes, including deletes, persist in an upper layer stored on disk.
+#[test]
+fn test_overlay_persistent_upper() {
+    let dir = TempDir::new().unwrap();
+    let base_path = dir.path().join("base.db");
+    let upper_path = dir.path().join("upper.db");
+    base(&base_path, true);
+    let open = || {
+        let base = base_backend(&base_path);
+        let upper = Sqlite::open(upper_path.clone(), &StoreParams::default()).unwrap();
+        Store::overlay(base, Box::new(upper), &StoreParams::default()).unwrap()
+    };
+
+    let mut store = open();
+    store.delete::<Foo>(foo_hash(1)).unwrap();
+    store.write(&Foo { inner: 6 }).unwrap();
+    drop(store);
+    assert_eq!(foos(&mut open()), [2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn test_overlay_encoding() {
+    let dir = TempDir::new().unwrap();
+    let params = StoreParams {
+        encoding: Some(Encoding::Cbor),
+        ..Default::default()
+    };
+    let path = dir.path().join("base.db");
+    Store::sqlite_from_path(path.clone(), params)
+        .unwrap()
+        .write(&Foo { inner: 1 })
+        .unwrap();
+    let base = || base_backend(&path);
+
+    // An empty upper layer takes the base's encoding.
+    let mut store = overlay(base());
+    assert_eq!(store.encoding(), Encoding::Cbor);
+    store.write(&Foo { inner: 2 }).unwrap();
+    assert_eq!(foos(&mut store), [1, 2]);
+    drop(store);
+
+    // An upper layer with values written before encodings were recorded holds JSON.
+    let mut upper = Memory::new();
+    let foo = Foo { inner: 3 };
+    upper
+        .write(Foo::ty(), hash(&foo), serde_json::to_vec(&foo).unwrap())
+        .unwrap();
+    assert!(matches!(
+        Store::overlay(base(), Box::new(upper), &StoreParams::default()),
+        Err(Error::EncodingMismatch {
+            stored: Encoding::Cbor,
+            requested: Encoding::Json
+        })
+    ));
+}
+
+/// Metadata set through the overlay is flushed with the values, so the base can be opened alone afterwards.
+#[test]
+fn test_overlay_flush_metadata() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("base.db");
+    let params = StoreParams {
+        encoding: Some(Encoding::Cbor),
+        compression: Some(Compression::default()),
+        ..Default::default()
+    };
+    let base = Sqlite::open(path.clone(), &StoreParams::default()).unwrap();
+    let mut store = Store::overlay(Box::new(base), Box::new(Memory::new()), &params).unwrap();
+    let big = Bar {
+        inner: "compressible ".repeat(100),
+    };
+    let big_hash = store.write(&big).unwrap();
+    store.set_root("main", Bar::ty(), big_hash).unwrap();
+    let migrations = || Migrations::new().rename("rename-bar", Ty::from("old::Bar"), Bar::ty());
+    assert_eq!(store.migrate(&migrations()).unwrap().applied, ["rename-bar"]);
+    store.flush().unwrap();
+    drop(store);
+
+    let mut base = Store::sqlite_from_path(path, StoreParams::default()).unwrap();
+    assert_eq!(base.encoding(), Encoding::Cbor);
+    assert_eq!(base.get_from_hash(big_hash).unwrap(), Some(big));
+    assert_eq!(base.roots().unwrap().len(), 1);
+    assert!(base.migrate(&migrations()).unwrap().applied.is_empty());
+    // The records of the metadata aren't flushed as values.
+    let tys: Vec<Ty> = base.list_types().unwrap().into_iter().map(|info| info.ty).collect();
+    assert_eq!(tys, [Bar::ty()]);
+}
@@ -0,0 +1,157 @@
+use std::thread;
+
+use tempfile::TempDir;
+use vanth::{
+    Ty, Vanth,
+    gc::{Collector, GcParams},
+    hash,
+    pins::{Pin, PinnedDeletes},
+    store::{Error, Memory, Store, StoreParams},
+};
+
+use crate::{
+    for_each_backend,
+    store::{Bar, Foo},
+};
+
+fn check_pins(store: &mut Store) {
+    let pinned = store.write(&Foo { inner: 1 }).unwrap();
+    let unpinned = store.write(&Foo { inner: 2 }).unwrap();
+    store.write(&Bar { inner: "bar".into() }).unwrap();
+
+    assert_eq!(store.pin::<Foo>(pinned).unwrap(), 1);
+    assert_eq!(store.pin::<Foo>(pinned).unwrap(), 2);
+    assert_eq!(store.pin_count::<Foo>(pinned).unwrap(), 2);
+    assert_eq!(store.pin_count::<Foo>(unpinned).unwrap(), 0);
+    assert_eq!(
+        store.pins().unwrap(),
+        [Pin {
+            ty: Foo::ty(),
+            content_hash: pinned,
+            count: 2,
+            delete_pending: false,
+        }]
+    );
+
+    assert!(matches!(
+        store.delete::<Foo>(pinned),
+        Err(Error::Pinned { content_hash, .. }) if content_hash == pinned
+    ));
+    assert!(matches!(store.delete_all::<Foo>(), Err(Error::Pinned { .. })));
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 2);
+    store.delete::<Foo>(unpinned).unwrap();
+    store.delete_all::<Bar>().unwrap();
+
+    // Pins are hidden from the store's types.
+    let types: Vec<Ty> = store.list_types().unwrap().into_iter().map(|info| info.ty).collect();
+    assert_eq!(types, [Foo::ty()]);
+
+    // Pinned values are kept by garbage collection.
+    let report = store.collect_garbage(&Collector::new(), &GcParams::default()).unwrap();
+    assert!(report.unreachable.is_empty());
+    assert_eq!(store.get_from_hash::<Foo>(pinned).unwrap(), Some(Foo { inner: 1 }));
+
+    assert_eq!(store.unpin::<Foo>(pinned).unwrap(), 1);
+    assert_eq!(store.unpin::<Foo>(pinned).unwrap(), 0);
+    assert_eq!(store.unpin::<Foo>(pinned).unwrap(), 0);
+    assert_eq!(store.pins().unwrap(), []);
+    assert_eq!(store.get_from_hash::<Foo>(pinned).unwrap(), Some(Foo { inner: 1 }));
+    store.delete::<Foo>(pinned).unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(pinned).unwrap(), None);
+
+    // A value can be pinned before it is written.
+    let later = hash(&Bar { inner: "later".into() });
+    assert_eq!(store.pin::<Bar>(later).unwrap(), 1);
+    store.write(&Bar { inner: "later".into() }).unwrap();
+    assert!(matches!(store.delete::<Bar>(later), Err(Error::Pinned { .. })));
+    store.unpin::<Bar>(later).unwrap();
+}
+
+#[test]
+fn test_pins() {
+    for_each_backend(StoreParams::default(), check_pins);
+}
+
+#[test]
+fn test_deferred_deletes() {
+    let params = StoreParams {
+        pinned_deletes: PinnedDeletes::Defer,
+        ..Default::default()
+    };
+    let mut store = Store::from_backend(Box::new(Memory::new()), &params).unwrap();
+    let pinned = store.write(&Foo { inner: 1 }).unwrap();
+    let unpinned = store.write(&Foo { inner: 2 }).unwrap();
+    store.pin::<Foo>(pinned).unwrap();
+    store.pin::<Foo>(pinned).unwrap();
+
+    store.delete::<Foo>(pinned).unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(pinned).unwrap(), Some(Foo { inner: 1 }));
+    assert!(store.pins().unwrap()[0].delete_pending);
+
+    // Only the unpinned values of a type are deleted.
+    store.delete_all::<Foo>().unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(unpinned).unwrap(), None);
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap(), [(pinned, Foo { inner: 1 })]);
+
+    assert_eq!(store.unpin::<Foo>(pinned).unwrap(), 1);
+    assert_eq!(store.get_from_hash::<Foo>(pinned).unwrap(), Some(Foo { inner: 1 }));
+    assert_eq!(store.unpin::<Foo>(pinned).unwrap(), 0);
+    assert_eq!(store.get_from_hash::<Foo>(pinned).unwrap(), None);
+    assert_eq!(store.pins().unwrap(), []);
+}
+
+#[test]
+fn test_pins_persist() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let params = StoreParams {
+        pinned_deletes: PinnedDeletes::Defer,
+        ..Default::default()
+    };
+    let mut store = Store::sqlite_from_path(path.clone(), params.clone()).unwrap();
+    let pinned = store.write(&Foo { inner: 1 }).unwrap();
+    store.pin::<Foo>(pinned).unwrap();
+    store.delete::<Foo>(pinned).unwrap();
+    drop(store);
+
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    assert_eq!(store.pin_count::<Foo>(pinned).unwrap(), 1);
+    assert!(matches!(store.delete::<Foo>(pinned), Err(Error::Pinned { .. })));
+    drop(store);
+
+    // The deferred delete recorded before the reopen happens when the pin is released.
+    let mut store = Store::sqlite_from_path(path, params).unwrap();
+    assert_eq!(store.unpin::<Foo>(pinned).unwrap(), 0);
+    assert_eq!(store.get_from_hash::<Foo>(pinned).unwrap(), None);
+}
+
+/// Stores sharing a database pin and unpin the same value at once without losing counts.
+#[test]
+fn test_concurrent_pins() {
+    const PINS: u64 = 50;
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let foo_hash = Store::sqlite_from_path(path.clone(), StoreParams::default())
+        .unwrap()
+        .write(&Foo { inner: 1 })
+        .unwrap();
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let path = path.clone();
+            thread::spawn(move || {
+                let mut store = Store::sqlite_from_path(path, StoreParams::default()).unwrap();
+                for _ in 0..PINS {
+                    store.pin::<Foo>(foo_hash).unwrap();
+                }
+                for _ in 0..PINS / 2 {
+                    store.unpin::<Foo>(foo_hash).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let mut store = Store::sqlite_from_path(path, StoreParams::default()).unwrap();
+    assert_eq!(store.pin_count::<Foo>(foo_hash).unwrap(), 4 * PINS / 2);
+}
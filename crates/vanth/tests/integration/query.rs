@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use vanth::{
+    Ty, Vanth,
+    query::{Order, Predicate, Query, field},
+    store::{Encoding, Error, Store, StoreParams},
+};
+
+use crate::for_each_backend;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, Vanth)]
+struct Item {
+    name: String,
+    score: Option<i64>,
+    level: f64,
+    tag: Value,
+}
+
+fn items() -> Vec<Item> {
+    let item = |name: &str, score, level, tag| Item {
+        name: name.into(),
+        score,
+        level,
+        tag,
+    };
+    vec![
+        item("a", Some(10), 1.5, json!("x")),
+        item("b", Some(20), 2.0, json!(5)),
+        item("c", None, 2.0, json!(true)),
+        item("d", Some(15), 0.5, Value::Null),
+        item("e", Some(30), 3.0, json!("5")),
+    ]
+}
+
+/// Queries and the names of the items they return. Results of queries without an order are sorted by name.
+fn cases() -> Result<Vec<(Query, Vec<&'static str>)>, Error> {
+    let filter = |predicate: Predicate| Query::new().filter(predicate);
+    Ok(vec![
+        (
+            filter(field("score")?.gt(12)).order_by(field("score")?, Order::Ascending),
+            vec!["d", "b", "e"],
+        ),
+        // Comparisons with null are unknown, so neither they nor their negation match.
+        (filter(field("score")?.ne(10)), vec!["b", "d", "e"]),
+        (filter(!field("score")?.eq(10)), vec!["b", "d", "e"]),
+        (filter(field("score")?.is_null()), vec!["c"]),
+        (filter(field("score")?.is_not_null()), vec!["a", "b", "d", "e"]),
+        (filter(field("score")?.between(10, 20)), vec!["a", "b", "d"]),
+        (
+            filter(field("score")?.gt(25).or(field("score")?.is_null())),
+            vec!["c", "e"],
+        ),
+        (
+            filter(field("score")?.ge(15).and(field("level")?.lt(2.5))),
+            vec!["b", "d"],
+        ),
+        // Integers equal reals, and booleans are integers.
+        (filter(field("level")?.eq(2)), vec!["b", "c"]),
+        (filter(field("tag")?.eq(5)), vec!["b"]),
+        (filter(field("tag")?.eq(true)), vec!["c"]),
+        (filter(field("tag")?.eq("5")), vec!["e"]),
+        // Text is greater than any number.
+        (filter(field("tag")?.gt(100)), vec!["a", "e"]),
+        (filter(field("name")?.ge("c")), vec!["c", "d", "e"]),
+        (filter(Predicate::Any(Vec::new())), vec![]),
+        (filter(Predicate::All(Vec::new())), vec!["a", "b", "c", "d", "e"]),
+        // Nulls come first in ascending order and last in descending order.
+        (
+            Query::new().order_by(field("score")?, Order::Ascending),
+            vec!["c", "a", "d", "b", "e"],
+        ),
+        (
+            Query::new().order_by(field("score")?, Order::Descending),
+            vec!["e", "b", "d", "a", "c"],
+        ),
+        (
+            Query::new()
+                .order_by(field("level")?, Order::Descending)
+                .order_by(field("name")?, Order::Ascending)
+                .offset(1)
+                .limit(3),
+            vec!["b", "c", "a"],
+        ),
+        (
+            filter(field("score")?.is_not_null())
+                .order_by(field("score")?, Order::Ascending)
+                .limit(2),
+            vec!["a", "d"],
+        ),
+    ])
+}
+
+fn check_queries(store: &mut Store) {
+    for item in items() {
+        store.write(&item).unwrap();
+    }
+    for (query, expected) in cases().unwrap() {
+        let mut names: Vec<String> = store
+            .query::<Item>(&query)
+            .unwrap()
+            .into_iter()
+            .map(|(_, item)| item.name)
+            .collect();
+        if query.order_by.is_empty() {
+            names.sort();
+        }
+        assert_eq!(names, expected, "{:?}", query);
+    }
+}
+
+#[test]
+fn test_queries() {
+    for encoding in [Encoding::Json, Encoding::Cbor] {
+        let params = StoreParams {
+            encoding: Some(encoding),
+            ..Default::default()
+        };
+        for_each_backend(params, check_queries);
+    }
+}
+
+/// Values stored under an alias are matched and ordered together with the rest.
+#[test]
+fn test_aliased_queries() {
+    let mut store = Store::in_memory().unwrap();
+    let old_ty = Ty::from("old::Item");
+    for item in items().into_iter().take(2) {
+        let content_hash = vanth::hash(&item);
+        store
+            .write_raw(old_ty.clone(), content_hash, serde_json::to_vec(&item).unwrap())
+            .unwrap();
+    }
+    for item in items().into_iter().skip(2) {
+        store.write(&item).unwrap();
+    }
+    store.alias::<Item>(old_ty);
+
+    let query = Query::new().order_by(field("score").unwrap(), Order::Descending);
+    let names: Vec<String> = store
+        .query::<Item>(&query)
+        .unwrap()
+        .into_iter()
+        .map(|(_, item)| item.name)
+        .collect();
+    assert_eq!(names, ["e", "b", "d", "a", "c"]);
+}
@@ -0,0 +1,113 @@
+use std::{sync::mpsc, thread};
+
+use tempfile::TempDir;
+use vanth::{
+    Ty, Vanth, hash,
+    shared_store::SharedStore,
+    store::{Error, StoreParams},
+};
+
+use crate::store::{Bar, Foo};
+
+fn assert_shareable<T: Send + Sync + Clone + 'static>() {}
+
+#[test]
+fn test_shared_store_is_shareable() {
+    assert_shareable::<SharedStore>();
+}
+
+fn check_concurrent(store: &SharedStore) {
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for j in 0..25 {
+                    let value = Foo { inner: i * 100 + j };
+                    let content_hash = store.write(&value).unwrap();
+                    // A write is visible to every reader once it returns.
+                    assert_eq!(store.get_from_hash::<Foo>(content_hash).unwrap(), Some(value));
+                    store.get_all_of_type::<Foo>().unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 100);
+    assert_eq!(store.type_info::<Foo>().unwrap().unwrap().count, 100);
+}
+
+#[test]
+fn test_sqlite_shared_store() {
+    for max_readers in [0, 1, 4] {
+        let dir = TempDir::new().unwrap();
+        let params = StoreParams {
+            max_readers,
+            ..Default::default()
+        };
+        let store = SharedStore::sqlite_from_path(dir.path().join("test.db"), params).unwrap();
+        check_concurrent(&store);
+    }
+}
+
+#[test]
+fn test_memory_shared_store() {
+    check_concurrent(&SharedStore::in_memory().unwrap());
+}
+
+/// Reads from the pool go ahead while a transaction is open, and don't see its writes until it commits.
+#[test]
+fn test_sqlite_shared_store_reads_during_transaction() {
+    let dir = TempDir::new().unwrap();
+    let store = SharedStore::sqlite_from_path(dir.path().join("test.db"), StoreParams::default()).unwrap();
+    store.write(&Foo { inner: 1 }).unwrap();
+
+    let (written_sender, written) = mpsc::channel();
+    let (commit_sender, commit) = mpsc::channel::<()>();
+    let writer = {
+        let store = store.clone();
+        thread::spawn(move || {
+            store
+                .transaction(|transaction| {
+                    transaction.write(&Foo { inner: 2 })?;
+                    written_sender.send(()).unwrap();
+                    commit.recv().unwrap();
+                    Ok::<_, Error>(())
+                })
+                .unwrap();
+        })
+    };
+
+    written.recv().unwrap();
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 1);
+    commit_sender.send(()).unwrap();
+    writer.join().unwrap();
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 2);
+}
+
+#[test]
+fn test_sqlite_shared_store_readers() {
+    let dir = TempDir::new().unwrap();
+    let store = SharedStore::sqlite_from_path(dir.path().join("test.db"), StoreParams::default()).unwrap();
+    let bar = Bar { inner: "old".into() };
+    let old_ty = Ty::from("old::Bar");
+    store
+        .write_raw(old_ty.clone(), hash(&bar), serde_json::to_vec(&bar).unwrap())
+        .unwrap();
+
+    // Open a reader before the alias is registered.
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap().len(), 0);
+    store.alias::<Bar>(old_ty);
+    assert_eq!(store.get_from_hash::<Bar>(hash(&bar)).unwrap(), Some(bar));
+    assert_eq!(store.list_types().unwrap().len(), 1);
+
+    let result = store.with_reader(|reader| reader.write(&Foo { inner: 1 })).unwrap();
+    assert!(result.is_err());
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 0);
+    assert_eq!(store.find_by_hash(hash(&Foo { inner: 1 })).unwrap(), Vec::<Ty>::new());
+    assert_eq!(
+        store.with_writer(|writer| writer.get_all_of_type_raw(Bar::ty()).unwrap().len()),
+        0
+    );
+}
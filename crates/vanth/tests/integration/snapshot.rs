@@ -0,0 +1,153 @@
+use std::{fs, path::Path, time::Duration};
+
+use tempfile::TempDir;
+use vanth::{
+    ContentHash, Ty, Vanth, hash,
+    snapshot::Autosave,
+    store::{Backend, Encoding, Error, Memory, Store, StoreParams},
+};
+
+use crate::store::{Bar, Foo, check_iteration, check_transactions};
+
+/// Whether the snapshot at `path` has the value of `ty` with `content_hash`.
+fn saved(path: &Path, ty: Ty, content_hash: ContentHash) -> bool {
+    Memory::load(path)
+        .unwrap()
+        .get_from_hash(ty, content_hash)
+        .unwrap()
+        .is_some()
+}
+
+#[test]
+fn test_snapshot_transactions() {
+    let dir = TempDir::new().unwrap();
+    let mut store = Store::memory_from_path(dir.path().join("store.snapshot"), StoreParams::default()).unwrap();
+    check_transactions(&mut store);
+}
+
+#[test]
+fn test_snapshot_iteration() {
+    let dir = TempDir::new().unwrap();
+    let mut store = Store::memory_from_path(dir.path().join("store.snapshot"), StoreParams::default()).unwrap();
+    check_iteration(&mut store);
+}
+
+#[test]
+fn test_snapshot_flush() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("store.snapshot");
+    let params = StoreParams {
+        encoding: Some(Encoding::Cbor),
+        ..Default::default()
+    };
+    let mut store = Store::memory_from_path(path.clone(), params).unwrap();
+    let foo_hash = store.write(&Foo { inner: 1 }).unwrap();
+    store.write(&Bar { inner: "bar".into() }).unwrap();
+    store.flush().unwrap();
+    // Without autosave, changes after the last flush are lost.
+    store.write(&Foo { inner: 2 }).unwrap();
+    drop(store);
+
+    let mut store = Store::memory_from_path(path.clone(), StoreParams::default()).unwrap();
+    assert_eq!(store.encoding(), Encoding::Cbor);
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap(), [(foo_hash, Foo { inner: 1 })]);
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap().len(), 1);
+
+    // A snapshot can also be loaded into any store through its backend.
+    let mut store = Store::from_backend(Box::new(Memory::load(&path).unwrap()), &StoreParams::default()).unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(foo_hash).unwrap(), Some(Foo { inner: 1 }));
+}
+
+#[test]
+fn test_snapshot_autosave_on_drop() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("store.snapshot");
+    let params = StoreParams {
+        autosave: Autosave::OnDrop,
+        ..Default::default()
+    };
+    let mut store = Store::memory_from_path(path.clone(), params.clone()).unwrap();
+    store.write(&Foo { inner: 1 }).unwrap();
+    assert!(!path.exists());
+    // Uncommitted changes are not saved.
+    let mut tx = store.begin().unwrap();
+    tx.write(&Foo { inner: 2 }).unwrap();
+    drop(tx);
+    drop(store);
+
+    let mut store = Store::memory_from_path(path.clone(), params).unwrap();
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 1);
+    assert_eq!(store.get_from_hash::<Foo>(hash(&Foo { inner: 2 })).unwrap(), None);
+}
+
+#[test]
+fn test_snapshot_autosave_every() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("store.snapshot");
+    let params = StoreParams {
+        autosave: Autosave::Every(Duration::ZERO),
+        ..Default::default()
+    };
+    let mut store = Store::memory_from_path(path.clone(), params).unwrap();
+    let foo_hash = store.write(&Foo { inner: 1 }).unwrap();
+    assert!(saved(&path, Foo::ty(), foo_hash));
+
+    // Changes in a transaction are saved once it is committed.
+    let mut tx = store.begin().unwrap();
+    let bar_hash = tx.write(&Bar { inner: "bar".into() }).unwrap();
+    assert!(!saved(&path, Bar::ty(), bar_hash));
+    tx.commit().unwrap();
+    assert!(saved(&path, Bar::ty(), bar_hash));
+
+    // Not yet due, so only saved when the store is dropped.
+    let params = StoreParams {
+        autosave: Autosave::Every(Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    let mut store = Store::memory_from_path(path.clone(), params).unwrap();
+    let later = store.write(&Foo { inner: 2 }).unwrap();
+    assert!(!saved(&path, Foo::ty(), later));
+    drop(store);
+    assert!(saved(&path, Foo::ty(), later));
+}
+
+#[test]
+fn test_snapshot_errors() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("store.snapshot");
+    let missing = StoreParams {
+        create_if_not_exists: false,
+        ..Default::default()
+    };
+    assert!(matches!(
+        Store::memory_from_path(path.clone(), missing),
+        Err(Error::Io(_))
+    ));
+
+    let mut store = Store::memory_from_path(path.clone(), StoreParams::default()).unwrap();
+    store.write(&Foo { inner: 1 }).unwrap();
+    store.flush().unwrap();
+    drop(store);
+
+    let read_only = StoreParams {
+        read_only: true,
+        autosave: Autosave::OnDrop,
+        ..Default::default()
+    };
+    let mut store = Store::memory_from_path(path.clone(), read_only).unwrap();
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 1);
+    assert!(matches!(store.write(&Foo { inner: 2 }), Err(Error::ReadOnly)));
+    drop(store);
+
+    let data = fs::read(&path).unwrap();
+    fs::write(&path, &data[..data.len() - 1]).unwrap();
+    assert!(matches!(
+        Store::memory_from_path(path.clone(), StoreParams::default()),
+        Err(Error::InvalidSnapshot(_))
+    ));
+    fs::write(&path, b"not a snapshot").unwrap();
+    assert!(matches!(
+        Store::memory_from_path(path, StoreParams::default()),
+        Err(Error::InvalidSnapshot(_))
+    ));
+}
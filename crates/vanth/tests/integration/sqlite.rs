@@ -0,0 +1,196 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tempfile::TempDir;
+use vanth::{
+    hash,
+    store::{JournalMode, Store, StoreParams, Synchronous},
+};
+
+use crate::store::Foo;
+
+/// The database the child processes spawned by [`test_sqlite_processes`] open.
+const DB_PATH_VAR: &str = "VANTH_TEST_DB";
+/// Which of the writer processes this is, so each writes different values.
+const WRITER_VAR: &str = "VANTH_TEST_WRITER";
+
+const INITIAL: i32 = 20;
+const WRITERS: i32 = 2;
+const WRITES: i32 = 100;
+const TOTAL: usize = (INITIAL + WRITERS * WRITES) as usize;
+
+fn journal_mode(path: &Path) -> String {
+    let connection = rusqlite::Connection::open(path).unwrap();
+    connection
+        .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+        .unwrap()
+}
+
+#[test]
+fn test_sqlite_settings() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let params = StoreParams {
+        synchronous: Synchronous::Normal,
+        sqlite_cache_size_bytes: 8 << 20,
+        ..Default::default()
+    };
+    let mut store = Store::sqlite_from_path(path.clone(), params).unwrap();
+    let foo_hash = store.write(&Foo { inner: 1 }).unwrap();
+    drop(store);
+    assert_eq!(journal_mode(&path), "wal");
+
+    // Read-only stores open a WAL database without changing its mode.
+    let read_only = StoreParams {
+        read_only: true,
+        journal_mode: JournalMode::Delete,
+        ..Default::default()
+    };
+    let mut store = Store::sqlite_from_path(path.clone(), read_only).unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(foo_hash).unwrap(), Some(Foo { inner: 1 }));
+    drop(store);
+    assert_eq!(journal_mode(&path), "wal");
+
+    let params = StoreParams {
+        journal_mode: JournalMode::Delete,
+        ..Default::default()
+    };
+    let mut store = Store::sqlite_from_path(path.clone(), params).unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(foo_hash).unwrap(), Some(Foo { inner: 1 }));
+    drop(store);
+    assert_eq!(journal_mode(&path), "delete");
+}
+
+#[test]
+fn test_sqlite_busy_timeout() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let params = |busy_timeout| StoreParams {
+        busy_timeout,
+        ..Default::default()
+    };
+    let mut store = Store::sqlite_from_path(path.clone(), params(Duration::from_millis(50))).unwrap();
+    store.write(&Foo { inner: 1 }).unwrap();
+
+    let lock = rusqlite::Connection::open(&path).unwrap();
+    lock.execute_batch("BEGIN IMMEDIATE").unwrap();
+    let start = Instant::now();
+    assert!(store.write(&Foo { inner: 2 }).is_err());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    // Reads carry on while another connection is writing.
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 1);
+
+    // A write waits for the lock to be released.
+    let mut store = Store::sqlite_from_path(path.clone(), params(Duration::from_secs(10))).unwrap();
+    let release = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        lock.execute_batch("COMMIT").unwrap();
+    });
+    store.write(&Foo { inner: 2 }).unwrap();
+    store.transaction(|tx| tx.write(&Foo { inner: 3 })).unwrap();
+    release.join().unwrap();
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 3);
+}
+
+/// Run the ignored test `name` in a child process of this test binary, with the database at `path`.
+fn spawn(name: &str, path: &Path, envs: &[(&str, String)]) -> Child {
+    Command::new(env::current_exe().unwrap())
+        .args([name, "--exact", "--ignored", "--quiet"])
+        .env(DB_PATH_VAR, path)
+        .envs(envs.iter().map(|(key, value)| (*key, value)))
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+fn child_db_path() -> PathBuf {
+    env::var_os(DB_PATH_VAR).expect("run by test_sqlite_processes").into()
+}
+
+/// Read every value until all of the writes have been seen.
+fn read_until_done(store: &mut Store) {
+    let deadline = Instant::now() + Duration::from_secs(60);
+    let mut seen = 0;
+    while seen < TOTAL {
+        let count = store.get_all_of_type::<Foo>().unwrap().len();
+        assert!(count >= seen, "reader saw {count} values after seeing {seen}");
+        seen = count;
+        assert!(Instant::now() < deadline, "reader only saw {seen} of {TOTAL} values");
+    }
+}
+
+/// Processes sharing a database, such as the CLI and a service, read and write it at once without failing.
+#[test]
+fn test_sqlite_processes() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    for inner in 0..INITIAL {
+        store.write(&Foo { inner }).unwrap();
+    }
+
+    let mut children: Vec<Child> = (0..WRITERS)
+        .map(|writer| {
+            spawn(
+                "sqlite::sqlite_writer_process",
+                &path,
+                &[(WRITER_VAR, writer.to_string())],
+            )
+        })
+        .collect();
+    children.extend((0..3).map(|_| spawn("sqlite::sqlite_reader_process", &path, &[])));
+    // Keep reading here too, until every child has finished.
+    let deadline = Instant::now() + Duration::from_secs(60);
+    while !children.is_empty() {
+        store.get_all_of_type::<Foo>().unwrap();
+        children.retain_mut(|child| match child.try_wait().unwrap() {
+            Some(status) => {
+                assert!(status.success(), "child process failed: {status}");
+                false
+            }
+            None => true,
+        });
+        assert!(Instant::now() < deadline, "child processes did not finish");
+    }
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), TOTAL);
+    // The catalog was kept in step with the writes from every process.
+    assert_eq!(store.type_info::<Foo>().unwrap().unwrap().count, TOTAL as u64);
+}
+
+#[test]
+#[ignore = "run in a child process by test_sqlite_processes"]
+fn sqlite_writer_process() {
+    let writer: i32 = env::var(WRITER_VAR).unwrap().parse().unwrap();
+    let mut store = Store::sqlite_from_path(child_db_path(), StoreParams::default()).unwrap();
+    let start = INITIAL + writer * WRITES;
+    for inner in start..start + WRITES {
+        if inner % 2 == 0 {
+            store.write(&Foo { inner }).unwrap();
+        } else {
+            // A transaction that reads before writing.
+            store
+                .transaction(|tx| {
+                    assert!(tx.get_from_hash::<Foo>(hash(&Foo { inner: inner - 1 }))?.is_some());
+                    tx.write(&Foo { inner })
+                })
+                .unwrap();
+        }
+    }
+}
+
+#[test]
+#[ignore = "run in a child process by test_sqlite_processes"]
+fn sqlite_reader_process() {
+    let params = StoreParams {
+        read_only: true,
+        create_if_not_exists: false,
+        ..Default::default()
+    };
+    let mut store = Store::sqlite_from_path(child_db_path(), params).unwrap();
+    read_until_done(&mut store);
+}
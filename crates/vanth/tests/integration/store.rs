@@ -1,16 +1,21 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use tempfile::TempDir;
-use vanth::{Vanth, hash, store::{Store, StoreParams}};
+use vanth::{
+    Vanth, hash,
+    store::{Encoding, Error, HashMismatch, IterParams, Memory, Store, StoreParams, Verifier},
+};
+
+use crate::for_each_backend;
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Vanth)]
-struct Foo {
-    inner: i32,
+pub(crate) struct Foo {
+    pub(crate) inner: i32,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Vanth)]
-struct Bar {
-    inner: String,
+pub(crate) struct Bar {
+    pub(crate) inner: String,
 }
 
 #[test]
@@ -45,3 +50,344 @@ fn test_sqlite_store() {
     assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 0);
     assert_eq!(store.get_all_of_type::<Bar>().unwrap().len(), 0);
 }
+
+pub(crate) fn check_transactions(store: &mut Store) {
+    let foo_1 = Foo { inner: 1 };
+    let foo_2 = Foo { inner: 2 };
+    let bar_1 = Bar { inner: "hello".into() };
+    let foo_1_hash = store.write(&foo_1).unwrap();
+
+    let result: Result<(), Error> = store.transaction(|tx| {
+        tx.write(&foo_2)?;
+        tx.write(&bar_1)?;
+        tx.delete::<Foo>(foo_1_hash)?;
+        assert_eq!(tx.get_all_of_type::<Foo>()?.len(), 1);
+        assert_eq!(tx.get_from_hash::<Foo>(foo_1_hash)?, None);
+        Err(Error::Serializiation("abort".into()))
+    });
+    assert!(result.is_err());
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 1);
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap().len(), 0);
+    assert_eq!(store.get_from_hash(foo_1_hash).unwrap(), Some(foo_1.clone()));
+
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _: Result<(), Error> = store.transaction(|tx| {
+            tx.delete_all::<Foo>()?;
+            panic!("abort");
+        });
+    }));
+    assert!(panicked.is_err());
+    assert_eq!(store.get_all_of_type::<Foo>().unwrap().len(), 1);
+
+    let tx = store.begin().unwrap();
+    drop(tx);
+    let mut tx = store.begin().unwrap();
+    tx.delete_all::<Foo>().unwrap();
+    tx.write(&foo_2).unwrap();
+    tx.write(&bar_1).unwrap();
+    tx.commit().unwrap();
+    assert_eq!(store.get_from_hash::<Foo>(foo_1_hash).unwrap(), None);
+    assert_eq!(store.get_from_hash(hash(&foo_2)).unwrap(), Some(foo_2));
+    assert_eq!(store.get_all_of_type::<Bar>().unwrap().len(), 1);
+}
+
+#[test]
+fn test_transactions() {
+    for_each_backend(StoreParams::default(), check_transactions);
+}
+
+pub(crate) fn check_iteration(store: &mut Store) {
+    let mut hashes: Vec<_> = (0..10).map(|i| store.write(&Foo { inner: i }).unwrap()).collect();
+    hashes.sort();
+    store.write(&Bar { inner: "hello".into() }).unwrap();
+
+    let page_params = |page_size| IterParams {
+        page_size,
+        ..Default::default()
+    };
+    let all: Vec<_> = store
+        .iter_of_type_raw(Foo::ty(), page_params(3))
+        .map(|item| item.unwrap().0)
+        .collect();
+    assert_eq!(all, hashes);
+
+    let limited: Vec<_> = store
+        .iter_of_type_raw(
+            Foo::ty(),
+            IterParams {
+                offset: 2,
+                limit: Some(5),
+                page_size: 2,
+                ..Default::default()
+            },
+        )
+        .map(|item| item.unwrap().0)
+        .collect();
+    assert_eq!(limited, hashes[2..7]);
+
+    let mut iter = store.iter_of_type_raw(Foo::ty(), page_params(4));
+    iter.by_ref().take(6).for_each(|item| drop(item.unwrap()));
+    let cursor = iter.cursor();
+    assert_eq!(cursor, Some(hashes[5]));
+    let resumed: Vec<_> = store
+        .iter_of_type::<Foo>(IterParams {
+            after: cursor,
+            ..Default::default()
+        })
+        .map(|item| item.unwrap())
+        .collect();
+    assert_eq!(resumed.len(), 4);
+
+    assert_eq!(store.iter_of_type_raw(Bar::ty(), page_params(1)).count(), 1);
+    store.delete_all::<Bar>().unwrap();
+    assert_eq!(store.iter_of_type_raw(Bar::ty(), page_params(1)).count(), 0);
+
+    // Iterating inside a transaction sees its uncommitted writes and deletes.
+    store
+        .transaction(|tx| {
+            tx.delete::<Foo>(hashes[0])?;
+            let new_hash = tx.write(&Foo { inner: 100 })?;
+            let seen: Vec<_> = tx
+                .iter_of_type_raw(Foo::ty(), IterParams::default())
+                .map(|item| item.unwrap().0)
+                .collect();
+            assert_eq!(seen.len(), 10);
+            assert!(!seen.contains(&hashes[0]));
+            assert!(seen.contains(&new_hash));
+            assert!(seen.is_sorted());
+            Ok::<_, Error>(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_iteration() {
+    for_each_backend(StoreParams::default(), check_iteration);
+}
+
+#[test]
+fn test_typed_decoding() {
+    let mut store = Store::in_memory().unwrap();
+    let foo_1 = Foo { inner: 1 };
+    let foo_1_hash = store.write(&foo_1).unwrap();
+
+    assert_eq!(
+        store.get_all_of_type::<Foo>().unwrap(),
+        vec![(foo_1_hash, foo_1.clone())]
+    );
+
+    let bad_hash = hash(&"not a foo");
+    store.write_raw(Foo::ty(), bad_hash, b"\"not a foo\"".to_vec()).unwrap();
+
+    match store.get_all_of_type::<Foo>() {
+        Err(Error::Deserialization { ty, content_hash, .. }) => {
+            assert_eq!(ty, Foo::ty());
+            assert_eq!(content_hash, bad_hash);
+        }
+        other => panic!("expected a deserialization error, got {:?}", other),
+    }
+    assert!(matches!(
+        store.get_from_hash::<Foo>(bad_hash),
+        Err(Error::Deserialization { .. })
+    ));
+
+    let contents = store.get_all_contents_of_type::<Foo>().unwrap();
+    assert_eq!(contents.len(), 2);
+    for item in contents {
+        if item.content_hash() == foo_1_hash {
+            assert_eq!(item.decode().unwrap(), foo_1);
+        } else {
+            assert_eq!(item.data(), b"\"not a foo\"");
+            assert!(item.decode().is_err());
+        }
+    }
+
+    let results: Vec<_> = store.iter_of_type::<Foo>(IterParams::default()).collect();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results.iter().filter(|item| item.is_err()).count(), 1);
+}
+
+#[test]
+fn test_encodings() {
+    let dir = TempDir::new().unwrap();
+    let foo_1 = Foo { inner: 1 };
+    let bar_1 = Bar { inner: "hello".into() };
+
+    for encoding in [Encoding::Json, Encoding::Bincode, Encoding::Cbor] {
+        let path = dir.path().join(format!("{}.db", encoding));
+        let params = StoreParams {
+            encoding: Some(encoding),
+            ..Default::default()
+        };
+        let mut store = Store::sqlite_from_path(path.clone(), params).unwrap();
+        let foo_1_hash = store.write(&foo_1).unwrap();
+        assert_eq!(foo_1_hash, hash(&foo_1));
+        store.write(&bar_1).unwrap();
+        assert_eq!(store.get_from_hash(foo_1_hash).unwrap(), Some(foo_1.clone()));
+        assert_eq!(store.get_all_of_type::<Bar>().unwrap()[0].1, bar_1);
+        let raw = store.get_from_hash_raw(Foo::ty(), foo_1_hash).unwrap().unwrap();
+        assert_eq!(raw, encoding.encode(&foo_1).unwrap());
+        drop(store);
+
+        // The encoding is picked up from the database when none is requested.
+        let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+        assert_eq!(store.encoding(), encoding);
+        assert_eq!(store.get_from_hash(foo_1_hash).unwrap(), Some(foo_1.clone()));
+        drop(store);
+
+        let other = if encoding == Encoding::Json {
+            Encoding::Bincode
+        } else {
+            Encoding::Json
+        };
+        let result = Store::sqlite_from_path(
+            path,
+            StoreParams {
+                encoding: Some(other),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(Error::EncodingMismatch { .. })));
+    }
+}
+
+/// Databases written before encodings were recorded hold JSON, whatever encoding is requested.
+#[test]
+fn test_legacy_encoding() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let foo_1 = Foo { inner: 1 };
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    connection
+        .execute_batch(&format!(
+            "CREATE TABLE \"ty_{}\" (content_hash BLOB PRIMARY KEY, content BLOB NOT NULL)",
+            Foo::ty()
+        ))
+        .unwrap();
+    connection
+        .execute(
+            &format!(
+                "INSERT INTO \"ty_{}\" (content_hash, content) VALUES (?1, ?2)",
+                Foo::ty()
+            ),
+            rusqlite::params![hash(&foo_1).hash.as_slice(), serde_json::to_vec(&foo_1).unwrap()],
+        )
+        .unwrap();
+    drop(connection);
+
+    let params = StoreParams {
+        encoding: Some(Encoding::Cbor),
+        ..Default::default()
+    };
+    let result = Store::sqlite_from_path(path.clone(), params.clone());
+    assert!(matches!(
+        result,
+        Err(Error::EncodingMismatch {
+            stored: Encoding::Json,
+            requested: Encoding::Cbor,
+        })
+    ));
+
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+    assert_eq!(store.encoding(), Encoding::Json);
+    assert_eq!(store.get_from_hash(hash(&foo_1)).unwrap(), Some(foo_1));
+    drop(store);
+    assert!(matches!(
+        Store::sqlite_from_path(path, params),
+        Err(Error::EncodingMismatch { .. })
+    ));
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Vanth)]
+struct Casing {
+    inner: i32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Vanth)]
+struct CaSing {
+    inner: i32,
+}
+
+#[test]
+fn test_verify() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("test.db");
+    let mut store = Store::sqlite_from_path(path.clone(), StoreParams::default()).unwrap();
+
+    let foo_1 = Foo { inner: 1 };
+    let foo_2 = Foo { inner: 2 };
+    store.write(&foo_1).unwrap();
+    store.write(&Bar { inner: "hello".into() }).unwrap();
+    let wrong_hash = hash(&"wrong");
+    store
+        .write_raw(Foo::ty(), wrong_hash, serde_json::to_vec(&foo_2).unwrap())
+        .unwrap();
+    let garbage_hash = hash(&"garbage");
+    store.write_raw(Foo::ty(), garbage_hash, b"garbage".to_vec()).unwrap();
+
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    connection.execute("CREATE TABLE other (x INTEGER)", []).unwrap();
+    drop(connection);
+
+    let report = store.verify(&Verifier::new().with::<Foo>()).unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(report.checked, 3);
+    assert_eq!(
+        report.mismatches,
+        vec![HashMismatch {
+            ty: Foo::ty(),
+            expected: wrong_hash,
+            actual: hash(&foo_2),
+        }]
+    );
+    assert_eq!(report.undecodable.len(), 1);
+    assert_eq!(report.undecodable[0].content_hash, garbage_hash);
+    assert_eq!(report.unchecked_tys, vec![Bar::ty()]);
+    assert_eq!(report.foreign_tables, vec!["other".to_string()]);
+    assert!(report.duplicate_tables.is_empty());
+
+    let report = store
+        .verify(&Verifier::new().with::<Casing>().with::<CaSing>())
+        .unwrap();
+    assert_eq!(report.duplicate_tables.len(), 1);
+
+    store.delete::<Foo>(wrong_hash).unwrap();
+    store.delete::<Foo>(garbage_hash).unwrap();
+    let mut memory = Store::in_memory().unwrap();
+    for store in [&mut store, &mut memory] {
+        store.write(&foo_1).unwrap();
+        let report = store.verify(&Verifier::new().with::<Foo>().with::<Bar>()).unwrap();
+        assert!(report.mismatches.is_empty() && report.undecodable.is_empty());
+        assert!(report.checked >= 1);
+    }
+    assert!(
+        memory
+            .verify(&Verifier::new().with::<Casing>().with::<CaSing>())
+            .unwrap()
+            .is_ok()
+    );
+}
+
+#[test]
+fn test_verify_on_read() {
+    let mut store = Store::from_backend(
+        Box::new(Memory::new()),
+        &StoreParams {
+            verify_on_read: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let foo_1 = Foo { inner: 1 };
+    let foo_1_hash = store.write(&foo_1).unwrap();
+    assert_eq!(store.get_from_hash(foo_1_hash).unwrap(), Some(foo_1));
+
+    let wrong_hash = hash(&"wrong");
+    store
+        .write_raw(Foo::ty(), wrong_hash, serde_json::to_vec(&Foo { inner: 2 }).unwrap())
+        .unwrap();
+    assert!(matches!(
+        store.get_from_hash::<Foo>(wrong_hash),
+        Err(Error::HashMismatch { .. })
+    ));
+}